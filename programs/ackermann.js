@@ -1,6 +1,8 @@
 function ack(m, n) {
-  if (m == 0) return n + 1;
-  if (n == 0) return ack(m - 1, 1);
+  if (m == 0)
+    return n + 1;
+  if (n == 0)
+    return ack(m - 1, 1);
   return ack(m - 1, ack(m, n - 1));
 }
-ack(3, 4);  // must return 125
\ No newline at end of file
+ack(3, 4); // must return 125
@@ -6,4 +6,4 @@ function fact(n) {
   }
   return result;
 }
-fact(5);  // must return 120
\ No newline at end of file
+fact(5); // must return 120
@@ -6,4 +6,4 @@ function gcd(a, b) {
   }
   return a;
 }
-gcd(48, 18);  // must return 6
\ No newline at end of file
+gcd(48, 18); // must return 6
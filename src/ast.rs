@@ -1,3 +1,5 @@
+use crate::token::{Span, Trivia, TriviaKind};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinaryOp {
     Add,
@@ -5,40 +7,158 @@ pub enum BinaryOp {
     Mul,
     Div,
     Mod,
+    // `**`, right-associative. Products wrap like `*`.
+    Pow,
     Eq,
     Ne,
+    // `===` and `!==`. The same as `==` and `!=` unless values carry their type (`--dynamic`).
+    StrictEq,
+    StrictNe,
     Lt,
     Gt,
     Le,
     Ge,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    // `>>>`. Without `--dynamic` the result is reinterpreted as signed, so `-1 >>> 0` is -1 rather
+    // than 4294967295.
+    UShr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpdateOp {
+    Increment,
+    Decrement,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum UnaryOp {
     Not,
     Neg,
+    BitNot,
+    Typeof,
+}
+
+// A name as written in the source: declared variables, params, call targets, assignment targets
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expression {
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpressionKind {
     Identifier(String),
-    Number(i32),
+    Number(f64),
+    Boolean(bool),
+    Null,
+    Undefined,
+    String(String),
     Binary(Box<Expression>, BinaryOp, Box<Expression>),
     Unary(UnaryOp, Box<Expression>),
-    Call(String, Vec<Expression>),
-    Assignment(String, Box<Expression>),
+    Call(Ident, Vec<Expression>),
+    Assignment(Ident, Box<Expression>),
+    // `x op= value`
+    CompoundAssignment(Ident, BinaryOp, Box<Expression>),
+    // `condition ? then : otherwise`
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+    // `a, b, c`: evaluates all of them, in order, to the value of the last
+    Sequence(Vec<Expression>),
+    // `++x` or `x--`. Prefix forms evaluate to the new value, postfix forms to the old one.
+    Update {
+        target: Ident,
+        op: UpdateOp,
+        prefix: bool,
+    },
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Expression { kind, span }
+    }
+
+    // Equality that ignores where the expressions were written
+    pub fn same_as(&self, other: &Expression) -> bool {
+        use ExpressionKind::*;
+        match (&self.kind, &other.kind) {
+            (Identifier(a), Identifier(b)) => a == b,
+            (Number(a), Number(b)) => a == b,
+            (Boolean(a), Boolean(b)) => a == b,
+            (Null, Null) | (Undefined, Undefined) => true,
+            (String(a), String(b)) => a == b,
+            (Binary(l1, op1, r1), Binary(l2, op2, r2)) => op1 == op2 && l1.same_as(l2) && r1.same_as(r2),
+            (Unary(op1, e1), Unary(op2, e2)) => op1 == op2 && e1.same_as(e2),
+            (Call(f1, args1), Call(f2, args2)) => {
+                f1.name == f2.name
+                    && args1.len() == args2.len()
+                    && args1.iter().zip(args2).all(|(a, b)| a.same_as(b))
+            }
+            (Assignment(t1, v1), Assignment(t2, v2)) => t1.name == t2.name && v1.same_as(v2),
+            (CompoundAssignment(t1, op1, v1), CompoundAssignment(t2, op2, v2)) => {
+                t1.name == t2.name && op1 == op2 && v1.same_as(v2)
+            }
+            (Conditional(c1, t1, e1), Conditional(c2, t2, e2)) => c1.same_as(c2) && t1.same_as(t2) && e1.same_as(e2),
+            (Sequence(a), Sequence(b)) => a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.same_as(y)),
+            (Update { target: t1, op: op1, prefix: p1 }, Update { target: t2, op: op2, prefix: p2 }) => {
+                t1.name == t2.name && op1 == op2 && p1 == p2
+            }
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+    // Comments and whitespace before the statement, for `doc`. Only filled in when parsing
+    // from `Lexer::with_trivia`; the formatter places comments by position instead.
+    pub leading: Vec<Trivia>,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Statement { kind, span, leading: Vec::new() }
+    }
+
+    // The `/** ... */` comment directly before the statement, without its delimiters and the
+    // `*` that starts each of its lines
+    pub fn doc(&self) -> Option<String> {
+        let comment = self.leading.iter().rev().find(|t| t.is_comment())?;
+        if comment.kind != TriviaKind::DocComment {
+            return None;
+        }
+        let inner = &comment.text[3..comment.text.len() - 2];
+        let lines: Vec<&str> = inner
+            .lines()
+            .map(|line| {
+                let line = line.trim();
+                line.strip_prefix("* ").or_else(|| line.strip_prefix('*')).unwrap_or(line)
+            })
+            .collect();
+        Some(lines.join("\n").trim().to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
     VariableDeclaration {
-        name: String,
+        name: Ident,
         init: Expression,
         is_const: bool,
     },
     FunctionDeclaration {
-        name: String,
-        params: Vec<String>,
+        name: Ident,
+        params: Vec<Ident>,
         body: Vec<Statement>,
     },
     If {
@@ -50,11 +170,36 @@ pub enum Statement {
         condition: Expression,
         body: Box<Statement>,
     },
+    DoWhile {
+        body: Box<Statement>,
+        condition: Expression,
+    },
+    Switch {
+        discriminant: Expression,
+        cases: Vec<SwitchCase>,
+    },
+    // `label: statement`
+    Labeled {
+        label: Ident,
+        body: Box<Statement>,
+    },
+    Break(Option<Ident>),
+    Continue(Option<Ident>),
     Return(Option<Expression>),
     Block(Vec<Statement>),
     Expression(Expression),
 }
 
+// A `case test:` or `default:` clause and the statements up to the next one. Cases fall
+// through into the next unless they end in `break`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwitchCase {
+    // None for `default`
+    pub test: Option<Expression>,
+    pub body: Vec<Statement>,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub body: Vec<Statement>,
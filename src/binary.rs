@@ -0,0 +1,379 @@
+use crate::wasm::{Function, Instruction, Module, ValType};
+
+// Encodes a `Module` in the WebAssembly binary format, so `--emit wasm` doesn't
+// need wat2wasm. Names are resolved to indices here: functions by their position
+// in the module, locals by their position after the params, labels by depth.
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: &[u8] = &[1, 0, 0, 0];
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_MEMORY: u8 = 5;
+const SECTION_GLOBAL: u8 = 6;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+const SECTION_DATA: u8 = 11;
+
+pub fn encode(module: &Module) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(VERSION);
+
+    // Type section: one entry per distinct signature
+    let mut types: Vec<(Vec<ValType>, ValType)> = Vec::new();
+    let mut type_indices = Vec::new();
+    for func in &module.functions {
+        let signature = (func.params.iter().map(|p| p.ty).collect::<Vec<_>>(), func.result);
+        let index = match types.iter().position(|t| *t == signature) {
+            Some(index) => index,
+            None => {
+                types.push(signature);
+                types.len() - 1
+            }
+        };
+        type_indices.push(index);
+    }
+
+    let mut section = Vec::new();
+    write_u32(&mut section, types.len() as u32);
+    for (params, result) in &types {
+        section.push(0x60);
+        write_u32(&mut section, params.len() as u32);
+        for param in params {
+            section.push(val_type(*param));
+        }
+        write_u32(&mut section, 1);
+        section.push(val_type(*result));
+    }
+    write_section(&mut out, SECTION_TYPE, &section);
+
+    // Function section
+    let mut section = Vec::new();
+    write_u32(&mut section, type_indices.len() as u32);
+    for index in type_indices {
+        write_u32(&mut section, index as u32);
+    }
+    write_section(&mut out, SECTION_FUNCTION, &section);
+
+    // Memory section
+    let has_memory = module.memory;
+    if has_memory {
+        let mut section = Vec::new();
+        write_u32(&mut section, 1);
+        section.push(0x00); // no maximum
+        write_u32(&mut section, module.memory_pages());
+        write_section(&mut out, SECTION_MEMORY, &section);
+    }
+
+    // Global section
+    if !module.globals.is_empty() {
+        let mut section = Vec::new();
+        write_u32(&mut section, module.globals.len() as u32);
+        for (_, value) in &module.globals {
+            section.extend([val_type(ValType::I32), 0x01]); // mutable
+            section.push(0x41);
+            write_i32(&mut section, *value);
+            section.push(0x0B);
+        }
+        write_section(&mut out, SECTION_GLOBAL, &section);
+    }
+
+    // Export section
+    let mut section = Vec::new();
+    write_u32(&mut section, module.exports.len() as u32 + has_memory as u32);
+    if has_memory {
+        write_name(&mut section, "memory");
+        section.push(0x02); // memory
+        write_u32(&mut section, 0);
+    }
+    for (export, func) in &module.exports {
+        write_name(&mut section, export);
+        section.push(0x00); // func
+        write_u32(&mut section, function_index(module, func));
+    }
+    write_section(&mut out, SECTION_EXPORT, &section);
+
+    // Code section
+    let mut section = Vec::new();
+    write_u32(&mut section, module.functions.len() as u32);
+    for func in &module.functions {
+        let body = encode_function(module, func);
+        write_u32(&mut section, body.len() as u32);
+        section.extend(body);
+    }
+    write_section(&mut out, SECTION_CODE, &section);
+
+    // Data section: one active segment at address 0
+    if !module.data.is_empty() {
+        let mut section = Vec::new();
+        write_u32(&mut section, 1);
+        section.push(0x00); // active, memory 0
+        section.extend([0x41, 0x00, 0x0B]); // i32.const 0, end
+        write_u32(&mut section, module.data.len() as u32);
+        section.extend_from_slice(&module.data);
+        write_section(&mut out, SECTION_DATA, &section);
+    }
+
+    out
+}
+
+fn encode_function(module: &Module, func: &Function) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    // Locals are declared as runs of the same type
+    let mut runs: Vec<(u32, ValType)> = Vec::new();
+    for local in &func.locals {
+        match runs.last_mut() {
+            Some((count, ty)) if *ty == local.ty => *count += 1,
+            _ => runs.push((1, local.ty)),
+        }
+    }
+    write_u32(&mut out, runs.len() as u32);
+    for (count, ty) in runs {
+        write_u32(&mut out, count);
+        out.push(val_type(ty));
+    }
+
+    let mut encoder = BodyEncoder { module, func, labels: Vec::new(), out };
+    encoder.encode(&func.body);
+    encoder.out.push(0x0B); // end
+    encoder.out
+}
+
+struct BodyEncoder<'a> {
+    module: &'a Module,
+    func: &'a Function,
+    // Enclosing labels, innermost last. `if` has no label of its own.
+    labels: Vec<Option<&'a str>>,
+    out: Vec<u8>,
+}
+
+impl<'a> BodyEncoder<'a> {
+    fn encode(&mut self, body: &'a [Instruction]) {
+        for instr in body {
+            match instr {
+                Instruction::I32Const(n) => {
+                    self.out.push(0x41);
+                    write_i32(&mut self.out, *n);
+                }
+                Instruction::I64Const(n) => {
+                    self.out.push(0x42);
+                    write_i64(&mut self.out, *n);
+                }
+                Instruction::F64Const(x) => {
+                    self.out.push(0x44);
+                    self.out.extend(x.to_le_bytes());
+                }
+                Instruction::LocalGet(name) => self.local(0x20, name),
+                Instruction::LocalSet(name) => self.local(0x21, name),
+                Instruction::LocalTee(name) => self.local(0x22, name),
+                Instruction::GlobalGet(name) => self.global(0x23, name),
+                Instruction::GlobalSet(name) => self.global(0x24, name),
+                Instruction::I32Add => self.out.push(0x6A),
+                Instruction::I32Sub => self.out.push(0x6B),
+                Instruction::I32Mul => self.out.push(0x6C),
+                Instruction::I32DivS => self.out.push(0x6D),
+                Instruction::I32RemS => self.out.push(0x6F),
+                Instruction::I32Eq => self.out.push(0x46),
+                Instruction::I32Ne => self.out.push(0x47),
+                Instruction::I32LtS => self.out.push(0x48),
+                Instruction::I32GtS => self.out.push(0x4A),
+                Instruction::I32LeS => self.out.push(0x4C),
+                Instruction::I32GeS => self.out.push(0x4E),
+                Instruction::I32LtU => self.out.push(0x49),
+                Instruction::I32GtU => self.out.push(0x4B),
+                Instruction::I32Eqz => self.out.push(0x45),
+                Instruction::I32And => self.out.push(0x71),
+                Instruction::I32Or => self.out.push(0x72),
+                Instruction::I32Xor => self.out.push(0x73),
+                Instruction::I32Shl => self.out.push(0x74),
+                Instruction::I32ShrS => self.out.push(0x75),
+                Instruction::I32ShrU => self.out.push(0x76),
+                Instruction::I32Clz => self.out.push(0x67),
+                Instruction::F64ConvertI32S => self.out.push(0xB7),
+                Instruction::F64Sqrt => self.out.push(0x9F),
+                Instruction::I32TruncSatF64S => self.out.extend([0xFC, 0x02]),
+                Instruction::I32Load(offset) => self.memory(0x28, 2, *offset),
+                Instruction::I32Load8U(offset) => self.memory(0x2D, 0, *offset),
+                Instruction::I64Load(offset) => self.memory(0x29, 3, *offset),
+                Instruction::I32Store(offset) => self.memory(0x36, 2, *offset),
+                Instruction::I32Store8(offset) => self.memory(0x3A, 0, *offset),
+                Instruction::MemorySize => self.out.extend([0x3F, 0x00]),
+                Instruction::MemoryGrow => self.out.extend([0x40, 0x00]),
+                Instruction::MemoryCopy => self.out.extend([0xFC, 10, 0x00, 0x00]),
+                Instruction::MemoryFill => self.out.extend([0xFC, 11, 0x00]),
+                Instruction::I64Eq => self.out.push(0x51),
+                Instruction::I64Ne => self.out.push(0x52),
+                Instruction::I64Eqz => self.out.push(0x50),
+                Instruction::I64And => self.out.push(0x83),
+                Instruction::I64Or => self.out.push(0x84),
+                Instruction::I64ShrU => self.out.push(0x88),
+                Instruction::I64Add => self.out.push(0x7C),
+                Instruction::I64Sub => self.out.push(0x7D),
+                Instruction::I64Mul => self.out.push(0x7E),
+                Instruction::I64DivU => self.out.push(0x80),
+                Instruction::I64RemU => self.out.push(0x82),
+                Instruction::I64ExtendI32U => self.out.push(0xAD),
+                Instruction::I32WrapI64 => self.out.push(0xA7),
+                Instruction::I64TruncSatF64S => self.out.extend([0xFC, 0x06]),
+                Instruction::I64TruncSatF64U => self.out.extend([0xFC, 0x07]),
+                Instruction::F64ConvertI64S => self.out.push(0xB9),
+                Instruction::F64ConvertI64U => self.out.push(0xBA),
+                Instruction::I64ReinterpretF64 => self.out.push(0xBD),
+                Instruction::F64ReinterpretI64 => self.out.push(0xBF),
+                Instruction::F64Add => self.out.push(0xA0),
+                Instruction::F64Sub => self.out.push(0xA1),
+                Instruction::F64Mul => self.out.push(0xA2),
+                Instruction::F64Div => self.out.push(0xA3),
+                Instruction::F64Min => self.out.push(0xA4),
+                Instruction::F64Max => self.out.push(0xA5),
+                Instruction::F64Copysign => self.out.push(0xA6),
+                Instruction::F64Eq => self.out.push(0x61),
+                Instruction::F64Ne => self.out.push(0x62),
+                Instruction::F64Lt => self.out.push(0x63),
+                Instruction::F64Gt => self.out.push(0x64),
+                Instruction::F64Le => self.out.push(0x65),
+                Instruction::F64Ge => self.out.push(0x66),
+                Instruction::F64Neg => self.out.push(0x9A),
+                Instruction::F64Abs => self.out.push(0x99),
+                Instruction::F64Floor => self.out.push(0x9C),
+                Instruction::F64Ceil => self.out.push(0x9B),
+                Instruction::F64Trunc => self.out.push(0x9D),
+                Instruction::F64Nearest => self.out.push(0x9E),
+                Instruction::F64ConvertI32U => self.out.push(0xB8),
+                Instruction::I32TruncSatF64U => self.out.extend([0xFC, 0x03]),
+                Instruction::Call(name) => {
+                    self.out.push(0x10);
+                    write_u32(&mut self.out, function_index(self.module, name));
+                }
+                Instruction::Drop => self.out.push(0x1A),
+                Instruction::Select => self.out.push(0x1B),
+                Instruction::Return => self.out.push(0x0F),
+                Instruction::Br(label) => self.branch(0x0C, label),
+                Instruction::BrIf(label) => self.branch(0x0D, label),
+                Instruction::BrTable { labels, default } => {
+                    self.out.push(0x0E);
+                    write_u32(&mut self.out, labels.len() as u32);
+                    for label in labels.iter().chain([default]) {
+                        let depth = self.depth(label);
+                        write_u32(&mut self.out, depth);
+                    }
+                }
+                Instruction::Block { label, body } | Instruction::Loop { label, body } => {
+                    self.out.push(if matches!(instr, Instruction::Block { .. }) { 0x02 } else { 0x03 });
+                    self.out.push(0x40); // no result
+                    self.labels.push(Some(label));
+                    self.encode(body);
+                    self.labels.pop();
+                    self.out.push(0x0B);
+                }
+                Instruction::If { result, then_body, else_body } => {
+                    self.out.push(0x04);
+                    self.out.push(result.map_or(0x40, val_type));
+                    self.labels.push(None);
+                    self.encode(then_body);
+                    if let Some(else_body) = else_body {
+                        self.out.push(0x05);
+                        self.encode(else_body);
+                    }
+                    self.labels.pop();
+                    self.out.push(0x0B);
+                }
+            }
+        }
+    }
+
+    fn local(&mut self, opcode: u8, name: &str) {
+        let index = self.func.params.iter()
+            .chain(&self.func.locals)
+            .position(|local| local.name == name)
+            .unwrap_or_else(|| panic!("Unknown local ${} in ${}", name, self.func.name));
+        self.out.push(opcode);
+        write_u32(&mut self.out, index as u32);
+    }
+
+    fn global(&mut self, opcode: u8, name: &str) {
+        let index = self.module.globals.iter()
+            .position(|(global, _)| global == name)
+            .unwrap_or_else(|| panic!("Unknown global ${}", name));
+        self.out.push(opcode);
+        write_u32(&mut self.out, index as u32);
+    }
+
+    // A load or store: `align` is the log2 of the alignment
+    fn memory(&mut self, opcode: u8, align: u8, offset: u32) {
+        self.out.push(opcode);
+        self.out.push(align);
+        write_u32(&mut self.out, offset);
+    }
+
+    fn branch(&mut self, opcode: u8, label: &str) {
+        let depth = self.depth(label);
+        self.out.push(opcode);
+        write_u32(&mut self.out, depth);
+    }
+
+    // How many enclosing blocks out `label` is
+    fn depth(&self, label: &str) -> u32 {
+        self.labels.iter().rev()
+            .position(|l| *l == Some(label))
+            .unwrap_or_else(|| panic!("Unknown label ${} in ${}", label, self.func.name)) as u32
+    }
+}
+
+fn function_index(module: &Module, name: &str) -> u32 {
+    module.functions.iter()
+        .position(|f| f.name == name)
+        .unwrap_or_else(|| panic!("Unknown function ${}", name)) as u32
+}
+
+fn val_type(ty: ValType) -> u8 {
+    match ty {
+        ValType::I32 => 0x7F,
+        ValType::I64 => 0x7E,
+        ValType::F64 => 0x7C,
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    write_u32(out, contents.len() as u32);
+    out.extend_from_slice(contents);
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+// Unsigned LEB128
+fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+// Signed LEB128
+fn write_i32(out: &mut Vec<u8>, value: i32) {
+    write_i64(out, value as i64);
+}
+
+fn write_i64(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
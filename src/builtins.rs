@@ -0,0 +1,170 @@
+use crate::wasm::{Function, Instruction, Local, ValType};
+
+// The `Math` namespace. `Math.abs(x)` parses as a call to a function named `Math.abs`, which
+// can't clash with user functions since their names never contain a dot.
+//
+// Everything is on `i32`, so `floor`, `ceil` and `trunc` return their argument, and results
+// that JavaScript would give as a fraction are truncated like `x | 0` would.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Abs,
+    Min,
+    Max,
+    Floor,
+    Ceil,
+    Sqrt,
+    Trunc,
+    Sign,
+    Imul,
+    Clz32,
+    Pow,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 11] = [
+        Builtin::Abs,
+        Builtin::Min,
+        Builtin::Max,
+        Builtin::Floor,
+        Builtin::Ceil,
+        Builtin::Sqrt,
+        Builtin::Trunc,
+        Builtin::Sign,
+        Builtin::Imul,
+        Builtin::Clz32,
+        Builtin::Pow,
+    ];
+
+    // Name as called from source, which is also the name of its runtime helper
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Abs => "Math.abs",
+            Builtin::Min => "Math.min",
+            Builtin::Max => "Math.max",
+            Builtin::Floor => "Math.floor",
+            Builtin::Ceil => "Math.ceil",
+            Builtin::Sqrt => "Math.sqrt",
+            Builtin::Trunc => "Math.trunc",
+            Builtin::Sign => "Math.sign",
+            Builtin::Imul => "Math.imul",
+            Builtin::Clz32 => "Math.clz32",
+            Builtin::Pow => "Math.pow",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Builtin> {
+        Builtin::ALL.into_iter().find(|builtin| builtin.name() == name)
+    }
+
+    // Fewest and most arguments. `min` and `max` take any number from one up.
+    pub fn arity(&self) -> (usize, Option<usize>) {
+        match self {
+            Builtin::Min | Builtin::Max => (1, None),
+            Builtin::Imul | Builtin::Pow => (2, Some(2)),
+            _ => (1, Some(1)),
+        }
+    }
+
+    // Result for constant arguments. `args` has the full arity.
+    pub fn evaluate(&self, args: &[i32]) -> i32 {
+        match self {
+            Builtin::Abs => args[0].wrapping_abs(),
+            Builtin::Min => args.iter().copied().min().unwrap(),
+            Builtin::Max => args.iter().copied().max().unwrap(),
+            Builtin::Floor | Builtin::Ceil | Builtin::Trunc => args[0],
+            // Like `i32.trunc_sat_f64_s`, `as` maps NaN (the root of a negative number) to 0
+            Builtin::Sqrt => (args[0] as f64).sqrt() as i32,
+            Builtin::Sign => args[0].signum(),
+            Builtin::Imul => args[0].wrapping_mul(args[1]),
+            Builtin::Clz32 => args[0].leading_zeros() as i32,
+            Builtin::Pow => pow(args[0], args[1]),
+        }
+    }
+
+    // The wasm function a call is compiled to, for the builtins with no instruction of their own
+    pub fn helper(&self) -> Option<Function> {
+        use Instruction::*;
+        let get = |name: &str| LocalGet(name.to_string());
+
+        let (params, locals, body) = match self {
+            // x < 0 ? -x : x
+            Builtin::Abs => (vec!["x"], vec![], vec![
+                I32Const(0), get("x"), I32Sub,
+                get("x"),
+                get("x"), I32Const(0), I32LtS,
+                Select,
+            ]),
+            Builtin::Min | Builtin::Max => {
+                let compare = if *self == Builtin::Min { I32LtS } else { I32GtS };
+                (vec!["a", "b"], vec![], vec![get("a"), get("b"), get("a"), get("b"), compare, Select])
+            }
+            // (x > 0) - (x < 0)
+            Builtin::Sign => (vec!["x"], vec![], vec![
+                get("x"), I32Const(0), I32GtS,
+                get("x"), I32Const(0), I32LtS,
+                I32Sub,
+            ]),
+            Builtin::Pow => (vec!["base", "exp"], vec!["result"], pow_body()),
+            _ => return None,
+        };
+        Some(Function {
+            name: self.name().to_string(),
+            params: params.into_iter().map(|name| Local::new(name, ValType::I32)).collect(),
+            result: ValType::I32,
+            locals: locals.into_iter().map(|name| Local::new(name, ValType::I32)).collect(),
+            body,
+        })
+    }
+}
+
+// `base ** exp` by repeated squaring. Products wrap like `*`. A negative exponent gives a
+// fraction, which truncates to 0, except for bases 1 and -1.
+pub fn pow(mut base: i32, mut exp: i32) -> i32 {
+    if exp < 0 {
+        return match base {
+            1 => 1,
+            -1 => if exp & 1 != 0 { -1 } else { 1 },
+            _ => 0,
+        };
+    }
+    let mut result: i32 = 1;
+    while exp != 0 {
+        if exp & 1 != 0 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    result
+}
+
+// The same algorithm as `pow`, in wasm
+fn pow_body() -> Vec<Instruction> {
+    use Instruction::*;
+    let get = |name: &str| LocalGet(name.to_string());
+    let set = |name: &str| LocalSet(name.to_string());
+
+    vec![
+        get("exp"), I32Const(0), I32LtS,
+        If { result: None, then_body: vec![
+            get("base"), I32Const(1), I32Eq,
+            If { result: None, then_body: vec![I32Const(1), Return], else_body: None },
+            get("base"), I32Const(-1), I32Eq,
+            If { result: None, then_body: vec![
+                I32Const(-1), I32Const(1), get("exp"), I32Const(1), I32And, Select, Return,
+            ], else_body: None },
+            I32Const(0), Return,
+        ], else_body: None },
+        I32Const(1), set("result"),
+        Block { label: "done".to_string(), body: vec![Loop { label: "next".to_string(), body: vec![
+            get("exp"), I32Eqz, BrIf("done".to_string()),
+            get("exp"), I32Const(1), I32And,
+            If { result: None, then_body: vec![get("result"), get("base"), I32Mul, set("result")], else_body: None },
+            get("base"), get("base"), I32Mul, set("base"),
+            get("exp"), I32Const(1), I32ShrS, set("exp"),
+            Br("next".to_string()),
+        ] }] },
+        get("result"),
+    ]
+}
@@ -1,328 +1,852 @@
-use crate::ast::{Program, Statement, Expression, BinaryOp, UnaryOp};
-use std::collections::HashMap;
+use crate::error::{CompileError, ErrorKind, Result};
+use crate::ast::{Program, Statement, StatementKind, SwitchCase, Expression, ExpressionKind, Ident, BinaryOp, UnaryOp, UpdateOp};
+use crate::wasm::{Function, Instruction, Local, Module, ValType};
+use crate::options::{CompileOptions, Pass};
+use crate::cse::{self, CommonSubexpressions};
+use crate::builtins::{self, Builtin};
+use crate::sema::Analysis;
+use crate::token::Span;
+use crate::value::{self, Strings};
+use crate::runtime::{self, Runtime};
+use crate::types::{self, Types};
+
+// Expressions under `--dynamic`
+mod dynamic;
+
+// The top-level code, and the wrapper `--dynamic` exports as `_start`. Like the runtime's, the
+// names have a dot so a function in the program can't take them.
+const MAIN: &str = "program.main";
+const START: &str = "program.start";
+
+// An enclosing statement that `break` or `continue` can jump out of
+struct JumpTarget {
+    // JavaScript labels on the statement
+    labels: Vec<String>,
+    // Whether a `break` without a label can target it: loops and `switch`, not labeled blocks
+    unlabeled: bool,
+    break_label: String,
+    // For loops
+    continue_label: Option<String>,
+    // Which of the labels something jumped to, so unused blocks can be left out
+    broken: bool,
+    continued: bool,
+}
 
 pub struct CodeGenerator {
-    output: String,
-    // Stack of scopes. Each scope maps "JS name" -> ("WASM name", is_const)
-    scopes: Vec<HashMap<String, (String, bool)>>, 
-    local_counter: usize,
+    options: CompileOptions,
+    // What every identifier refers to, from semantic analysis
+    analysis: Analysis,
+    module: Module,
+    // Instructions of the body currently being generated
+    body: Vec<Instruction>,
+    temp_counter: usize,
     label_counter: usize,
+    // Shared subexpressions of the statement being generated
+    cse: CommonSubexpressions,
+    // Locals introduced while generating the current function body (CSE temporaries)
+    temps: Vec<Local>,
+    // Builtins called so far whose runtime helper has to be added to the module
+    helpers: Vec<Builtin>,
+    // The same for the runtime of dynamic values, and the strings they use
+    runtime: Vec<Runtime>,
+    strings: Strings,
+    // What type variables and function results have, under `--dynamic`
+    types: Types,
+    // Enclosing loops, switches and labeled statements, innermost last
+    targets: Vec<JumpTarget>,
+    // Labels for the statement about to be generated, for `a: b: while (...)`
+    pending_labels: Vec<String>,
+    // The first internal error, returned once generation is done
+    error: Option<CompileError>,
 }
 
-impl Default for CodeGenerator {
-    fn default() -> Self {
-        Self::new()
+impl CodeGenerator {
+    pub fn new(analysis: Analysis) -> Self {
+        Self::with_options(CompileOptions::default(), analysis)
     }
-}
 
-impl CodeGenerator {
-    pub fn new() -> Self {
+    pub fn with_options(options: CompileOptions, analysis: Analysis) -> Self {
         CodeGenerator {
-            output: String::new(),
-            scopes: vec![HashMap::new()], // Global scope
-            local_counter: 0,
+            options,
+            analysis,
+            module: Module::default(),
+            body: Vec::new(),
+            temp_counter: 0,
             label_counter: 0,
+            cse: CommonSubexpressions::default(),
+            temps: Vec::new(),
+            helpers: Vec::new(),
+            runtime: Vec::new(),
+            strings: Strings::default(),
+            types: Types::default(),
+            targets: Vec::new(),
+            pending_labels: Vec::new(),
+            error: None,
         }
     }
 
-    fn enter_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+    // What a variable, parameter or function result holds
+    fn value_type(&self) -> ValType {
+        if self.options.dynamic { ValType::I64 } else { ValType::I32 }
     }
 
-    fn exit_scope(&mut self) {
-        self.scopes.pop();
+    // The value of a missing argument or return value
+    fn emit_undefined(&mut self) {
+        if self.options.dynamic {
+            self.emit(Instruction::I64Const(value::UNDEFINED));
+        } else {
+            self.emit(Instruction::I32Const(0));
+        }
     }
 
-    fn declare_local(&mut self, name: &str, is_const: bool) -> String {
-        let wasm_name = format!("${}_{}", name, self.local_counter);
-        self.local_counter += 1;
-        
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.to_string(), (wasm_name.clone(), is_const));
+    fn wasm_name(&mut self, span: Span) -> String {
+        match self.analysis.binding(span) {
+            Some(binding) => binding.wasm_name.clone(),
+            None => {
+                self.internal_error("identifier without a binding", span);
+                String::new()
+            }
         }
-        wasm_name
     }
 
-    fn get_local(&self, name: &str) -> Option<(String, bool)> {
-        // Search from inner-most scope to outer-most
-        for scope in self.scopes.iter().rev() {
-            if let Some(info) = scope.get(name) {
-                return Some(info.clone());
+    // How many parameters a called function takes
+    fn params(&mut self, callee: &Ident) -> usize {
+        match self.analysis.function(&callee.name) {
+            Some(function) => function.params,
+            None => {
+                self.internal_error("call to an unknown function", callee.span);
+                0
             }
         }
-        None
+    }
+
+    // Sema should have rejected whatever led here. Generation goes on with a placeholder and
+    // `generate` returns the first of these.
+    fn internal_error(&mut self, message: &str, span: Span) {
+        let error = CompileError::new(ErrorKind::Internal, format!("Internal compiler error: {}", message), Some(span))
+            .with_help("this is a bug in the compiler, not in the program");
+        self.error.get_or_insert(error);
+    }
+
+    // `let`/`const` locals of a function body, or of the top-level code for None
+    fn locals(&self, function: Option<&str>) -> Vec<Local> {
+        self.analysis.locals(function)
+            .map(|binding| Local::new(&binding.wasm_name, self.value_type()))
+            .collect()
     }
 
     fn new_label(&mut self, prefix: &str) -> String {
-        let label = format!("${}_{}", prefix, self.label_counter);
+        let label = format!("{}_{}", prefix, self.label_counter);
         self.label_counter += 1;
         label
     }
 
-    pub fn generate(&mut self, program: &Program) -> String {
-        self.output.push_str("(module\n");
-        
+    fn emit(&mut self, instr: Instruction) {
+        self.body.push(instr);
+    }
+
+    // Runs `f` against a fresh body and returns what it generated
+    fn nested(&mut self, f: impl FnOnce(&mut Self)) -> Vec<Instruction> {
+        let outer = std::mem::take(&mut self.body);
+        f(self);
+        std::mem::replace(&mut self.body, outer)
+    }
+
+    // `program` must have passed semantic analysis, which produced `self.analysis`
+    pub fn generate(mut self, program: &Program) -> Result<Module> {
+        if self.options.dynamic {
+            self.types = types::infer(program, &self.analysis);
+        }
+
         // 1. Generate all function declarations first (hoisting)
         for stmt in &program.body {
-            if let Statement::FunctionDeclaration { name, params, body } = stmt {
+            if let StatementKind::FunctionDeclaration { name, params, body } = &stmt.kind {
                 self.generate_function(name, params, body);
             }
         }
 
         // 2. Generate the main entry point for top-level code
-        self.output.push_str("  (func $main (result i32)\n");
-        
-        // Pre-pass: Declare locals for top-level code
-        // Note: This is a simplification. Real JS `let` is block-scoped.
-        // We need to scan the top-level body for variables.
-        let locals = self.collect_locals(&program.body);
-        for local in locals {
-            self.output.push_str(&format!("    (local {} i32)\n", local));
-        }
+        let mut locals = self.locals(None);
 
         // Generate code for non-function statements
         let stmts: Vec<&Statement> = program.body.iter()
-            .filter(|s| !matches!(s, Statement::FunctionDeclaration { .. }))
+            .filter(|s| !matches!(s.kind, StatementKind::FunctionDeclaration { .. }))
             .collect();
 
-        if let Some((last, rest)) = stmts.split_last() {
-            for stmt in rest {
-                self.generate_statement(stmt);
-            }
-            
-            // Handle the last statement specially
-            match last {
-                Statement::Expression(expr) => {
-                    self.generate_expression(expr);
-                    // Do NOT drop. This is our return value.
+        let body = self.nested(|this| {
+            if let Some((last, rest)) = stmts.split_last() {
+                for stmt in rest {
+                    this.generate_statement(stmt);
                 }
-                _ => {
-                    self.generate_statement(last);
-                    self.output.push_str("    i32.const 0\n"); // Default return
+
+                // Handle the last statement specially
+                match &last.kind {
+                    StatementKind::Expression(expr) => {
+                        this.generate_root(expr);
+                        // Do NOT drop. This is our return value.
+                    }
+                    _ => {
+                        this.generate_statement(last);
+                        this.emit_undefined(); // Default return
+                    }
                 }
+            } else {
+                this.emit_undefined(); // Empty program
             }
+        });
+
+        locals.append(&mut self.temps);
+
+        self.module.functions.push(Function {
+            name: MAIN.to_string(),
+            params: Vec::new(),
+            result: self.value_type(),
+            locals,
+            body,
+        });
+        if self.options.dynamic {
+            // Hosts get the program's result as a plain number
+            let to_number = self.runtime_call(Runtime::ToNumber);
+            self.module.functions.push(Function {
+                name: START.to_string(),
+                params: Vec::new(),
+                result: ValType::F64,
+                locals: Vec::new(),
+                body: vec![Instruction::Call(MAIN.to_string()), to_number],
+            });
+            self.module.exports.push(("_start".to_string(), START.to_string()));
         } else {
-            self.output.push_str("    i32.const 0\n"); // Empty program
+            self.module.exports.push(("_start".to_string(), MAIN.to_string()));
         }
 
-        self.output.push_str("  )\n");
-        self.output.push_str("  (export \"_start\" (func $main))\n");
-        self.output.push_str(")\n");
-        
-        self.output.clone()
-    }
-
-    fn generate_function(&mut self, name: &str, params: &[String], body: &[Statement]) {
-        self.output.push_str(&format!("  (func ${} ", name));
-        
-        self.enter_scope();
-        
-        // Params
-        for param in params {
-            let wasm_name = format!("${}", param); // Params don't need unique suffix usually, but let's be safe? 
-            // Actually, params are locals too. Let's just use the name directly for params to keep it simple,
-            // assuming no collision with keywords.
-            self.output.push_str(&format!("(param {} i32) ", wasm_name));
-            
-            // Add to scope
-            if let Some(scope) = self.scopes.last_mut() {
-                scope.insert(param.clone(), (wasm_name, false)); // Params are mutable
+        for builtin in std::mem::take(&mut self.helpers) {
+            self.module.functions.extend(builtin.helper());
+        }
+        // Runtime functions call each other, so this goes on until nothing new is called
+        let mut i = 0;
+        while let Some(&runtime) = self.runtime.get(i) {
+            for &callee in runtime.calls() {
+                self.runtime_call(callee);
             }
+            let function = runtime.function(&mut self.strings);
+            self.module.functions.push(function);
+            i += 1;
         }
-        self.output.push_str("(result i32)\n");
-
-        // Locals
-        let locals = self.collect_locals(body);
-        for local in locals {
-            self.output.push_str(&format!("    (local {} i32)\n", local));
+        self.module.data = std::mem::take(&mut self.strings).into_data();
+        // The heap starts after the data
+        if self.runtime.contains(&Runtime::Alloc) {
+            self.module.globals.push((runtime::HEAP.to_string(), self.module.data.len().next_multiple_of(4) as i32));
         }
-
-        // Body
-        for stmt in body {
-            self.generate_statement(stmt);
+        // `_start` converts the result with `runtime.to_number`, which reads strings
+        self.module.memory = self.options.dynamic;
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.module),
         }
+    }
 
-        // Default return 0
-        self.output.push_str("    i32.const 0\n");
-        self.output.push_str("  )\n");
-        
-        self.exit_scope();
-    }
-
-    // Helper to find all `let` declarations in a block (recursively) and assign them unique WASM names
-    fn collect_locals(&mut self, stmts: &[Statement]) -> Vec<String> {
-        let mut locals = Vec::new();
-        for stmt in stmts {
-            match stmt {
-                Statement::VariableDeclaration { name, is_const, .. } => {
-                    let wasm_name = self.declare_local(name, *is_const);
-                    locals.push(wasm_name);
-                }
-                Statement::Block(inner) => {
-                    locals.extend(self.collect_locals(inner));
-                }
-                Statement::If { then_branch, else_branch, .. } => {
-                    // We need to peek inside blocks
-                    if let Statement::Block(b) = &**then_branch {
-                        locals.extend(self.collect_locals(b));
-                    }
-                    if let Some(else_b) = else_branch
-                        && let Statement::Block(b) = &**else_b {
-                            locals.extend(self.collect_locals(b));
-                        }
-                }
-                Statement::While { body, .. } => {
-                    if let Statement::Block(b) = &**body {
-                        locals.extend(self.collect_locals(b));
-                    }
-                }
-                _ => {}
+    fn generate_function(&mut self, name: &Ident, params: &[Ident], body: &[Statement]) {
+        let wasm_params = params.iter()
+            .map(|param| Local::new(&self.wasm_name(param.span), self.value_type()))
+            .collect();
+        let mut locals = self.locals(Some(&name.name));
+
+        let instructions = self.nested(|this| {
+            for stmt in body {
+                this.generate_statement(stmt);
             }
-        }
-        locals
+            // Default return undefined
+            this.emit_undefined();
+        });
+        locals.append(&mut self.temps);
+
+        self.module.functions.push(Function {
+            name: name.name.clone(),
+            params: wasm_params,
+            result: self.value_type(),
+            locals,
+            body: instructions,
+        });
     }
 
     fn generate_statement(&mut self, stmt: &Statement) {
-        match stmt {
-            Statement::VariableDeclaration { name, init, .. } => {
-                self.generate_expression(init);
-                let (wasm_name, _) = self.get_local(name).expect("Local not found (should be declared in pre-pass)");
-                self.output.push_str(&format!("    local.set {}\n", wasm_name));
-            }
-            Statement::Expression(expr) => {
-                self.generate_expression(expr);
+        match &stmt.kind {
+            StatementKind::VariableDeclaration { name, init, .. } => {
+                self.generate_root(init);
+                let wasm_name = self.wasm_name(name.span);
+                self.emit(Instruction::LocalSet(wasm_name));
+            }
+            StatementKind::Expression(expr) => {
+                match &expr.kind {
+                    // Nothing uses the old value of a postfix `i++` here, so emit it as `++i`
+                    ExpressionKind::Update { target, op, prefix: false } => {
+                        let kind = ExpressionKind::Update { target: target.clone(), op: *op, prefix: true };
+                        self.generate_root(&Expression::new(kind, expr.span));
+                    }
+                    _ => self.generate_root(expr),
+                }
                 // If expression returns a value, drop it (unless it's the last one, but for now drop to keep stack clean)
-                self.output.push_str("    drop\n"); 
+                self.emit(Instruction::Drop);
             }
-            Statement::Return(expr) => {
+            StatementKind::Return(expr) => {
                 if let Some(e) = expr {
-                    self.generate_expression(e);
+                    self.generate_root(e);
                 } else {
-                    self.output.push_str("    i32.const 0\n");
+                    self.emit_undefined();
                 }
-                self.output.push_str("    return\n");
+                self.emit(Instruction::Return);
             }
-            Statement::Block(stmts) => {
+            StatementKind::Block(stmts) => {
                 // WASM blocks don't create scope automatically for locals (we handled that with renaming),
                 // but they are useful for control flow.
                 for s in stmts {
                     self.generate_statement(s);
                 }
             }
-            Statement::If { condition, then_branch, else_branch } => {
-                self.generate_expression(condition);
-                self.output.push_str("    (if\n");
-                self.output.push_str("      (then\n");
-                self.generate_statement(then_branch);
-                self.output.push_str("      )\n");
-                if let Some(else_b) = else_branch {
-                    self.output.push_str("      (else\n");
-                    self.generate_statement(else_b);
-                    self.output.push_str("      )\n");
-                }
-                self.output.push_str("    )\n");
+            StatementKind::If { condition, then_branch, else_branch } => {
+                self.generate_condition(condition);
+                let then_body = self.nested(|this| this.generate_statement(then_branch));
+                let else_body = else_branch.as_ref()
+                    .map(|else_b| self.nested(|this| this.generate_statement(else_b)));
+                self.emit(Instruction::If { result: None, then_body, else_body });
             }
-            Statement::While { condition, body } => {
+            StatementKind::While { condition, body } => {
                 let block_label = self.new_label("break");
                 let loop_label = self.new_label("continue");
-                
-                self.output.push_str(&format!("    (block {}\n", block_label));
-                self.output.push_str(&format!("      (loop {}\n", loop_label));
-                
-                // Condition
-                self.generate_expression(condition);
-                self.output.push_str("        i32.eqz\n"); // Invert condition for br_if
-                self.output.push_str(&format!("        br_if {}\n", block_label));
-                
-                // Body
-                self.generate_statement(body);
-                
-                // Jump back
-                self.output.push_str(&format!("        br {}\n", loop_label));
-                
-                self.output.push_str("      )\n"); // End loop
-                self.output.push_str("    )\n"); // End block
-            }
-            _ => {}
+                // The loop re-checks the condition at the top, so `continue` can just go there
+                self.enter(block_label.clone(), Some(loop_label.clone()), true);
+
+                let loop_body = self.nested(|this| {
+                    // Condition
+                    this.generate_condition(condition);
+                    this.emit(Instruction::I32Eqz); // Invert condition for br_if
+                    this.emit(Instruction::BrIf(block_label.clone()));
+
+                    // Body
+                    this.generate_statement(body);
+
+                    // Jump back
+                    this.emit(Instruction::Br(loop_label.clone()));
+                });
+                self.targets.pop();
+
+                self.emit(Instruction::Block {
+                    label: block_label,
+                    body: vec![Instruction::Loop { label: loop_label, body: loop_body }],
+                });
+            }
+            StatementKind::DoWhile { body, condition } => {
+                let block_label = self.new_label("break");
+                let loop_label = self.new_label("loop");
+                let continue_label = self.new_label("continue");
+                self.enter(block_label.clone(), Some(continue_label.clone()), true);
+
+                let mut loop_body = self.nested(|this| this.generate_statement(body));
+                let target = self.targets.pop().expect("loop target");
+                // `continue` skips the rest of the body but still checks the condition
+                if target.continued {
+                    loop_body = vec![Instruction::Block { label: continue_label, body: loop_body }];
+                }
+                loop_body.append(&mut self.nested(|this| {
+                    this.generate_condition(condition);
+                    this.emit(Instruction::BrIf(loop_label.clone()));
+                }));
+
+                let looped = Instruction::Loop { label: loop_label, body: loop_body };
+                self.emit_breakable(block_label, target.broken, vec![looped]);
+            }
+            StatementKind::Switch { discriminant, cases } => self.generate_switch(discriminant, cases),
+            StatementKind::Labeled { label, body } => {
+                self.pending_labels.push(label.name.clone());
+                match body.kind {
+                    // These take the labels as their own
+                    StatementKind::Labeled { .. } | StatementKind::While { .. } | StatementKind::DoWhile { .. } | StatementKind::Switch { .. } => {
+                        self.generate_statement(body);
+                    }
+                    _ => {
+                        let block_label = self.new_label(&label.name);
+                        self.enter(block_label.clone(), None, false);
+                        let block_body = self.nested(|this| this.generate_statement(body));
+                        let target = self.targets.pop().expect("label target");
+                        self.emit_breakable(block_label, target.broken, block_body);
+                    }
+                }
+            }
+            StatementKind::Break(label) => {
+                let target = self.target(label.as_ref(), |t| t.unlabeled);
+                target.broken = true;
+                let label = target.break_label.clone();
+                self.emit(Instruction::Br(label));
+            }
+            StatementKind::Continue(label) => {
+                let target = self.target(label.as_ref(), |t| t.continue_label.is_some());
+                target.continued = true;
+                let label = target.continue_label.clone().expect("continue to a loop");
+                self.emit(Instruction::Br(label));
+            }
+            StatementKind::FunctionDeclaration { .. } => {}
+        }
+    }
+
+    // Starts a statement that `break` can leave, taking the labels written before it
+    fn enter(&mut self, break_label: String, continue_label: Option<String>, unlabeled: bool) {
+        let labels = std::mem::take(&mut self.pending_labels);
+        self.targets.push(JumpTarget { labels, unlabeled, break_label, continue_label, broken: false, continued: false });
+    }
+
+    // The statement a `break`/`continue` jumps to: the one with `label`, or else the innermost
+    // that `accepts` an unlabeled jump. Sema has checked that it exists.
+    fn target(&mut self, label: Option<&Ident>, accepts: impl Fn(&JumpTarget) -> bool) -> &mut JumpTarget {
+        self.targets.iter_mut().rev()
+            .find(|t| match label {
+                Some(label) => t.labels.contains(&label.name),
+                None => accepts(t),
+            })
+            .expect("jump without a target")
+    }
+
+    // `body` wrapped in a block to break out of, if anything does
+    fn emit_breakable(&mut self, label: String, broken: bool, mut body: Vec<Instruction>) {
+        if broken {
+            self.emit(Instruction::Block { label, body });
+        } else {
+            self.body.append(&mut body);
+        }
+    }
+
+    // Each case's code follows the end of a block that its dispatch branches out of, so
+    // falling off the end of one case runs into the next:
+    //
+    //   (block $break
+    //     (block $case_1
+    //       (block $case_0
+    //         dispatch: br_if $case_0 ... br $break)
+    //       case 0 statements)
+    //     case 1 statements)
+    fn generate_switch(&mut self, discriminant: &Expression, cases: &[SwitchCase]) {
+        let break_label = self.new_label("break");
+        let case_labels: Vec<String> = cases.iter().map(|_| self.new_label("case")).collect();
+        let default_label = cases.iter()
+            .position(|case| case.test.is_none())
+            .map_or(&break_label, |i| &case_labels[i])
+            .clone();
+
+        // Dynamic values can't index a table
+        let table = if self.options.dynamic { None } else { jump_table(cases, &case_labels, &default_label) };
+        let mut body = self.nested(|this| match table {
+            Some((min, labels)) => {
+                this.generate_root(discriminant);
+                if min != 0 {
+                    this.emit(Instruction::I32Const(min));
+                    this.emit(Instruction::I32Sub);
+                }
+                this.emit(Instruction::BrTable { labels, default: default_label.clone() });
+            }
+            None => {
+                // The discriminant is evaluated once, the tests in order until one matches
+                let temp = this.new_temp("switch", this.value_type());
+                this.generate_root(discriminant);
+                this.emit(Instruction::LocalSet(temp.clone()));
+                for (case, label) in cases.iter().zip(&case_labels) {
+                    if let Some(test) = &case.test {
+                        this.emit(Instruction::LocalGet(temp.clone()));
+                        this.generate_root(test);
+                        if this.options.dynamic {
+                            this.call_runtime(Runtime::StrictEquals);
+                        } else {
+                            this.emit(Instruction::I32Eq);
+                        }
+                        this.emit(Instruction::BrIf(label.clone()));
+                    }
+                }
+                this.emit(Instruction::Br(default_label.clone()));
+            }
+        });
+
+        self.enter(break_label.clone(), None, true);
+        for (case, label) in cases.iter().zip(case_labels) {
+            let mut statements = self.nested(|this| {
+                for stmt in &case.body {
+                    this.generate_statement(stmt);
+                }
+            });
+            body = vec![Instruction::Block { label, body }];
+            body.append(&mut statements);
+        }
+        self.targets.pop();
+        self.emit(Instruction::Block { label: break_label, body });
+    }
+
+    // Temporaries are `prefix.N`: sema names locals `name_N`, and names in source never have a dot
+    fn new_temp(&mut self, prefix: &str, ty: ValType) -> String {
+        let temp = format!("{}.{}", prefix, self.temp_counter);
+        self.temp_counter += 1;
+        self.temps.push(Local::new(&temp, ty));
+        temp
+    }
+
+    // Generates the top-level expression of a statement, sharing repeated subexpressions if enabled
+    fn generate_root(&mut self, expr: &Expression) {
+        if self.options.dynamic {
+            self.generate_value(expr);
+            return;
+        }
+        if self.options.has_pass(Pass::Cse) {
+            let mut new_temps = Vec::new();
+            self.cse = cse::plan(expr, || {
+                let temp = format!("cse.{}", self.temp_counter);
+                self.temp_counter += 1;
+                new_temps.push(Local::new(&temp, ValType::I32));
+                temp
+            });
+            self.temps.append(&mut new_temps);
+        }
+        self.generate_expression(expr);
+        self.cse = CommonSubexpressions::default();
+    }
+
+    // An `if`/loop condition, leaving a non-zero `i32` if it's true
+    fn generate_condition(&mut self, expr: &Expression) {
+        if self.options.dynamic {
+            self.generate_test(expr);
+        } else {
+            self.generate_root(expr);
         }
     }
 
     fn generate_expression(&mut self, expr: &Expression) {
-        match expr {
-            Expression::Number(n) => {
-                self.output.push_str(&format!("    i32.const {}\n", n));
+        let shared = self.cse.lookup(expr);
+        if let Some((temp, true)) = &shared {
+            self.emit(Instruction::LocalGet(temp.clone()));
+            return;
+        }
+
+        self.generate_uncached(expr);
+
+        let barrier = match &expr.kind {
+            ExpressionKind::Assignment(..) | ExpressionKind::CompoundAssignment(..) | ExpressionKind::Update { .. } => true,
+            ExpressionKind::Call(callee, _) => Builtin::parse(&callee.name).is_none(),
+            _ => false,
+        };
+        if barrier {
+            self.cse.invalidate();
+        }
+        if let Some((temp, _)) = shared {
+            self.emit(Instruction::LocalTee(temp));
+            self.cse.mark_available(expr);
+        }
+    }
+
+    fn generate_uncached(&mut self, expr: &Expression) {
+        match &expr.kind {
+            // The lexer only lets through 32-bit integers without --dynamic
+            ExpressionKind::Number(n) => {
+                self.emit(Instruction::I32Const(*n as i32));
+            }
+            // Without type tags `true` is 1 and the rest are 0
+            ExpressionKind::Boolean(_) | ExpressionKind::Null | ExpressionKind::Undefined => {
+                let value = constant_value(expr).expect("literal");
+                self.emit(Instruction::I32Const(value));
             }
-            Expression::Identifier(name) => {
-                let (wasm_name, _) = self.get_local(name).unwrap_or_else(|| panic!("Undefined variable: {}", name));
-                self.output.push_str(&format!("    local.get {}\n", wasm_name));
+            ExpressionKind::String(_) => unreachable!("sema only allows strings with --dynamic"),
+            ExpressionKind::Identifier(_) => {
+                let wasm_name = self.wasm_name(expr.span);
+                self.emit(Instruction::LocalGet(wasm_name));
             }
-            Expression::Binary(left, op, right) => {
+            ExpressionKind::Binary(left, op, right) => {
                 // Constant Folding Optimization
-                if let (Expression::Number(l), Expression::Number(r)) = (&**left, &**right) {
-                    let result = match op {
-                        BinaryOp::Add => l.wrapping_add(*r),
-                        BinaryOp::Sub => l.wrapping_sub(*r),
-                        BinaryOp::Mul => l.wrapping_mul(*r),
-                        BinaryOp::Div => if *r != 0 { l.wrapping_div(*r) } else { 0 }, // Avoid panic
-                        BinaryOp::Mod => if *r != 0 { l.wrapping_rem(*r) } else { 0 },
-                        BinaryOp::Eq => if l == r { 1 } else { 0 },
-                        BinaryOp::Ne => if l != r { 1 } else { 0 },
-                        BinaryOp::Lt => if l < r { 1 } else { 0 },
-                        BinaryOp::Gt => if l > r { 1 } else { 0 },
-                        BinaryOp::Le => if l <= r { 1 } else { 0 },
-                        BinaryOp::Ge => if l >= r { 1 } else { 0 },
-                    };
-                    self.output.push_str(&format!("    i32.const {}\n", result));
+                if let Some(value) = self.fold(expr) {
+                    self.emit(Instruction::I32Const(value));
                     return;
                 }
 
                 self.generate_expression(left);
                 self.generate_expression(right);
-                match op {
-                    BinaryOp::Add => self.output.push_str("    i32.add\n"),
-                    BinaryOp::Sub => self.output.push_str("    i32.sub\n"),
-                    BinaryOp::Mul => self.output.push_str("    i32.mul\n"),
-                    BinaryOp::Div => self.output.push_str("    i32.div_s\n"), // Signed division
-                    BinaryOp::Mod => self.output.push_str("    i32.rem_s\n"),
-                    BinaryOp::Eq => self.output.push_str("    i32.eq\n"),
-                    BinaryOp::Ne => self.output.push_str("    i32.ne\n"),
-                    BinaryOp::Lt => self.output.push_str("    i32.lt_s\n"),
-                    BinaryOp::Gt => self.output.push_str("    i32.gt_s\n"),
-                    BinaryOp::Le => self.output.push_str("    i32.le_s\n"),
-                    BinaryOp::Ge => self.output.push_str("    i32.ge_s\n"),
-                }
+                self.emit_binary(op);
             }
-            Expression::Assignment(name, value) => {
+            ExpressionKind::Assignment(target, value) => {
                 self.generate_expression(value);
-                let (wasm_name, is_const) = self.get_local(name).unwrap_or_else(|| panic!("Undefined variable: {}", name));
-                
-                if is_const {
-                    panic!("Assignment to constant variable '{}'", name);
+                let wasm_name = self.wasm_name(target.span);
+
+                // tee sets the local AND leaves value on stack
+                self.emit(Instruction::LocalTee(wasm_name));
+            }
+            ExpressionKind::CompoundAssignment(target, op, value) => {
+                let wasm_name = self.wasm_name(target.span);
+                self.emit(Instruction::LocalGet(wasm_name.clone()));
+                self.generate_expression(value);
+                self.emit_binary(op);
+                self.emit(Instruction::LocalTee(wasm_name));
+            }
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                if let Some(value) = self.fold(expr) {
+                    self.emit(Instruction::I32Const(value));
+                    return;
+                }
+                if self.options.has_pass(Pass::FoldConstants) && let Some(value) = constant_value(condition) {
+                    self.generate_expression(if value != 0 { then } else { otherwise });
+                    return;
                 }
 
-                self.output.push_str("    local.tee "); // tee sets the local AND leaves value on stack
-                self.output.push_str(&wasm_name);
-                self.output.push('\n');
+                if cse::is_select(condition, then, otherwise) {
+                    self.generate_expression(then);
+                    self.generate_expression(otherwise);
+                    self.generate_expression(condition);
+                    self.emit(Instruction::Select);
+                } else {
+                    self.generate_expression(condition);
+                    let before = self.cse.snapshot();
+                    let then_body = self.nested(|this| this.generate_expression(then));
+                    let after_then = self.cse.snapshot();
+                    self.cse.restore(&before);
+                    let else_body = self.nested(|this| this.generate_expression(otherwise));
+                    self.cse.intersect(&after_then);
+                    self.emit(Instruction::If { result: Some(ValType::I32), then_body, else_body: Some(else_body) });
+                }
+            }
+            ExpressionKind::Sequence(exprs) => {
+                let (last, rest) = exprs.split_last().expect("empty sequence");
+                for e in rest {
+                    self.generate_expression(e);
+                    self.emit(Instruction::Drop);
+                }
+                self.generate_expression(last);
+            }
+            ExpressionKind::Update { target, op, prefix } => {
+                let wasm_name = self.wasm_name(target.span);
+                let step = match op {
+                    UpdateOp::Increment => Instruction::I32Add,
+                    UpdateOp::Decrement => Instruction::I32Sub,
+                };
+                self.emit(Instruction::LocalGet(wasm_name.clone()));
+                if *prefix {
+                    self.emit(Instruction::I32Const(1));
+                    self.emit(step);
+                    self.emit(Instruction::LocalTee(wasm_name));
+                } else {
+                    // Leave the old value underneath the update
+                    self.emit(Instruction::LocalGet(wasm_name.clone()));
+                    self.emit(Instruction::I32Const(1));
+                    self.emit(step);
+                    self.emit(Instruction::LocalSet(wasm_name));
+                }
             }
-            Expression::Call(name, args) => {
-                for arg in args {
+            ExpressionKind::Call(callee, args) if let Some(builtin) = Builtin::parse(&callee.name) => {
+                if let Some(value) = self.fold(expr) {
+                    self.emit(Instruction::I32Const(value));
+                    return;
+                }
+                self.generate_builtin(builtin, args);
+            }
+            ExpressionKind::Call(callee, args) => {
+                // Sema only lets a mismatched argument count through under `loose_arity`
+                let params = self.params(callee);
+                for (i, arg) in args.iter().enumerate() {
                     self.generate_expression(arg);
+                    if i >= params {
+                        self.emit(Instruction::Drop); // Extra arguments are still evaluated
+                    }
+                }
+                for _ in args.len()..params {
+                    self.emit(Instruction::I32Const(0)); // Missing arguments are undefined
                 }
-                self.output.push_str(&format!("    call ${}\n", name));
+                self.emit(Instruction::Call(callee.name.clone()));
             }
-            Expression::Unary(op, right) => {
+            ExpressionKind::Unary(op, right) => {
+                if let Some(value) = self.fold(expr) {
+                    self.emit(Instruction::I32Const(value));
+                    return;
+                }
+
                 match op {
                     UnaryOp::Not => {
                         self.generate_expression(right);
-                        self.output.push_str("    i32.eqz\n"); // 0 -> 1, non-zero -> 0
+                        self.emit(Instruction::I32Eqz); // 0 -> 1, non-zero -> 0
                     }
                     UnaryOp::Neg => {
-                        self.output.push_str("    i32.const 0\n");
+                        self.emit(Instruction::I32Const(0));
+                        self.generate_expression(right);
+                        self.emit(Instruction::I32Sub); // 0 - x
+                    }
+                    UnaryOp::BitNot => {
                         self.generate_expression(right);
-                        self.output.push_str("    i32.sub\n"); // 0 - x
+                        self.emit(Instruction::I32Const(-1));
+                        self.emit(Instruction::I32Xor); // x ^ 0xFFFFFFFF
                     }
+                    UnaryOp::Typeof => unreachable!("sema only allows typeof with --dynamic"),
                 }
             }
         }
     }
+
+    fn emit_binary(&mut self, op: &BinaryOp) {
+        if *op == BinaryOp::Pow {
+            self.call_helper(Builtin::Pow);
+        } else {
+            self.emit(binary_instruction(op));
+        }
+    }
+
+    fn call_helper(&mut self, builtin: Builtin) {
+        if !self.helpers.contains(&builtin) {
+            self.helpers.push(builtin);
+        }
+        self.emit(Instruction::Call(builtin.name().to_string()));
+    }
+
+    fn generate_builtin(&mut self, builtin: Builtin, args: &[Expression]) {
+        // As with user functions, sema only lets a mismatched count through under `loose_arity`
+        let (min, max) = builtin.arity();
+        for (i, arg) in args.iter().enumerate() {
+            self.generate_expression(arg);
+            if max.is_some_and(|max| i >= max) {
+                self.emit(Instruction::Drop);
+            }
+        }
+        for _ in args.len()..min {
+            self.emit(Instruction::I32Const(0));
+        }
+        let count = args.len().clamp(min, max.unwrap_or(usize::MAX));
+
+        match builtin {
+            Builtin::Floor | Builtin::Ceil | Builtin::Trunc => {}
+            Builtin::Imul => self.emit(Instruction::I32Mul),
+            Builtin::Clz32 => self.emit(Instruction::I32Clz),
+            Builtin::Sqrt => {
+                self.emit(Instruction::F64ConvertI32S);
+                self.emit(Instruction::F64Sqrt);
+                self.emit(Instruction::I32TruncSatF64S);
+            }
+            // `Math.min(a, b, c)` is `min(min(a, b), c)`
+            Builtin::Min | Builtin::Max => {
+                for _ in 1..count {
+                    self.call_helper(builtin);
+                }
+            }
+            Builtin::Abs | Builtin::Sign | Builtin::Pow => self.call_helper(builtin),
+        }
+    }
+
+    // Evaluates `expr` at compile time, as far as the enabled folding passes allow
+    fn fold(&self, expr: &Expression) -> Option<i32> {
+        if self.options.has_pass(Pass::FoldConstants) {
+            return constant_value(expr);
+        }
+        if self.options.has_pass(Pass::FoldLiterals)
+            && let ExpressionKind::Binary(left, op, right) = &expr.kind
+            && let (ExpressionKind::Number(l), ExpressionKind::Number(r)) = (&left.kind, &right.kind) {
+                return fold_binary(op, *l as i32, *r as i32);
+            }
+        None
+    }
+}
+
+// For a switch whose cases are all constants close enough together, the smallest case value and
+// a `br_table` entry for every value from there up to the largest. The first matching case wins.
+fn jump_table(cases: &[SwitchCase], case_labels: &[String], default_label: &str) -> Option<(i32, Vec<String>)> {
+    let mut values = Vec::new();
+    for (case, label) in cases.iter().zip(case_labels) {
+        if let Some(test) = &case.test {
+            values.push((constant_value(test)?, label));
+        }
+    }
+    // Fewer cases than this are as cheap to test one by one
+    if values.len() < 3 {
+        return None;
+    }
+    let min = values.iter().map(|(value, _)| *value).min()?;
+    let max = values.iter().map(|(value, _)| *value).max()?;
+    let size = (max as i64 - min as i64 + 1) as usize;
+    if size > 2 * values.len() {
+        return None;
+    }
+
+    let mut table: Vec<Option<&String>> = vec![None; size];
+    for (value, label) in values {
+        table[(value as i64 - min as i64) as usize].get_or_insert(label);
+    }
+    let labels = table.into_iter().map(|label| label.map_or(default_label, |l| l).to_string()).collect();
+    Some((min, labels))
+}
+
+// Value of an expression built only from literals, or None if it depends on anything else
+pub fn constant_value(expr: &Expression) -> Option<i32> {
+    match &expr.kind {
+        ExpressionKind::Number(n) => Some(*n as i32),
+        ExpressionKind::Boolean(b) => Some(*b as i32),
+        ExpressionKind::Null | ExpressionKind::Undefined => Some(0),
+        ExpressionKind::Binary(left, op, right) => {
+            fold_binary(op, constant_value(left)?, constant_value(right)?)
+        }
+        ExpressionKind::Unary(UnaryOp::Neg, right) => Some(constant_value(right)?.wrapping_neg()),
+        ExpressionKind::Unary(UnaryOp::Not, right) => Some((constant_value(right)? == 0) as i32),
+        ExpressionKind::Unary(UnaryOp::BitNot, right) => Some(!constant_value(right)?),
+        // The arm that isn't taken never runs, so it doesn't matter what it is
+        ExpressionKind::Conditional(condition, then, otherwise) => {
+            constant_value(if constant_value(condition)? != 0 { then } else { otherwise })
+        }
+        ExpressionKind::Sequence(exprs) => exprs.iter().map(constant_value).collect::<Option<Vec<_>>>()?.pop(),
+        ExpressionKind::Call(callee, args) => builtin_value(Builtin::parse(&callee.name)?, args),
+        _ => None,
+    }
+}
+
+// Value of a builtin call with constant arguments, if they are also the right number of them
+fn builtin_value(builtin: Builtin, args: &[Expression]) -> Option<i32> {
+    let (min, max) = builtin.arity();
+    if args.len() < min || max.is_some_and(|max| args.len() > max) {
+        return None;
+    }
+    let values = args.iter().map(constant_value).collect::<Option<Vec<_>>>()?;
+    Some(builtin.evaluate(&values))
+}
+
+// `l op r` as wasm computes it, or None where it traps so that folding leaves the trap in place
+pub fn fold_binary(op: &BinaryOp, l: i32, r: i32) -> Option<i32> {
+    Some(match op {
+        BinaryOp::Add => l.wrapping_add(r),
+        BinaryOp::Sub => l.wrapping_sub(r),
+        BinaryOp::Mul => l.wrapping_mul(r),
+        // Dividing by zero traps, and so does the one quotient that overflows
+        BinaryOp::Div => l.checked_div(r)?,
+        // `i32.rem_s` only traps on zero: i32::MIN % -1 is 0
+        BinaryOp::Mod => if r != 0 { l.wrapping_rem(r) } else { return None },
+        BinaryOp::Pow => builtins::pow(l, r),
+        BinaryOp::Eq | BinaryOp::StrictEq => if l == r { 1 } else { 0 },
+        BinaryOp::Ne | BinaryOp::StrictNe => if l != r { 1 } else { 0 },
+        BinaryOp::Lt => if l < r { 1 } else { 0 },
+        BinaryOp::Gt => if l > r { 1 } else { 0 },
+        BinaryOp::Le => if l <= r { 1 } else { 0 },
+        BinaryOp::Ge => if l >= r { 1 } else { 0 },
+        BinaryOp::BitAnd => l & r,
+        BinaryOp::BitOr => l | r,
+        BinaryOp::BitXor => l ^ r,
+        // JavaScript and wasm both only use the low 5 bits of the shift count
+        BinaryOp::Shl => l.wrapping_shl(r as u32),
+        BinaryOp::Shr => l.wrapping_shr(r as u32),
+        BinaryOp::UShr => (l as u32).wrapping_shr(r as u32) as i32,
+    })
+}
+
+fn binary_instruction(op: &BinaryOp) -> Instruction {
+    match op {
+        BinaryOp::Add => Instruction::I32Add,
+        BinaryOp::Sub => Instruction::I32Sub,
+        BinaryOp::Mul => Instruction::I32Mul,
+        BinaryOp::Div => Instruction::I32DivS, // Signed division
+        BinaryOp::Mod => Instruction::I32RemS,
+        BinaryOp::Pow => unreachable!("`**` calls a runtime helper"),
+        BinaryOp::Eq | BinaryOp::StrictEq => Instruction::I32Eq,
+        BinaryOp::Ne | BinaryOp::StrictNe => Instruction::I32Ne,
+        BinaryOp::Lt => Instruction::I32LtS,
+        BinaryOp::Gt => Instruction::I32GtS,
+        BinaryOp::Le => Instruction::I32LeS,
+        BinaryOp::Ge => Instruction::I32GeS,
+        BinaryOp::BitAnd => Instruction::I32And,
+        BinaryOp::BitOr => Instruction::I32Or,
+        BinaryOp::BitXor => Instruction::I32Xor,
+        BinaryOp::Shl => Instruction::I32Shl,
+        BinaryOp::Shr => Instruction::I32ShrS,
+        BinaryOp::UShr => Instruction::I32ShrU,
+    }
 }
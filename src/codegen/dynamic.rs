@@ -0,0 +1,408 @@
+use super::CodeGenerator;
+use crate::ast::{BinaryOp, Expression, ExpressionKind, UnaryOp, UpdateOp};
+use crate::builtins::Builtin;
+use crate::runtime::Runtime;
+use crate::value::{self, Type};
+use crate::wasm::{Instruction, ValType};
+
+// Code generation for `--dynamic`, where every value is a tagged `i64` (see value.rs). Operators
+// work on what they need rather than on values: arithmetic on `f64`s, bitwise operators on `i32`s
+// and conditions on `i32` truth values, so `a + b * c` only boxes its result. Conversions go
+// through the runtime unless the type of the operand is known from its syntax.
+
+impl CodeGenerator {
+    // Registers `runtime` to be added to the module, and returns a call to it
+    pub(super) fn runtime_call(&mut self, runtime: Runtime) -> Instruction {
+        if !self.runtime.contains(&runtime) {
+            self.runtime.push(runtime);
+        }
+        Instruction::Call(runtime.name().to_string())
+    }
+
+    pub(super) fn call_runtime(&mut self, runtime: Runtime) {
+        let call = self.runtime_call(runtime);
+        self.emit(call);
+    }
+
+    // The type `expr` evaluates to, if it's the same every time
+    fn static_type(&self, expr: &Expression) -> Option<Type> {
+        self.types.of(expr, &self.analysis)
+    }
+
+    // Whether `left + right` is known to add numbers rather than concatenate strings
+    fn adds_numbers(&self, left: &Expression, right: &Expression) -> bool {
+        let number = |expr| self.static_type(expr).is_some_and(|ty| ty != Type::String);
+        number(left) && number(right)
+    }
+
+    // Leaves the tagged value of `expr`
+    pub(super) fn generate_value(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Number(n) => self.emit(Instruction::I64Const(value::number(*n))),
+            ExpressionKind::Boolean(b) => self.emit(Instruction::I64Const(value::boolean(*b))),
+            ExpressionKind::Null => self.emit(Instruction::I64Const(value::NULL)),
+            ExpressionKind::Undefined => self.emit(Instruction::I64Const(value::UNDEFINED)),
+            ExpressionKind::String(s) => {
+                let value = self.strings.value(s);
+                self.emit(Instruction::I64Const(value));
+            }
+            ExpressionKind::Identifier(_) => {
+                let wasm_name = self.wasm_name(expr.span);
+                self.emit(Instruction::LocalGet(wasm_name));
+            }
+            ExpressionKind::Unary(UnaryOp::Typeof, operand) => {
+                self.generate_value(operand);
+                match self.static_type(operand) {
+                    Some(ty) => {
+                        // Still evaluated for its side effects
+                        self.emit(Instruction::Drop);
+                        let name = self.strings.value(ty.name());
+                        self.emit(Instruction::I64Const(name));
+                    }
+                    None => self.call_runtime(Runtime::Typeof),
+                }
+            }
+            ExpressionKind::Assignment(target, value) => {
+                self.generate_value(value);
+                let wasm_name = self.wasm_name(target.span);
+                self.emit(Instruction::LocalTee(wasm_name));
+            }
+            // `x op= v` is `x = x op v`
+            ExpressionKind::CompoundAssignment(target, op, value) => {
+                let current = Expression::new(ExpressionKind::Identifier(target.name.clone()), target.span);
+                let kind = ExpressionKind::Binary(Box::new(current), op.clone(), value.clone());
+                self.generate_value(&Expression::new(kind, expr.span));
+                let wasm_name = self.wasm_name(target.span);
+                self.emit(Instruction::LocalTee(wasm_name));
+            }
+            // Unlike `x += 1`, `++x` always adds numbers, even to a string
+            ExpressionKind::Update { target, op, prefix: true } => {
+                let wasm_name = self.wasm_name(target.span);
+                self.generate_number(&Expression::new(ExpressionKind::Identifier(target.name.clone()), target.span));
+                self.emit(Instruction::F64Const(1.0));
+                self.emit(match op {
+                    UpdateOp::Increment => Instruction::F64Add,
+                    UpdateOp::Decrement => Instruction::F64Sub,
+                });
+                self.emit(Instruction::I64ReinterpretF64);
+                self.emit(Instruction::LocalTee(wasm_name));
+            }
+            // The old value, converted to a number
+            ExpressionKind::Update { target, op, prefix: false } => {
+                let wasm_name = self.wasm_name(target.span);
+                let old = self.new_temp("old", ValType::F64);
+                self.generate_number(&Expression::new(ExpressionKind::Identifier(target.name.clone()), target.span));
+                self.emit(Instruction::LocalTee(old.clone()));
+                self.emit(Instruction::F64Const(1.0));
+                self.emit(match op {
+                    UpdateOp::Increment => Instruction::F64Add,
+                    UpdateOp::Decrement => Instruction::F64Sub,
+                });
+                self.emit(Instruction::I64ReinterpretF64);
+                self.emit(Instruction::LocalSet(wasm_name));
+                self.emit(Instruction::LocalGet(old));
+                self.emit(Instruction::I64ReinterpretF64);
+            }
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                self.generate_test(condition);
+                let then_body = self.nested(|this| this.generate_value(then));
+                let else_body = self.nested(|this| this.generate_value(otherwise));
+                self.emit(Instruction::If { result: Some(ValType::I64), then_body, else_body: Some(else_body) });
+            }
+            ExpressionKind::Sequence(exprs) => {
+                let (last, rest) = exprs.split_last().expect("empty sequence");
+                for e in rest {
+                    self.generate_value(e);
+                    self.emit(Instruction::Drop);
+                }
+                self.generate_value(last);
+            }
+            ExpressionKind::Call(callee, args) if Builtin::parse(&callee.name).is_none() => {
+                // Sema only lets a mismatched argument count through under `loose_arity`
+                let params = self.params(callee);
+                for (i, arg) in args.iter().enumerate() {
+                    self.generate_value(arg);
+                    if i >= params {
+                        self.emit(Instruction::Drop); // Extra arguments are still evaluated
+                    }
+                }
+                for _ in args.len()..params {
+                    self.emit_undefined();
+                }
+                self.emit(Instruction::Call(callee.name.clone()));
+            }
+            // Concatenation, or `+` on values whose types aren't known
+            ExpressionKind::Binary(left, BinaryOp::Add, right) if !self.adds_numbers(left, right) => {
+                if self.static_type(left) == Some(Type::String) || self.static_type(right) == Some(Type::String) {
+                    self.generate_string(left);
+                    self.generate_string(right);
+                    self.call_runtime(Runtime::Concat);
+                } else {
+                    self.generate_value(left);
+                    self.generate_value(right);
+                    self.call_runtime(Runtime::Add);
+                }
+            }
+            // Everything else is a number or a boolean
+            _ => match self.static_type(expr) {
+                Some(Type::Boolean) => {
+                    self.generate_test(expr);
+                    self.emit(Instruction::I64ExtendI32U);
+                    self.emit(Instruction::I64Const(value::FALSE));
+                    self.emit(Instruction::I64Or);
+                }
+                _ => {
+                    self.generate_number(expr);
+                    self.emit(Instruction::I64ReinterpretF64);
+                }
+            },
+        }
+    }
+
+    // Leaves the string value `expr` converts to
+    fn generate_string(&mut self, expr: &Expression) {
+        self.generate_value(expr);
+        if self.static_type(expr) != Some(Type::String) {
+            self.call_runtime(Runtime::ToString);
+        }
+    }
+
+    // Leaves the number `expr` converts to as an `f64`
+    fn generate_number(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Number(n) => self.emit(Instruction::F64Const(*n)),
+            ExpressionKind::Binary(left, op, right)
+                if !is_comparison(op) && (*op != BinaryOp::Add || self.adds_numbers(left, right)) => match op {
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod | BinaryOp::Pow => {
+                    self.generate_number(left);
+                    self.generate_number(right);
+                    match op {
+                        BinaryOp::Add => self.emit(Instruction::F64Add),
+                        BinaryOp::Sub => self.emit(Instruction::F64Sub),
+                        BinaryOp::Mul => self.emit(Instruction::F64Mul),
+                        BinaryOp::Div => self.emit(Instruction::F64Div),
+                        BinaryOp::Mod => self.call_runtime(Runtime::Rem),
+                        _ => self.call_runtime(Runtime::Pow),
+                    }
+                }
+                // The only operator whose result is unsigned
+                BinaryOp::UShr => {
+                    self.generate_int32(expr);
+                    self.emit(Instruction::F64ConvertI32U);
+                }
+                _ => {
+                    self.generate_int32(expr);
+                    self.emit(Instruction::F64ConvertI32S);
+                }
+            },
+            ExpressionKind::Unary(UnaryOp::Neg, operand) => {
+                self.generate_number(operand);
+                self.emit(Instruction::F64Neg);
+            }
+            ExpressionKind::Unary(UnaryOp::BitNot, _) => {
+                self.generate_int32(expr);
+                self.emit(Instruction::F64ConvertI32S);
+            }
+            ExpressionKind::Call(callee, args) if let Some(builtin) = Builtin::parse(&callee.name) => {
+                self.generate_builtin_number(builtin, args);
+            }
+            _ => match self.static_type(expr) {
+                Some(Type::Boolean) => {
+                    self.generate_test(expr);
+                    self.emit(Instruction::F64ConvertI32U);
+                }
+                ty => {
+                    self.generate_value(expr);
+                    if ty == Some(Type::Number) {
+                        self.emit(Instruction::F64ReinterpretI64);
+                    } else {
+                        self.call_runtime(Runtime::ToNumber);
+                    }
+                }
+            },
+        }
+    }
+
+    // Leaves `expr` converted to a 32-bit integer, as the operands of bitwise operators are
+    fn generate_int32(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Number(n) => self.emit(Instruction::I32Const(to_int32(*n))),
+            ExpressionKind::Binary(left, op, right) if int32_instruction(op).is_some() => {
+                self.generate_int32(left);
+                self.generate_int32(right);
+                self.emit(int32_instruction(op).expect("bitwise operator"));
+            }
+            ExpressionKind::Unary(UnaryOp::BitNot, operand) => {
+                self.generate_int32(operand);
+                self.emit(Instruction::I32Const(-1));
+                self.emit(Instruction::I32Xor);
+            }
+            _ => {
+                self.generate_number(expr);
+                self.call_runtime(Runtime::ToInt32);
+            }
+        }
+    }
+
+    // Leaves 1 if `expr` is truthy and 0 if not
+    pub(super) fn generate_test(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Boolean(b) => self.emit(Instruction::I32Const(*b as i32)),
+            ExpressionKind::Unary(UnaryOp::Not, operand) => {
+                self.generate_test(operand);
+                self.emit(Instruction::I32Eqz);
+            }
+            ExpressionKind::Binary(left, op, right) if is_comparison(op) => {
+                let (left_type, right_type) = (self.static_type(left), self.static_type(right));
+                match op {
+                    BinaryOp::Eq | BinaryOp::Ne | BinaryOp::StrictEq | BinaryOp::StrictNe => {
+                        self.generate_equality(left, op, right);
+                    }
+                    // Two strings compare by their text, and everything else as numbers
+                    _ if left_type.is_none_or(|ty| ty == Type::String) && right_type.is_none_or(|ty| ty == Type::String) => {
+                        self.generate_value(left);
+                        self.generate_value(right);
+                        self.call_runtime(Runtime::Compare);
+                        // -1, 0, 1 or 2 for NaN
+                        let (bound, compare) = match op {
+                            BinaryOp::Lt => (0, Instruction::I32LtS),
+                            BinaryOp::Le => (0, Instruction::I32LeS),
+                            BinaryOp::Gt => (1, Instruction::I32Eq),
+                            _ => (2, Instruction::I32LtU),
+                        };
+                        self.emit(Instruction::I32Const(bound));
+                        self.emit(compare);
+                    }
+                    _ => {
+                        self.generate_number(left);
+                        self.generate_number(right);
+                        self.emit(match op {
+                            BinaryOp::Lt => Instruction::F64Lt,
+                            BinaryOp::Gt => Instruction::F64Gt,
+                            BinaryOp::Le => Instruction::F64Le,
+                            _ => Instruction::F64Ge,
+                        });
+                    }
+                }
+            }
+            _ => {
+                self.generate_value(expr);
+                if self.static_type(expr) == Some(Type::Boolean) {
+                    // The low bit is the boolean
+                    self.emit(Instruction::I32WrapI64);
+                } else {
+                    self.call_runtime(Runtime::Truthy);
+                }
+            }
+        }
+    }
+
+    // `==`, `!=`, `===` and `!==` as an i32, without the runtime when the types are known
+    fn generate_equality(&mut self, left: &Expression, op: &BinaryOp, right: &Expression) {
+        let strict = matches!(op, BinaryOp::StrictEq | BinaryOp::StrictNe);
+        let equal = matches!(op, BinaryOp::Eq | BinaryOp::StrictEq);
+        let nullish = |ty: Type| matches!(ty, Type::Null | Type::Undefined);
+        match (self.static_type(left), self.static_type(right)) {
+            // Values of different types are never `===`
+            (Some(a), Some(b)) if strict && a != b => {
+                self.generate_value(left);
+                self.generate_value(right);
+                self.emit(Instruction::Drop);
+                self.emit(Instruction::Drop);
+                self.emit(Instruction::I32Const(!equal as i32));
+            }
+            // Two numbers, or any other mix of numbers, booleans and strings, compare as numbers
+            (Some(a), Some(b)) if (a == Type::Number && b == Type::Number)
+                || (!strict && a != b && !nullish(a) && !nullish(b)) => {
+                self.generate_number(left);
+                self.generate_number(right);
+                self.emit(if equal { Instruction::F64Eq } else { Instruction::F64Ne });
+            }
+            _ => {
+                self.generate_value(left);
+                self.generate_value(right);
+                self.call_runtime(if strict { Runtime::StrictEquals } else { Runtime::LooseEquals });
+                if !equal {
+                    self.emit(Instruction::I32Eqz);
+                }
+            }
+        }
+    }
+
+    // `Math` functions on doubles. Unlike without --dynamic, `floor`, `sqrt` and friends are exact.
+    fn generate_builtin_number(&mut self, builtin: Builtin, args: &[Expression]) {
+        // `imul` and `clz32` work on 32-bit integers
+        let integers = matches!(builtin, Builtin::Imul | Builtin::Clz32);
+        let (min, max) = builtin.arity();
+        for (i, arg) in args.iter().enumerate() {
+            if max.is_some_and(|max| i >= max) {
+                self.generate_value(arg);
+                self.emit(Instruction::Drop);
+            } else if integers {
+                self.generate_int32(arg);
+            } else {
+                self.generate_number(arg);
+            }
+        }
+        // Missing arguments are undefined, which is NaN as a number and 0 as an integer. `min`
+        // and `max` only miss one when they have none, and then start from their identity.
+        for _ in args.len()..min {
+            self.emit(match builtin {
+                Builtin::Min => Instruction::F64Const(f64::INFINITY),
+                Builtin::Max => Instruction::F64Const(f64::NEG_INFINITY),
+                _ if integers => Instruction::I32Const(0),
+                _ => Instruction::F64Const(f64::NAN),
+            });
+        }
+        let count = args.len().clamp(min, max.unwrap_or(usize::MAX));
+
+        match builtin {
+            Builtin::Abs => self.emit(Instruction::F64Abs),
+            Builtin::Floor => self.emit(Instruction::F64Floor),
+            Builtin::Ceil => self.emit(Instruction::F64Ceil),
+            Builtin::Trunc => self.emit(Instruction::F64Trunc),
+            Builtin::Sqrt => self.emit(Instruction::F64Sqrt),
+            Builtin::Min | Builtin::Max => {
+                for _ in 1..count {
+                    self.emit(if builtin == Builtin::Min { Instruction::F64Min } else { Instruction::F64Max });
+                }
+            }
+            Builtin::Sign => self.call_runtime(Runtime::Sign),
+            Builtin::Pow => self.call_runtime(Runtime::Pow),
+            Builtin::Imul => {
+                self.emit(Instruction::I32Mul);
+                self.emit(Instruction::F64ConvertI32S);
+            }
+            Builtin::Clz32 => {
+                self.emit(Instruction::I32Clz);
+                self.emit(Instruction::F64ConvertI32S);
+            }
+        }
+    }
+}
+
+fn is_comparison(op: &BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::StrictEq | BinaryOp::StrictNe
+            | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge
+    )
+}
+
+// The `i32` instruction of a bitwise operator
+fn int32_instruction(op: &BinaryOp) -> Option<Instruction> {
+    Some(match op {
+        BinaryOp::BitAnd => Instruction::I32And,
+        BinaryOp::BitOr => Instruction::I32Or,
+        BinaryOp::BitXor => Instruction::I32Xor,
+        BinaryOp::Shl => Instruction::I32Shl,
+        BinaryOp::Shr => Instruction::I32ShrS,
+        BinaryOp::UShr => Instruction::I32ShrU,
+        _ => return None,
+    })
+}
+
+// ToInt32 of a literal: truncated and wrapped modulo 2^32, with NaN and the infinities as 0
+fn to_int32(n: f64) -> i32 {
+    if n.is_finite() { n.trunc().rem_euclid(4294967296.0) as u32 as i32 } else { 0 }
+}
@@ -0,0 +1,200 @@
+use crate::ast::{BinaryOp, Expression, ExpressionKind};
+use crate::builtins::Builtin;
+use crate::codegen::constant_value;
+
+// Common subexpression elimination within a single expression tree.
+//
+// Before codegen emits a statement's expression, `plan` walks it in evaluation
+// order and finds pure `Binary`/`Unary` subtrees and `Math` calls that are computed more than once.
+// The first occurrence is emitted normally followed by `local.tee $cse.N`; later
+// occurrences become `local.get $cse.N`. Calls and assignments (including `++`/`--`) are barriers: once
+// one has run, nothing computed before it may be reused.
+
+#[derive(Debug, Clone)]
+struct Entry {
+    expr: Expression,
+    temp: String,
+    available: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommonSubexpressions {
+    entries: Vec<Entry>,
+}
+
+impl CommonSubexpressions {
+    // Temporary holding `expr`, if it is shared. The bool tells whether it has been computed yet.
+    pub fn lookup(&self, expr: &Expression) -> Option<(String, bool)> {
+        self.entries.iter()
+            .find(|entry| entry.expr.same_as(expr))
+            .map(|entry| (entry.temp.clone(), entry.available))
+    }
+
+    pub fn mark_available(&mut self, expr: &Expression) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.expr.same_as(expr)) {
+            entry.available = true;
+        }
+    }
+
+    // Called after a barrier (call or assignment) has been emitted
+    pub fn invalidate(&mut self) {
+        for entry in &mut self.entries {
+            entry.available = false;
+        }
+    }
+
+    // Which temporaries hold their value at this point. Codegen takes one before each arm of a
+    // conditional, since only one of them runs.
+    pub fn snapshot(&self) -> Vec<bool> {
+        self.entries.iter().map(|entry| entry.available).collect()
+    }
+
+    pub fn restore(&mut self, snapshot: &[bool]) {
+        for (entry, available) in self.entries.iter_mut().zip(snapshot) {
+            entry.available = *available;
+        }
+    }
+
+    // After both arms: a temporary is only set if it was set on both paths
+    pub fn intersect(&mut self, snapshot: &[bool]) {
+        for (entry, available) in self.entries.iter_mut().zip(snapshot) {
+            entry.available &= *available;
+        }
+    }
+}
+
+// Decides which subexpressions of `root` get a temporary. `new_temp` names them.
+pub fn plan(root: &Expression, mut new_temp: impl FnMut() -> String) -> CommonSubexpressions {
+    let mut sim = Simulation::default();
+    sim.walk(root);
+
+    let entries = sim.seen.into_iter()
+        .filter(|(_, hits)| *hits > 0)
+        .map(|(expr, _)| Entry { expr, temp: new_temp(), available: false })
+        .collect();
+    CommonSubexpressions { entries }
+}
+
+// Replays codegen's evaluation order, counting how often each candidate could be reused
+#[derive(Default)]
+struct Simulation {
+    // (candidate, times it was found already computed)
+    seen: Vec<(Expression, usize)>,
+    available: Vec<Expression>,
+}
+
+impl Simulation {
+    fn walk(&mut self, expr: &Expression) {
+        let candidate = is_candidate(expr);
+        if candidate && self.available.iter().any(|e| e.same_as(expr)) {
+            if let Some((_, hits)) = self.seen.iter_mut().find(|(e, _)| e.same_as(expr)) {
+                *hits += 1;
+            }
+            return;
+        }
+
+        match &expr.kind {
+            ExpressionKind::Binary(left, _, right) => {
+                self.walk(left);
+                self.walk(right);
+            }
+            ExpressionKind::Unary(_, right) => self.walk(right),
+            ExpressionKind::Assignment(_, value) | ExpressionKind::CompoundAssignment(_, _, value) => {
+                self.walk(value);
+                self.available.clear();
+            }
+            ExpressionKind::Call(callee, args) => {
+                for arg in args {
+                    self.walk(arg);
+                }
+                if Builtin::parse(&callee.name).is_none() {
+                    self.available.clear();
+                }
+            }
+            ExpressionKind::Update { .. } => self.available.clear(),
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                if is_select(condition, then, otherwise) {
+                    // Lowered to `select`, which evaluates both arms before the condition
+                    self.walk(then);
+                    self.walk(otherwise);
+                    self.walk(condition);
+                } else {
+                    self.walk(condition);
+                    let before = self.available.clone();
+                    self.walk(then);
+                    let after_then = std::mem::replace(&mut self.available, before);
+                    self.walk(otherwise);
+                    self.available.retain(|e| after_then.iter().any(|t| t.same_as(e)));
+                }
+            }
+            ExpressionKind::Sequence(exprs) => {
+                for e in exprs {
+                    self.walk(e);
+                }
+            }
+            ExpressionKind::Identifier(_)
+                | ExpressionKind::Number(_)
+                | ExpressionKind::Boolean(_)
+                | ExpressionKind::Null
+                | ExpressionKind::Undefined
+                | ExpressionKind::String(_) => {}
+        }
+
+        if candidate {
+            if !self.seen.iter().any(|(e, _)| e.same_as(expr)) {
+                self.seen.push((expr.clone(), 0));
+            }
+            self.available.push(expr.clone());
+        }
+    }
+}
+
+// Operator trees with no side effects that would not be folded away anyway
+fn is_candidate(expr: &Expression) -> bool {
+    matches!(expr.kind, ExpressionKind::Binary(..) | ExpressionKind::Unary(..) | ExpressionKind::Call(..))
+        && is_pure(expr)
+        && constant_value(expr).is_none()
+}
+
+fn is_pure(expr: &Expression) -> bool {
+    match &expr.kind {
+        ExpressionKind::Identifier(_)
+            | ExpressionKind::Number(_)
+            | ExpressionKind::Boolean(_)
+            | ExpressionKind::Null
+            | ExpressionKind::Undefined
+            | ExpressionKind::String(_) => true,
+        ExpressionKind::Binary(left, _, right) => is_pure(left) && is_pure(right),
+        ExpressionKind::Unary(_, right) => is_pure(right),
+        ExpressionKind::Conditional(condition, then, otherwise) => is_pure(condition) && is_pure(then) && is_pure(otherwise),
+        ExpressionKind::Sequence(exprs) => exprs.iter().all(is_pure),
+        // Builtins have no side effects of their own
+        ExpressionKind::Call(callee, args) => Builtin::parse(&callee.name).is_some() && args.iter().all(is_pure),
+        ExpressionKind::Assignment(..)
+            | ExpressionKind::CompoundAssignment(..)
+            | ExpressionKind::Update { .. } => false,
+    }
+}
+
+// Whether `cond ? then : otherwise` can be lowered to `select`, which evaluates both arms and
+// then the condition. That is only invisible if nothing has side effects and the arms can't trap.
+pub fn is_select(condition: &Expression, then: &Expression, otherwise: &Expression) -> bool {
+    is_pure(condition) && can_speculate(then) && can_speculate(otherwise)
+}
+
+// Whether `expr` can run even when its value isn't wanted: no side effects, and no division,
+// which traps on zero
+fn can_speculate(expr: &Expression) -> bool {
+    match &expr.kind {
+        ExpressionKind::Binary(_, BinaryOp::Div | BinaryOp::Mod, _) => false,
+        ExpressionKind::Binary(left, _, right) => can_speculate(left) && can_speculate(right),
+        ExpressionKind::Unary(_, right) => can_speculate(right),
+        ExpressionKind::Conditional(condition, then, otherwise) => {
+            can_speculate(condition) && can_speculate(then) && can_speculate(otherwise)
+        }
+        ExpressionKind::Sequence(exprs) => exprs.iter().all(can_speculate),
+        // A builtin doesn't trap, but its arguments might
+        ExpressionKind::Call(_, args) => is_pure(expr) && args.iter().all(can_speculate),
+        _ => is_pure(expr),
+    }
+}
@@ -0,0 +1,41 @@
+use crate::wasm::{Instruction, Module};
+use std::collections::HashSet;
+
+// Unused function removal. Codegen emits every function in the program, but only `_start` is
+// exported, so a function that no call chain from an export reaches can never run and is
+// dropped along with its code. Runtime functions are only emitted when called, so this mostly
+// removes helpers in the source that nothing uses.
+
+pub fn remove_unused_functions(module: &mut Module) {
+    let mut reached: HashSet<String> = module.exports.iter().map(|(_, name)| name.clone()).collect();
+    let mut pending: Vec<String> = reached.iter().cloned().collect();
+    while let Some(name) = pending.pop() {
+        let Some(function) = module.functions.iter().find(|function| function.name == name) else {
+            continue;
+        };
+        let mut callees = Vec::new();
+        collect_calls(&function.body, &mut callees);
+        for callee in callees {
+            if reached.insert(callee.clone()) {
+                pending.push(callee);
+            }
+        }
+    }
+    module.functions.retain(|function| reached.contains(&function.name));
+}
+
+fn collect_calls(body: &[Instruction], out: &mut Vec<String>) {
+    for instr in body {
+        match instr {
+            Instruction::Call(name) => out.push(name.clone()),
+            Instruction::Block { body, .. } | Instruction::Loop { body, .. } => collect_calls(body, out),
+            Instruction::If { then_body, else_body, .. } => {
+                collect_calls(then_body, out);
+                if let Some(else_body) = else_body {
+                    collect_calls(else_body, out);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::dump::span_json;
+use crate::error::{CompileError, Fix, Label};
+use crate::json::Json;
+use crate::token::Span;
+
+// rustc-style rendering of errors against the source they came from:
+//
+//   error[E0201]: Assignment to constant variable 'c'
+//    --> input.js:2:1
+//     |
+//   1 | const c = 1;
+//     |       - 'c' declared as const here
+//   2 | c = 2;
+//     | ^^^^^ cannot assign to a constant
+//     |
+//     = help: declare 'c' with `let` if it needs to change
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Option<Span>,
+    pub label: Option<String>,
+    pub related: Vec<Label>,
+    pub help: Vec<String>,
+    pub fixes: Vec<Fix>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: String, span: Span) -> Self {
+        Diagnostic {
+            severity,
+            code: None,
+            message,
+            span: Some(span),
+            label: None,
+            related: Vec::new(),
+            help: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+
+    pub fn with_related(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.related.push(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_help(mut self, message: impl Into<String>) -> Self {
+        self.help.push(message.into());
+        self
+    }
+
+    pub fn with_fix(mut self, message: impl Into<String>, span: Span, replacement: impl Into<String>) -> Self {
+        self.fixes.push(Fix { message: message.into(), span, replacement: replacement.into() });
+        self
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(err: &CompileError) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: err.code,
+            message: err.message.clone(),
+            span: err.span,
+            label: err.label.clone(),
+            related: err.related.clone(),
+            help: err.help.clone(),
+            fixes: err.fixes.clone(),
+        }
+    }
+}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+struct Painter {
+    color: bool,
+}
+
+impl Painter {
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color && !text.is_empty() {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+// One underline to draw beneath a source line
+struct Underline<'a> {
+    span: Span,
+    message: &'a str,
+    primary: bool,
+}
+
+pub fn render(diagnostic: &Diagnostic, source: &str, file: &str, color: bool) -> String {
+    let p = Painter { color };
+    let severity = diagnostic.severity;
+    let header = match diagnostic.code {
+        Some(code) => format!("{}[{}]:", severity.name(), code),
+        None => format!("{}:", severity.name()),
+    };
+    let mut out = format!(
+        "{}{}\n",
+        p.paint(severity.color(), &header),
+        p.paint(BOLD, &format!(" {}", diagnostic.message)),
+    );
+
+    let Some(span) = diagnostic.span else {
+        for help in &diagnostic.help {
+            out.push_str(&format!("{} help: {}\n", p.paint(BLUE, "="), help));
+        }
+        return out;
+    };
+
+    let mut underlines = vec![Underline { span, message: diagnostic.label.as_deref().unwrap_or(""), primary: true }];
+    for label in &diagnostic.related {
+        underlines.push(Underline { span: label.span, message: &label.message, primary: false });
+    }
+    underlines.sort_by_key(|u| (u.span.line, u.span.column));
+
+    let lines: Vec<&str> = source.lines().collect();
+    let last_line = underlines.iter().map(|u| u.span.line).max().unwrap_or(1);
+    let width = last_line.to_string().len();
+    let gutter = |number: &str| p.paint(BLUE, &format!("{:>width$} |", number));
+
+    out.push_str(&format!(
+        "{}{} {}:{}:{}\n",
+        " ".repeat(width),
+        p.paint(BLUE, "-->"),
+        file,
+        span.line,
+        span.column,
+    ));
+    out.push_str(&format!("{}\n", gutter("")));
+
+    let mut previous: Option<usize> = None;
+    for underline in &underlines {
+        let number = underline.span.line;
+        let text = lines.get(number.wrapping_sub(1)).copied().unwrap_or("");
+
+        if previous != Some(number) {
+            // Show a single skipped line, elide longer gaps
+            match previous {
+                Some(prev) if number == prev + 2 => {
+                    out.push_str(&format!("{} {}\n", gutter(&(prev + 1).to_string()), lines[prev]));
+                }
+                Some(prev) if number > prev + 2 => out.push_str(&format!("{}\n", p.paint(BLUE, "..."))),
+                _ => {}
+            }
+            out.push_str(&format!("{} {}\n", gutter(&number.to_string()), text));
+        }
+        previous = Some(number);
+
+        // Keep tabs so the marker lines up with the source above it
+        let indent: String = text.chars()
+            .take(underline.span.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let length = if underline.span.end_line == number {
+            underline.span.end_column.saturating_sub(underline.span.column)
+        } else {
+            text.chars().count().saturating_sub(underline.span.column - 1)
+        };
+        let (marker, style) = if underline.primary { ('^', severity.color()) } else { ('-', BLUE) };
+        let marks = format!("{} {}", marker.to_string().repeat(length.max(1)), underline.message);
+        out.push_str(&format!("{} {}{}\n", gutter(""), indent, p.paint(style, marks.trim_end())));
+    }
+
+    if !diagnostic.help.is_empty() {
+        out.push_str(&format!("{}\n", gutter("")));
+        for help in &diagnostic.help {
+            out.push_str(&format!("{} {} help: {}\n", " ".repeat(width), p.paint(BLUE, "="), help));
+        }
+    }
+    out
+}
+
+// One diagnostic as a JSON object, for `--error-format json`. The schema is documented in the README.
+pub fn to_json(diagnostic: &Diagnostic, file: &str) -> Json {
+    Json::object(vec![
+        ("code", diagnostic.code.map_or(Json::Null, Json::string)),
+        ("severity", diagnostic.severity.name().into()),
+        ("message", diagnostic.message.as_str().into()),
+        ("file", file.into()),
+        ("span", diagnostic.span.as_ref().map_or(Json::Null, span_json)),
+        ("label", diagnostic.label.as_deref().map_or(Json::Null, Json::string)),
+        ("related", Json::Array(diagnostic.related.iter().map(|label| Json::object(vec![
+            ("message", label.message.as_str().into()),
+            ("span", span_json(&label.span)),
+        ])).collect())),
+        ("help", Json::Array(diagnostic.help.iter().map(|help| help.as_str().into()).collect())),
+        ("fixes", Json::Array(diagnostic.fixes.iter().map(|fix| Json::object(vec![
+            ("message", fix.message.as_str().into()),
+            ("span", span_json(&fix.span)),
+            ("replacement", fix.replacement.as_str().into()),
+        ])).collect())),
+    ])
+}
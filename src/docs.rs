@@ -0,0 +1,19 @@
+use crate::ast::{Program, StatementKind};
+
+// Markdown reference for `--emit docs`: one section per top-level function, with the text of
+// the `/** ... */` comment in front of it.
+pub fn render(program: &Program) -> String {
+    let mut out = String::new();
+    for stmt in &program.body {
+        let StatementKind::FunctionDeclaration { name, params, .. } = &stmt.kind else { continue };
+        let params: Vec<&str> = params.iter().map(|p| p.name.as_str()).collect();
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("## `{}({})`\n", name.name, params.join(", ")));
+        if let Some(doc) = stmt.doc().filter(|doc| !doc.is_empty()) {
+            out.push_str(&format!("\n{}\n", doc));
+        }
+    }
+    out
+}
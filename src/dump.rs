@@ -0,0 +1,347 @@
+use crate::ast::{BinaryOp, Expression, ExpressionKind, Ident, Program, Statement, StatementKind, UnaryOp, UpdateOp};
+use crate::json::Json;
+use crate::token::{Span, SpannedToken};
+
+// Debug views of the front end, used by `--emit tokens`, `--emit ast` and `--emit ast-json`.
+
+// One token per line, prefixed with its line:column
+pub fn tokens(tokens: &[SpannedToken]) -> String {
+    let mut out = String::new();
+    for t in tokens {
+        let position = format!("{}:{}", t.span.line, t.span.column);
+        out.push_str(&format!("{:<8}{:?}\n", position, t.token));
+    }
+    out
+}
+
+// Indented tree, one node per line
+pub fn ast(program: &Program) -> String {
+    let mut out = String::from("Program\n");
+    for stmt in &program.body {
+        write_statement(&mut out, stmt, 1);
+    }
+    out
+}
+
+fn line(out: &mut String, depth: usize, text: &str) {
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(text);
+    out.push('\n');
+}
+
+fn write_statement(out: &mut String, stmt: &Statement, depth: usize) {
+    match &stmt.kind {
+        StatementKind::VariableDeclaration { name, init, is_const } => {
+            let keyword = if *is_const { "const" } else { "let" };
+            line(out, depth, &format!("VariableDeclaration {} {}", keyword, name.name));
+            write_expression(out, init, depth + 1);
+        }
+        StatementKind::FunctionDeclaration { name, params, body } => {
+            line(out, depth, &format!("FunctionDeclaration {}({})", name.name, names(params).join(", ")));
+            for s in body {
+                write_statement(out, s, depth + 1);
+            }
+        }
+        StatementKind::If { condition, then_branch, else_branch } => {
+            line(out, depth, "If");
+            write_expression(out, condition, depth + 1);
+            write_statement(out, then_branch, depth + 1);
+            if let Some(else_branch) = else_branch {
+                line(out, depth, "Else");
+                write_statement(out, else_branch, depth + 1);
+            }
+        }
+        StatementKind::While { condition, body } => {
+            line(out, depth, "While");
+            write_expression(out, condition, depth + 1);
+            write_statement(out, body, depth + 1);
+        }
+        StatementKind::DoWhile { body, condition } => {
+            line(out, depth, "DoWhile");
+            write_statement(out, body, depth + 1);
+            write_expression(out, condition, depth + 1);
+        }
+        StatementKind::Switch { discriminant, cases } => {
+            line(out, depth, "Switch");
+            write_expression(out, discriminant, depth + 1);
+            for case in cases {
+                match &case.test {
+                    Some(test) => {
+                        line(out, depth + 1, "Case");
+                        write_expression(out, test, depth + 2);
+                    }
+                    None => line(out, depth + 1, "Default"),
+                }
+                for s in &case.body {
+                    write_statement(out, s, depth + 2);
+                }
+            }
+        }
+        StatementKind::Labeled { label, body } => {
+            line(out, depth, &format!("Labeled {}", label.name));
+            write_statement(out, body, depth + 1);
+        }
+        StatementKind::Break(label) => line(out, depth, &jump("Break", label)),
+        StatementKind::Continue(label) => line(out, depth, &jump("Continue", label)),
+        StatementKind::Return(value) => {
+            line(out, depth, "Return");
+            if let Some(value) = value {
+                write_expression(out, value, depth + 1);
+            }
+        }
+        StatementKind::Block(stmts) => {
+            line(out, depth, "Block");
+            for s in stmts {
+                write_statement(out, s, depth + 1);
+            }
+        }
+        StatementKind::Expression(expr) => {
+            line(out, depth, "ExpressionStatement");
+            write_expression(out, expr, depth + 1);
+        }
+    }
+}
+
+fn jump(keyword: &str, label: &Option<Ident>) -> String {
+    match label {
+        Some(label) => format!("{} {}", keyword, label.name),
+        None => keyword.to_string(),
+    }
+}
+
+fn write_expression(out: &mut String, expr: &Expression, depth: usize) {
+    match &expr.kind {
+        ExpressionKind::Identifier(name) => line(out, depth, &format!("Identifier {}", name)),
+        ExpressionKind::Number(n) => line(out, depth, &format!("Number {}", n)),
+        ExpressionKind::Boolean(b) => line(out, depth, &format!("Boolean {}", b)),
+        ExpressionKind::Null => line(out, depth, "Null"),
+        ExpressionKind::Undefined => line(out, depth, "Undefined"),
+        ExpressionKind::String(s) => line(out, depth, &format!("String {:?}", s)),
+        ExpressionKind::Binary(left, op, right) => {
+            line(out, depth, &format!("Binary {}", binary_op(op)));
+            write_expression(out, left, depth + 1);
+            write_expression(out, right, depth + 1);
+        }
+        ExpressionKind::Unary(op, right) => {
+            line(out, depth, &format!("Unary {}", unary_op(op)));
+            write_expression(out, right, depth + 1);
+        }
+        ExpressionKind::Call(callee, args) => {
+            line(out, depth, &format!("Call {}", callee.name));
+            for arg in args {
+                write_expression(out, arg, depth + 1);
+            }
+        }
+        ExpressionKind::Assignment(target, value) => {
+            line(out, depth, &format!("Assignment {}", target.name));
+            write_expression(out, value, depth + 1);
+        }
+        ExpressionKind::CompoundAssignment(target, op, value) => {
+            line(out, depth, &format!("CompoundAssignment {} {}=", target.name, binary_op(op)));
+            write_expression(out, value, depth + 1);
+        }
+        ExpressionKind::Update { target, op, prefix: true } => line(out, depth, &format!("Update {}{}", update_op(op), target.name)),
+        ExpressionKind::Update { target, op, prefix: false } => line(out, depth, &format!("Update {}{}", target.name, update_op(op))),
+        ExpressionKind::Conditional(condition, then, otherwise) => {
+            line(out, depth, "Conditional");
+            write_expression(out, condition, depth + 1);
+            write_expression(out, then, depth + 1);
+            write_expression(out, otherwise, depth + 1);
+        }
+        ExpressionKind::Sequence(exprs) => {
+            line(out, depth, "Sequence");
+            for e in exprs {
+                write_expression(out, e, depth + 1);
+            }
+        }
+    }
+}
+
+pub fn binary_op(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Mod => "%",
+        BinaryOp::Pow => "**",
+        BinaryOp::Eq => "==",
+        BinaryOp::Ne => "!=",
+        BinaryOp::StrictEq => "===",
+        BinaryOp::StrictNe => "!==",
+        BinaryOp::Lt => "<",
+        BinaryOp::Gt => ">",
+        BinaryOp::Le => "<=",
+        BinaryOp::Ge => ">=",
+        BinaryOp::BitAnd => "&",
+        BinaryOp::BitOr => "|",
+        BinaryOp::BitXor => "^",
+        BinaryOp::Shl => "<<",
+        BinaryOp::Shr => ">>",
+        BinaryOp::UShr => ">>>",
+    }
+}
+
+pub fn unary_op(op: &UnaryOp) -> &'static str {
+    match op {
+        UnaryOp::Not => "!",
+        UnaryOp::Neg => "-",
+        UnaryOp::BitNot => "~",
+        UnaryOp::Typeof => "typeof",
+    }
+}
+
+pub fn update_op(op: &UpdateOp) -> &'static str {
+    match op {
+        UpdateOp::Increment => "++",
+        UpdateOp::Decrement => "--",
+    }
+}
+
+fn names(idents: &[Ident]) -> Vec<&str> {
+    idents.iter().map(|i| i.name.as_str()).collect()
+}
+
+// JSON form. Every node is an object with a "type" field named after the AST variant
+// and a "span" giving its source range.
+pub fn ast_json(program: &Program) -> Json {
+    Json::object(vec![
+        ("type", "Program".into()),
+        ("body", Json::Array(program.body.iter().map(statement_json).collect())),
+    ])
+}
+
+pub fn span_json(span: &Span) -> Json {
+    Json::object(vec![
+        ("line", span.line.into()),
+        ("column", span.column.into()),
+        ("endLine", span.end_line.into()),
+        ("endColumn", span.end_column.into()),
+        ("start", span.start.into()),
+        ("end", span.end.into()),
+    ])
+}
+
+fn ident_json(ident: &Ident) -> Json {
+    Json::object(vec![
+        ("type", "Identifier".into()),
+        ("name", ident.name.as_str().into()),
+        ("span", span_json(&ident.span)),
+    ])
+}
+
+fn node(kind: &str, span: &Span, mut fields: Vec<(&str, Json)>) -> Json {
+    fields.insert(0, ("type", kind.into()));
+    fields.push(("span", span_json(span)));
+    Json::object(fields)
+}
+
+fn statement_json(stmt: &Statement) -> Json {
+    let span = &stmt.span;
+    match &stmt.kind {
+        StatementKind::VariableDeclaration { name, init, is_const } => node("VariableDeclaration", span, vec![
+            ("name", ident_json(name)),
+            ("isConst", (*is_const).into()),
+            ("init", expression_json(init)),
+        ]),
+        StatementKind::FunctionDeclaration { name, params, body } => node("FunctionDeclaration", span, vec![
+            ("name", ident_json(name)),
+            ("params", Json::Array(params.iter().map(ident_json).collect())),
+            ("body", Json::Array(body.iter().map(statement_json).collect())),
+        ]),
+        StatementKind::If { condition, then_branch, else_branch } => node("If", span, vec![
+            ("condition", expression_json(condition)),
+            ("then", statement_json(then_branch)),
+            ("else", else_branch.as_deref().map_or(Json::Null, statement_json)),
+        ]),
+        StatementKind::While { condition, body } => node("While", span, vec![
+            ("condition", expression_json(condition)),
+            ("body", statement_json(body)),
+        ]),
+        StatementKind::DoWhile { body, condition } => node("DoWhile", span, vec![
+            ("body", statement_json(body)),
+            ("condition", expression_json(condition)),
+        ]),
+        StatementKind::Switch { discriminant, cases } => node("Switch", span, vec![
+            ("discriminant", expression_json(discriminant)),
+            ("cases", Json::Array(cases.iter().map(|case| node("SwitchCase", &case.span, vec![
+                ("test", case.test.as_ref().map_or(Json::Null, expression_json)),
+                ("body", Json::Array(case.body.iter().map(statement_json).collect())),
+            ])).collect())),
+        ]),
+        StatementKind::Labeled { label, body } => node("Labeled", span, vec![
+            ("label", ident_json(label)),
+            ("body", statement_json(body)),
+        ]),
+        StatementKind::Break(label) => node("Break", span, vec![
+            ("label", label.as_ref().map_or(Json::Null, ident_json)),
+        ]),
+        StatementKind::Continue(label) => node("Continue", span, vec![
+            ("label", label.as_ref().map_or(Json::Null, ident_json)),
+        ]),
+        StatementKind::Return(value) => node("Return", span, vec![
+            ("value", value.as_ref().map_or(Json::Null, expression_json)),
+        ]),
+        StatementKind::Block(stmts) => node("Block", span, vec![
+            ("body", Json::Array(stmts.iter().map(statement_json).collect())),
+        ]),
+        StatementKind::Expression(expr) => node("ExpressionStatement", span, vec![
+            ("expression", expression_json(expr)),
+        ]),
+    }
+}
+
+fn expression_json(expr: &Expression) -> Json {
+    let span = &expr.span;
+    match &expr.kind {
+        ExpressionKind::Identifier(name) => node("Identifier", span, vec![
+            ("name", name.as_str().into()),
+        ]),
+        ExpressionKind::Number(n) => node("Number", span, vec![
+            ("value", (*n).into()),
+        ]),
+        ExpressionKind::Boolean(b) => node("Boolean", span, vec![
+            ("value", (*b).into()),
+        ]),
+        ExpressionKind::Null => node("Null", span, vec![]),
+        ExpressionKind::Undefined => node("Undefined", span, vec![]),
+        ExpressionKind::String(s) => node("String", span, vec![
+            ("value", s.as_str().into()),
+        ]),
+        ExpressionKind::Binary(left, op, right) => node("Binary", span, vec![
+            ("operator", binary_op(op).into()),
+            ("left", expression_json(left)),
+            ("right", expression_json(right)),
+        ]),
+        ExpressionKind::Unary(op, right) => node("Unary", span, vec![
+            ("operator", unary_op(op).into()),
+            ("argument", expression_json(right)),
+        ]),
+        ExpressionKind::Call(callee, args) => node("Call", span, vec![
+            ("callee", ident_json(callee)),
+            ("arguments", Json::Array(args.iter().map(expression_json).collect())),
+        ]),
+        ExpressionKind::Assignment(target, value) => node("Assignment", span, vec![
+            ("target", ident_json(target)),
+            ("value", expression_json(value)),
+        ]),
+        ExpressionKind::CompoundAssignment(target, op, value) => node("CompoundAssignment", span, vec![
+            ("operator", format!("{}=", binary_op(op)).as_str().into()),
+            ("target", ident_json(target)),
+            ("value", expression_json(value)),
+        ]),
+        ExpressionKind::Update { target, op, prefix } => node("Update", span, vec![
+            ("operator", update_op(op).into()),
+            ("prefix", (*prefix).into()),
+            ("target", ident_json(target)),
+        ]),
+        ExpressionKind::Conditional(condition, then, otherwise) => node("Conditional", span, vec![
+            ("test", expression_json(condition)),
+            ("consequent", expression_json(then)),
+            ("alternate", expression_json(otherwise)),
+        ]),
+        ExpressionKind::Sequence(exprs) => node("Sequence", span, vec![
+            ("expressions", Json::Array(exprs.iter().map(expression_json).collect())),
+        ]),
+    }
+}
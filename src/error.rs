@@ -0,0 +1,96 @@
+use crate::token::Span;
+use std::fmt;
+
+// Which stage of the pipeline rejected the program. Each maps to its own process exit code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Lexer,
+    Parser,
+    Semantic,
+    // A later stage found something the earlier ones should have ruled out: a compiler bug
+    Internal,
+}
+
+impl ErrorKind {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorKind::Lexer => 2,
+            ErrorKind::Parser => 3,
+            ErrorKind::Semantic => 4,
+            ErrorKind::Internal => 7,
+        }
+    }
+}
+
+// A secondary location shown alongside an error, e.g. where a const was declared
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+// A machine-applicable edit that would fix the error: replace `span` with `replacement`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fix {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub kind: ErrorKind,
+    // Stable identifier such as "E0201", listed in the README
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Option<Span>,
+    // Short text shown under the primary span
+    pub label: Option<String>,
+    pub related: Vec<Label>,
+    pub help: Vec<String>,
+    pub fixes: Vec<Fix>,
+}
+
+impl CompileError {
+    pub fn new(kind: ErrorKind, message: String, span: Option<Span>) -> Self {
+        CompileError { kind, code: None, message, span, label: None, related: Vec::new(), help: Vec::new(), fixes: Vec::new() }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_label(mut self, message: impl Into<String>) -> Self {
+        self.label = Some(message.into());
+        self
+    }
+
+    pub fn with_related(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.related.push(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_help(mut self, message: impl Into<String>) -> Self {
+        self.help.push(message.into());
+        self
+    }
+
+    pub fn with_fix(mut self, message: impl Into<String>, span: Span, replacement: impl Into<String>) -> Self {
+        self.fixes.push(Fix { message: message.into(), span, replacement: replacement.into() });
+        self
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "Error at line {}, column {}: {}", span.line, span.column, self.message),
+            None => write!(f, "Error: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+pub type Result<T> = std::result::Result<T, CompileError>;
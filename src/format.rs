@@ -0,0 +1,382 @@
+use crate::ast::{BinaryOp, Expression, ExpressionKind, Ident, Program, Statement, StatementKind, SwitchCase, UnaryOp};
+use crate::dump::{binary_op, unary_op, update_op};
+use crate::lexer::Lexer;
+use crate::token::{Span, Token, Trivia};
+
+// Pretty-printer for `--fmt`. Renders the AST back to source with two-space indentation, as in
+// programs/, braces on the same line and parentheses only where precedence needs them.
+//
+// Comments come from the lexer's trivia and are placed by position: a comment is printed before
+// the first statement that starts after it, or at the end of the line if it trails a statement.
+// Expressions are printed on one line, so a comment inside one moves after its statement.
+// Runs of blank lines between statements are collapsed to one.
+
+const INDENT: &str = "  ";
+
+// `source` is what `program` was parsed from, for spelling number literals the way they were written
+pub fn format(program: &Program, comments: &[Trivia], source: &str) -> String {
+    let mut f = Formatter { source, out: String::new(), depth: 0, comments, next_comment: 0, last_line: 0, block_start: true, block_end: usize::MAX };
+    for stmt in &program.body {
+        f.statement(stmt);
+    }
+    f.comments_before(usize::MAX);
+    f.out
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    out: String,
+    depth: usize,
+    comments: &'a [Trivia],
+    // First comment not printed yet
+    next_comment: usize,
+    // Source line of the last thing printed, to carry blank lines over
+    last_line: usize,
+    // Nothing printed yet since the opening `{`, so no blank line is wanted
+    block_start: bool,
+    // Offset of the enclosing block's `}`. Comments after it belong outside the block.
+    block_end: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn indent(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    // Keep a blank line if the source had one before `line`
+    fn separate(&mut self, line: usize) {
+        if !self.block_start && line > self.last_line + 1 {
+            self.out.push('\n');
+        }
+    }
+
+    // Prints the comments that start before byte offset `offset`, each on its own line
+    fn comments_before(&mut self, offset: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment).filter(|c| c.span.start < offset) {
+            self.separate(comment.span.line);
+            self.indent();
+            self.out.push_str(comment.text.trim_end());
+            self.out.push('\n');
+            // A comment moved out of the statement before it mustn't open a gap after itself
+            self.last_line = self.last_line.max(comment.span.end_line);
+            self.block_start = false;
+            self.next_comment += 1;
+        }
+    }
+
+    fn trailing_comment(&self, line: usize) -> Option<&'a Trivia> {
+        self.comments.get(self.next_comment).filter(|c| c.span.line == line && c.span.start < self.block_end)
+    }
+
+    // Ends the current line, pulling up comments that follow on the same source line
+    fn end_line(&mut self, mut line: usize) {
+        while let Some(comment) = self.trailing_comment(line) {
+            self.out.push(' ');
+            self.out.push_str(comment.text.trim_end());
+            self.next_comment += 1;
+            line = comment.span.end_line;
+        }
+        self.out.push('\n');
+        self.last_line = line;
+        self.block_start = false;
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        self.comments_before(stmt.span.start);
+        self.separate(stmt.span.line);
+        self.indent();
+        let ended = self.statement_kind(stmt);
+        if !ended {
+            self.end_line(stmt.span.end_line);
+        }
+    }
+
+    // The statement itself, from the current position. Returns whether the line has been ended.
+    fn statement_kind(&mut self, stmt: &Statement) -> bool {
+        match &stmt.kind {
+            StatementKind::VariableDeclaration { name, init, is_const } => {
+                let keyword = if *is_const { "const" } else { "let" };
+                self.out.push_str(&format!("{} {} = {};", keyword, name.name, self.operand(init, 1)));
+                false
+            }
+            StatementKind::FunctionDeclaration { name, params, body } => {
+                let params: Vec<&str> = params.iter().map(|p| p.name.as_str()).collect();
+                self.out.push_str(&format!("function {}({}) ", name.name, params.join(", ")));
+                self.block(body, stmt.span);
+                false
+            }
+            StatementKind::If { .. } => self.if_statement(stmt),
+            StatementKind::While { condition, body } => {
+                self.out.push_str(&format!("while ({})", self.expression(condition)));
+                self.body(body)
+            }
+            StatementKind::DoWhile { body, condition } => {
+                self.out.push_str("do");
+                if self.body(body) {
+                    self.indent();
+                } else {
+                    self.out.push(' ');
+                }
+                self.out.push_str(&format!("while ({});", self.expression(condition)));
+                false
+            }
+            StatementKind::Switch { discriminant, cases } => {
+                self.out.push_str(&format!("switch ({}) ", self.expression(discriminant)));
+                self.switch_cases(cases, stmt.span);
+                false
+            }
+            // The label goes on the same line as what it labels
+            StatementKind::Labeled { label, body } => {
+                self.out.push_str(&format!("{}: ", label.name));
+                self.statement_kind(body)
+            }
+            StatementKind::Break(label) => {
+                self.out.push_str(&jump("break", label));
+                false
+            }
+            StatementKind::Continue(label) => {
+                self.out.push_str(&jump("continue", label));
+                false
+            }
+            StatementKind::Return(value) => {
+                match value {
+                    Some(value) => self.out.push_str(&format!("return {};", self.expression(value))),
+                    None => self.out.push_str("return;"),
+                }
+                false
+            }
+            StatementKind::Block(stmts) => {
+                self.block(stmts, stmt.span);
+                false
+            }
+            StatementKind::Expression(expr) => {
+                self.out.push_str(&format!("{};", self.expression(expr)));
+                false
+            }
+        }
+    }
+
+    // `{ case ...: ... }` with each case's statements indented one more level than the case
+    fn switch_cases(&mut self, cases: &[SwitchCase], span: Span) {
+        let has_comments = self.comments.get(self.next_comment).is_some_and(|c| c.span.start < span.end);
+        if cases.is_empty() && !has_comments {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.depth += 1;
+        self.block_start = true;
+        let outer_end = std::mem::replace(&mut self.block_end, span.end - 1);
+        for case in cases {
+            self.comments_before(case.span.start);
+            self.separate(case.span.line);
+            self.indent();
+            let line = match &case.test {
+                Some(test) => {
+                    self.out.push_str(&format!("case {}:", self.expression(test)));
+                    test.span.end_line
+                }
+                None => {
+                    self.out.push_str("default:");
+                    case.span.line
+                }
+            };
+            // A comment after a statement on the same line as the `case` stays with the statement
+            let limit = case.body.first().map_or(self.block_end, |stmt| stmt.span.start);
+            let block_end = std::mem::replace(&mut self.block_end, limit);
+            self.end_line(line);
+            self.block_end = block_end;
+            self.depth += 1;
+            self.block_start = true;
+            for stmt in &case.body {
+                self.statement(stmt);
+            }
+            self.depth -= 1;
+        }
+        self.block_end = outer_end;
+        self.comments_before(span.end);
+        self.depth -= 1;
+        self.indent();
+        self.out.push('}');
+    }
+
+    // `{ ... }`, leaving the line open after the closing brace. `span` ends at that brace.
+    fn block(&mut self, stmts: &[Statement], span: Span) {
+        let has_comments = self.comments.get(self.next_comment).is_some_and(|c| c.span.start < span.end);
+        if stmts.is_empty() && !has_comments {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.depth += 1;
+        self.block_start = true;
+        let outer_end = std::mem::replace(&mut self.block_end, span.end - 1);
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+        self.block_end = outer_end;
+        self.comments_before(span.end);
+        self.depth -= 1;
+        self.indent();
+        self.out.push('}');
+    }
+
+    // The body of an `if` or `while`. A block stays on the header line; anything else goes on
+    // its own indented line. Returns whether the line has already been ended.
+    fn body(&mut self, body: &Statement) -> bool {
+        if let StatementKind::Block(stmts) = &body.kind {
+            self.out.push(' ');
+            self.block(stmts, body.span);
+            false
+        } else {
+            self.out.push('\n');
+            self.depth += 1;
+            self.block_start = true;
+            self.statement(body);
+            self.depth -= 1;
+            true
+        }
+    }
+
+    fn if_statement(&mut self, stmt: &Statement) -> bool {
+        let StatementKind::If { condition, then_branch, else_branch } = &stmt.kind else { unreachable!() };
+        self.out.push_str(&format!("if ({})", self.expression(condition)));
+        let mut ended = self.body(then_branch);
+        let Some(else_branch) = else_branch else { return ended };
+
+        // A comment after the `}` stays there, which pushes the `else` onto the next line
+        if !ended && self.trailing_comment(then_branch.span.end_line).is_some() {
+            self.end_line(then_branch.span.end_line);
+            ended = true;
+        }
+
+        if ended {
+            self.indent();
+        } else {
+            self.out.push(' ');
+        }
+        self.out.push_str("else");
+        if let StatementKind::If { .. } = else_branch.kind {
+            self.out.push(' ');
+            self.if_statement(else_branch)
+        } else {
+            self.body(else_branch)
+        }
+    }
+}
+
+fn jump(keyword: &str, label: &Option<Ident>) -> String {
+    match label {
+        Some(label) => format!("{} {};", keyword, label.name),
+        None => format!("{};", keyword),
+    }
+}
+
+// Binding strength, loosest first, matching the parser's precedence climbing
+fn precedence(expr: &Expression) -> u8 {
+    match &expr.kind {
+        ExpressionKind::Sequence(_) => 0,
+        ExpressionKind::Assignment(..) | ExpressionKind::CompoundAssignment(..) => 1,
+        ExpressionKind::Conditional(..) => 2,
+        ExpressionKind::Binary(_, op, _) => match op {
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::StrictEq | BinaryOp::StrictNe => 6,
+            BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge => 7,
+            BinaryOp::Shl | BinaryOp::Shr | BinaryOp::UShr => 8,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 10,
+            BinaryOp::Pow => 11,
+        },
+        // `-2147483648` is a single literal but reads as a negation
+        ExpressionKind::Number(n) if *n < 0.0 => 12,
+        ExpressionKind::Unary(..) | ExpressionKind::Update { prefix: true, .. } => 12,
+        ExpressionKind::Update { prefix: false, .. } => 13,
+        ExpressionKind::Identifier(_)
+            | ExpressionKind::Number(_)
+            | ExpressionKind::Boolean(_)
+            | ExpressionKind::Null
+            | ExpressionKind::Undefined
+            | ExpressionKind::String(_)
+            | ExpressionKind::Call(..) => 14,
+    }
+}
+
+impl Formatter<'_> {
+    // `expr`, parenthesized if it binds looser than `min`
+    fn operand(&self, expr: &Expression, min: u8) -> String {
+        if precedence(expr) < min {
+            format!("({})", self.expression(expr))
+        } else {
+            self.expression(expr)
+        }
+    }
+
+    // The source text of the number or string token in `expr`. The expression's span also covers
+    // any parentheses around it, and the comments and minus sign in between.
+    fn literal(&self, expr: &Expression) -> Option<String> {
+        let text = self.source.get(expr.span.start..expr.span.end)?;
+        let tokens = Lexer::new(text).with_doubles(true).tokenize().ok()?;
+        let literal = tokens.iter().find(|t| matches!(t.token, Token::Number(_) | Token::String(_)))?;
+        text.get(literal.span.start..literal.span.end).map(str::to_string)
+    }
+
+    fn expression(&self, expr: &Expression) -> String {
+        match &expr.kind {
+            // As written, so `0xFF` and `1_000` survive. `- 2147483648` is a single literal.
+            ExpressionKind::Number(n) => match self.literal(expr) {
+                Some(text) if *n < 0.0 => format!("-{}", text),
+                Some(text) => text,
+                None => n.to_string(),
+            },
+            ExpressionKind::Boolean(b) => b.to_string(),
+            ExpressionKind::Null => "null".to_string(),
+            ExpressionKind::Undefined => "undefined".to_string(),
+            // As written, keeping the quotes and escapes
+            ExpressionKind::String(s) => self.literal(expr).unwrap_or_else(|| format!("{:?}", s)),
+            ExpressionKind::Identifier(name) => name.clone(),
+            ExpressionKind::Call(callee, args) => {
+                let args: Vec<String> = args.iter().map(|arg| self.operand(arg, 1)).collect();
+                format!("{}({})", callee.name, args.join(", "))
+            }
+            // Right-associative, so only a comma in the value needs parentheses
+            ExpressionKind::Assignment(target, value) => format!("{} = {}", target.name, self.operand(value, 1)),
+            ExpressionKind::CompoundAssignment(target, op, value) => {
+                format!("{} {}= {}", target.name, binary_op(op), self.operand(value, 1))
+            }
+            // The arms take anything but a comma; the condition is a tighter operator
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                format!("{} ? {} : {}", self.operand(condition, 3), self.operand(then, 1), self.operand(otherwise, 1))
+            }
+            ExpressionKind::Sequence(exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(|e| self.operand(e, 1)).collect();
+                exprs.join(", ")
+            }
+            // Right-associative, and a unary operator on the left must be parenthesized
+            ExpressionKind::Binary(left, BinaryOp::Pow, right) => {
+                let level = precedence(expr);
+                format!("{} ** {}", self.operand(left, level + 2), self.operand(right, level))
+            }
+            // Left-associative: an operand on the right at the same level keeps its parentheses
+            ExpressionKind::Binary(left, op, right) => {
+                let level = precedence(expr);
+                format!("{} {} {}", self.operand(left, level), binary_op(op), self.operand(right, level + 1))
+            }
+            ExpressionKind::Update { target, op, prefix: true } => format!("{}{}", update_op(op), target.name),
+            ExpressionKind::Update { target, op, prefix: false } => format!("{}{}", target.name, update_op(op)),
+            ExpressionKind::Unary(op, operand_expr) => {
+                let inner = self.operand(operand_expr, precedence(expr));
+                // `- -x`, not `--x`
+                let space = match op {
+                    UnaryOp::Neg if inner.starts_with('-') => " ",
+                    UnaryOp::Typeof => " ",
+                    _ => "",
+                };
+                format!("{}{}{}", unary_op(op), space, inner)
+            }
+        }
+    }
+}
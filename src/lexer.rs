@@ -1,10 +1,20 @@
-use crate::token::{Token, SpannedToken, Span};
+use crate::token::{Token, SpannedToken, Span, Position, Trivia, TriviaKind};
+use crate::error::{CompileError, ErrorKind, Result};
 
 pub struct Lexer {
     input: Vec<char>,
     pos: usize,
     line: usize,
     column: usize,
+    // Byte offset of `pos` in the original string
+    offset: usize,
+    // Keep whitespace and comments on the tokens instead of skipping them
+    trivia: bool,
+    // Accept any number literal, not just 32-bit integers (for `--dynamic`)
+    doubles: bool,
+    comments: Vec<Trivia>,
+    // Line the previous token ended on
+    last_line: usize,
 }
 
 impl Lexer {
@@ -14,9 +24,37 @@ impl Lexer {
             pos: 0,
             line: 1,
             column: 1,
+            offset: 0,
+            trivia: false,
+            doubles: false,
+            comments: Vec::new(),
+            last_line: 1,
         }
     }
 
+    // A lexer for tools that need to reproduce the source, like the formatter. Slower than `new`.
+    pub fn with_trivia(input: &str) -> Self {
+        Lexer { trivia: true, ..Lexer::new(input) }
+    }
+
+    // Whether number literals may be fractions or outside the `i32` range, like `0.5` or `1e21`
+    pub fn with_doubles(self, doubles: bool) -> Self {
+        Lexer { doubles, ..self }
+    }
+
+    pub fn doubles(&self) -> bool {
+        self.doubles
+    }
+
+    // With trivia enabled, the comments lexed so far in source order. Always empty otherwise.
+    pub fn comments(&self) -> &[Trivia] {
+        &self.comments
+    }
+
+    fn position(&self) -> Position {
+        Position { line: self.line, column: self.column, offset: self.offset }
+    }
+
     pub fn peek(&self) -> Option<char> {
         if self.pos >= self.input.len() {
             None
@@ -30,6 +68,7 @@ impl Lexer {
         let c = self.peek();
         if let Some(ch) = c {
             self.pos += 1;
+            self.offset += ch.len_utf8();
             if ch == '\n' {
                 self.line += 1;
                 self.column = 1;
@@ -50,18 +89,92 @@ impl Lexer {
         }
     }
 
-    pub fn next_token(&mut self) -> SpannedToken {
+    pub fn next_token(&mut self) -> Result<SpannedToken> {
+        let mut token = if self.trivia {
+            let leading = self.read_trivia(false)?;
+            let mut token = self.read_token()?;
+            token.leading = leading;
+            token.trailing = self.read_trivia(true)?;
+            token
+        } else {
+            self.read_token()?
+        };
+        token.newline_before = token.span.line > self.last_line;
+        self.last_line = token.span.end_line;
+        Ok(token)
+    }
+
+    // Whitespace and comments up to the next token, or with `trailing` up to the end of the line
+    fn read_trivia(&mut self, trailing: bool) -> Result<Vec<Trivia>> {
+        let mut trivia = Vec::new();
+        loop {
+            let start = self.position();
+            let mut text = String::new();
+            let kind = match self.peek() {
+                Some('\n') if trailing => break,
+                Some(c) if c.is_whitespace() => {
+                    while let Some(c) = self.peek().filter(|c| c.is_whitespace() && !(trailing && *c == '\n')) {
+                        text.push(c);
+                        self.advance();
+                    }
+                    TriviaKind::Whitespace
+                }
+                Some('/') if self.input.get(self.pos + 1) == Some(&'/') => {
+                    while let Some(c) = self.peek().filter(|c| *c != '\n') {
+                        text.push(c);
+                        self.advance();
+                    }
+                    TriviaKind::LineComment
+                }
+                Some('/') if self.input.get(self.pos + 1) == Some(&'*') => {
+                    let from = self.pos;
+                    self.advance();
+                    self.skip_block_comment(start)?;
+                    text = self.input[from..self.pos].iter().collect();
+                    // `/**/` is just an empty comment
+                    if text.starts_with("/**") && text != "/**/" {
+                        TriviaKind::DocComment
+                    } else {
+                        TriviaKind::BlockComment
+                    }
+                }
+                _ => break,
+            };
+            let piece = Trivia { kind, text, span: Span::new(start, self.position()) };
+            if piece.is_comment() {
+                self.comments.push(piece.clone());
+            }
+            trivia.push(piece);
+        }
+        Ok(trivia)
+    }
+
+    // Skips the rest of a `/* ... */` comment whose `/` (at `start`) has been consumed
+    fn skip_block_comment(&mut self, start: Position) -> Result<()> {
+        self.advance(); // consume '*'
+        loop {
+            match self.advance() {
+                Some('*') if self.match_char('/') => return Ok(()),
+                Some(_) => {}
+                None => {
+                    let opening = Position { column: start.column + 2, offset: start.offset + 2, ..start };
+                    return Err(CompileError::new(ErrorKind::Lexer, "Unterminated block comment".to_string(), Some(Span::new(start, opening)))
+                        .with_code("E0003")
+                        .with_label("comment starts here")
+                        .with_help("close it with `*/`"));
+                }
+            }
+        }
+    }
+
+    fn read_token(&mut self) -> Result<SpannedToken> {
         self.skip_whitespace();
 
-        let start_line = self.line;
-        let start_column = self.column;
+        let start = self.position();
 
         let c = match self.advance() {
             Some(c) => c,
-            None => return SpannedToken {
-                token: Token::EOF,
-                span: Span::new(start_line, start_column),
-            },
+            None => return Ok(SpannedToken::new(Token::EOF, Span::new(start, start))),
         };
 
         let token = match c {
@@ -72,10 +185,43 @@ impl Lexer {
             '}' => Token::RBrace,
             ',' => Token::Comma,
             ';' => Token::Semi,
-            '+' => Token::Plus,
-            '-' => Token::Minus,
-            '*' => Token::Star,
-            '%' => Token::Percent,
+            '?' => Token::Question,
+            ':' => Token::Colon,
+            '+' => {
+                if self.match_char('+') {
+                    Token::PlusPlus
+                } else if self.match_char('=') {
+                    Token::PlusEq
+                } else {
+                    Token::Plus
+                }
+            }
+            '-' => {
+                if self.match_char('-') {
+                    Token::MinusMinus
+                } else if self.match_char('=') {
+                    Token::MinusEq
+                } else {
+                    Token::Minus
+                }
+            }
+            '*' => {
+                if self.match_char('*') {
+                    if self.match_char('=') { Token::StarStarEq } else { Token::StarStar }
+                } else if self.match_char('=') {
+                    Token::StarEq
+                } else {
+                    Token::Star
+                }
+            }
+            // `.5`
+            '.' if self.peek().is_some_and(|c| c.is_ascii_digit()) => self.read_number(c, start)?,
+            '.' => Token::Dot,
+            '%' => if self.match_char('=') { Token::PercentEq } else { Token::Percent },
+            '~' => Token::Tilde,
+            '&' => if self.match_char('=') { Token::AmpEq } else { Token::Amp },
+            '|' => if self.match_char('=') { Token::PipeEq } else { Token::Pipe },
+            '^' => if self.match_char('=') { Token::CaretEq } else { Token::Caret },
 
             // Slash or Comment
             '/' => {
@@ -85,44 +231,260 @@ impl Lexer {
                         if c == '\n' { break; }
                         self.advance();
                     }
-                    return self.next_token(); // Recursively get next real token
+                    return self.read_token(); // Recursively get next real token
+                } else if let Some('*') = self.peek() {
+                    self.skip_block_comment(start)?;
+                    return self.read_token();
+                } else if self.match_char('=') {
+                    Token::SlashEq
                 } else {
                     Token::Slash
                 }
             }
  
             // Multi-char operators
-            '=' => if self.match_char('=') { Token::EqEq } else { Token::Eq },
-            '!' => if self.match_char('=') { Token::BangEq } else { Token::Bang },
-            '<' => if self.match_char('=') { Token::LtEq } else { Token::Lt },
-            '>' => if self.match_char('=') { Token::GtEq } else { Token::Gt },
+            '=' => {
+                if self.match_char('=') {
+                    if self.match_char('=') { Token::EqEqEq } else { Token::EqEq }
+                } else {
+                    Token::Eq
+                }
+            }
+            '!' => {
+                if self.match_char('=') {
+                    if self.match_char('=') { Token::BangEqEq } else { Token::BangEq }
+                } else {
+                    Token::Bang
+                }
+            }
+            '<' => {
+                if self.match_char('<') {
+                    if self.match_char('=') { Token::ShlEq } else { Token::Shl }
+                } else if self.match_char('=') {
+                    Token::LtEq
+                } else {
+                    Token::Lt
+                }
+            }
+            '>' => {
+                if self.match_char('>') {
+                    if self.match_char('>') {
+                        if self.match_char('=') { Token::UShrEq } else { Token::UShr }
+                    } else if self.match_char('=') {
+                        Token::ShrEq
+                    } else {
+                        Token::Shr
+                    }
+                } else if self.match_char('=') {
+                    Token::GtEq
+                } else {
+                    Token::Gt
+                }
+            }
 
             // Numbers
-            '0'..='9' => self.read_number(c),
+            '0'..='9' => self.read_number(c, start)?,
+
+            '"' | '\'' => self.read_string(c, start)?,
 
             // Identifiers & Keywords
             'a'..='z' | 'A'..='Z' | '_' => self.read_identifier(c),
 
-            _ => panic!("Unexpected character '{}' at line {}, column {}", c, start_line, start_column),
+            _ => return Err(CompileError::new(
+                ErrorKind::Lexer,
+                format!("Unexpected character '{}'", c),
+                Some(Span::new(start, self.position())),
+            ).with_code("E0001").with_label("unexpected character")),
         };
 
-        SpannedToken {
-            token,
-            span: Span::new(start_line, start_column),
+        Ok(SpannedToken::new(token, Span::new(start, self.position())))
+    }
+
+    // Lexes the whole input, including the final EOF token
+    pub fn tokenize(&mut self) -> Result<Vec<SpannedToken>> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            let done = token.token == Token::EOF;
+            tokens.push(token);
+            if done {
+                return Ok(tokens);
+            }
         }
     }
 
-    fn read_number(&mut self, first: char) -> Token {
-        let mut s = String::new();
-        s.push(first);
+    // Decimal literals with an optional fraction and exponent, `0x`/`0o`/`0b` integers, and `_`
+    // separators between digits. Without doubles, a decimal value must be a whole number no larger
+    // than 2^31, and the parser only accepts 2^31 itself right after a unary minus. A prefixed one
+    // is a bit pattern instead: anything up to 32 bits wraps to an i32, as `| 0` would.
+    fn read_number(&mut self, first: char, start: Position) -> Result<Token> {
+        let from = self.pos - 1;
+        let radix = match (first, self.peek()) {
+            ('0', Some('x' | 'X')) => 16,
+            ('0', Some('o' | 'O')) => 8,
+            ('0', Some('b' | 'B')) => 2,
+            _ => 10,
+        };
+        let value = if radix == 10 {
+            self.read_decimal(first, from, start)?
+        } else {
+            self.advance(); // consume the prefix letter
+            let mut digits = String::new();
+            self.read_digits(radix, &mut digits, from, start)?;
+            if digits.is_empty() {
+                return Err(self.number_error(from, start, "E0004", "Invalid number literal", "expected digits after the prefix"));
+            }
+            match u64::from_str_radix(&digits, radix) {
+                Ok(value) => Some(value as f64),
+                // Out of range without doubles, and a double this big needn't be exact
+                Err(_) => Some(digits.chars().fold(0.0, |value, c| value * radix as f64 + c.to_digit(radix).unwrap() as f64)),
+            }
+        };
+
+        // `5px`, `0b102`
+        if self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                self.advance();
+            }
+            return Err(self.number_error(from, start, "E0004", "Invalid number literal", "identifiers can't start with a digit"));
+        }
+        if !self.doubles && radix != 10 {
+            return match value {
+                Some(value) if value <= u32::MAX as f64 => Ok(Token::Number(value as u32 as i32 as f64)),
+                _ => Err(self.number_error(from, start, "E0002", "Number literal out of range", "without `--dynamic`, hex, octal and binary literals can have at most 32 bits, up to 0xFFFFFFFF")),
+            };
+        }
+        // 2^31 is left for the parser, which accepts it after a `-`
+        match value {
+            Some(value) if self.doubles || value <= 2147483648.0 => Ok(Token::Number(value)),
+            _ => Err(self.number_error(from, start, "E0002", "Number literal out of range", "without `--dynamic`, number literals must fit in an i32, from -2147483648 to 2147483647")),
+        }
+    }
+
+    // The rest of a decimal literal starting with `first`, a digit or the `.` of `.5`. Without
+    // doubles, None if the value doesn't fit in a u64.
+    fn read_decimal(&mut self, first: char, from: usize, start: Position) -> Result<Option<f64>> {
+        // `0_1` could be mistaken for an old-style octal literal, so JavaScript rejects it
+        if first == '0' && self.peek() == Some('_') {
+            self.advance();
+            return Err(self.number_error(from, start, "E0004", "Invalid number literal", "`_` can't follow a leading `0`"));
+        }
+        let mut digits = String::new();
+        if first != '.' {
+            digits.push(first);
+            self.read_digits(10, &mut digits, from, start)?;
+        }
+
+        // The value is `digits * 10^scale`
+        let mut scale: i64 = 0;
+        if first == '.' || self.match_char('.') {
+            let before = digits.len();
+            self.read_digits(10, &mut digits, from, start)?;
+            scale -= (digits.len() - before) as i64;
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.advance();
+            let negative = self.match_char('-');
+            if !negative {
+                self.match_char('+');
+            }
+            let mut exponent = String::new();
+            self.read_digits(10, &mut exponent, from, start)?;
+            if exponent.is_empty() {
+                return Err(self.number_error(from, start, "E0004", "Invalid number literal", "expected digits in the exponent"));
+            }
+            // Too many digits to parse is far out of range either way
+            let exponent: i64 = exponent.parse().unwrap_or(i64::MAX / 2);
+            scale += if negative { -exponent } else { exponent };
+        }
+        // Rust's parser rounds to the nearest double, as JavaScript does
+        if self.doubles {
+            return Ok(format!("{}e{}", digits, scale).parse().ok());
+        }
+
+        let mut digits = digits.trim_start_matches('0').to_string();
+        if digits.is_empty() {
+            return Ok(Some(0.0));
+        }
+        while scale < 0 && digits.ends_with('0') {
+            digits.pop();
+            scale += 1;
+        }
+        if scale < 0 {
+            return Err(self.number_error(from, start, "E0004", "Number literal is not an integer", "only 32-bit integers are supported unless compiled with `--dynamic`"));
+        }
+        if digits.len() as i64 + scale > 19 {
+            return Ok(None);
+        }
+        Ok(digits.parse::<u64>().ok().and_then(|n| n.checked_mul(10u64.pow(scale as u32))).map(|n| n as f64))
+    }
+
+    // Appends digits in `radix` to `digits`. A `_` may only sit between two of them.
+    fn read_digits(&mut self, radix: u32, digits: &mut String, from: usize, start: Position) -> Result<()> {
         while let Some(c) = self.peek() {
-            if c.is_ascii_digit() {
-                s.push(self.advance().unwrap());
+            if c.is_digit(radix) {
+                digits.push(c);
+            } else if c == '_' {
+                let previous_is_digit = self.input[self.pos - 1].is_digit(radix);
+                let next_is_digit = self.input.get(self.pos + 1).is_some_and(|next| next.is_digit(radix));
+                if !previous_is_digit || !next_is_digit {
+                    self.advance();
+                    return Err(self.number_error(from, start, "E0004", "Invalid number literal", "`_` is only allowed between digits"));
+                }
             } else {
                 break;
             }
+            self.advance();
         }
-        Token::Number(s.parse().unwrap())
+        Ok(())
+    }
+
+    // `message: <literal so far>`, pointing at the literal
+    fn number_error(&self, from: usize, start: Position, code: &'static str, message: &str, help: &str) -> CompileError {
+        let text: String = self.input[from..self.pos].iter().collect();
+        CompileError::new(ErrorKind::Lexer, format!("{}: {}", message, text), Some(Span::new(start, self.position())))
+            .with_code(code)
+            .with_help(help)
+    }
+
+    // The rest of a string literal opened by `quote`. Unknown escapes stand for the escaped
+    // character, as in JavaScript (`"\q"` is `"q"`).
+    fn read_string(&mut self, quote: char, start: Position) -> Result<Token> {
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                Some(c) if c == quote => {
+                    self.advance();
+                    return Ok(Token::String(s));
+                }
+                Some('\\') => {
+                    self.advance();
+                    match self.peek().filter(|c| *c != '\n') {
+                        Some(c) => {
+                            self.advance();
+                            s.push(match c {
+                                'n' => '\n',
+                                't' => '\t',
+                                'r' => '\r',
+                                '0' => '\0',
+                                _ => c,
+                            });
+                        }
+                        None => break,
+                    }
+                }
+                Some('\n') | None => break,
+                Some(c) => {
+                    self.advance();
+                    s.push(c);
+                }
+            }
+        }
+        let opening = Position { column: start.column + 1, offset: start.offset + 1, ..start };
+        Err(CompileError::new(ErrorKind::Lexer, "Unterminated string literal".to_string(), Some(Span::new(start, opening)))
+            .with_code("E0005")
+            .with_label("string starts here")
+            .with_help(format!("close it with `{}` before the end of the line", quote)))
     }
 
     fn read_identifier(&mut self, first: char) -> Token {
@@ -144,18 +506,28 @@ impl Lexer {
             "if" => Token::If,
             "else" => Token::Else,
             "while" => Token::While,
+            "do" => Token::Do,
+            "switch" => Token::Switch,
+            "case" => Token::Case,
+            "default" => Token::Default,
+            "break" => Token::Break,
+            "continue" => Token::Continue,
+            "true" => Token::True,
+            "false" => Token::False,
+            "null" => Token::Null,
+            "undefined" => Token::Undefined,
+            "typeof" => Token::Typeof,
             _ => Token::Identifier(s),
         }
     }
 
     // Check if next char matches expected, consume if yes
     fn match_char(&mut self, expected: char) -> bool {
-        if let Some(c) = self.peek() {
-            if c == expected {
+        if let Some(c) = self.peek()
+            && c == expected {
                 self.advance();
                 return true;
             }
-        }
         false
     }
 }
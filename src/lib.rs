@@ -1,18 +1,125 @@
+// `CompileError` carries everything needed to render a diagnostic. It's only built once,
+// on the way out, so its size on the error path doesn't matter.
+#![allow(clippy::result_large_err)]
+
 pub mod lexer;
 pub mod token;
 pub mod ast;
 pub mod parser;
+pub mod error;
+pub mod diagnostic;
+pub mod json;
+pub mod dump;
+pub mod sema;
+pub mod lint;
+pub mod format;
+pub mod docs;
+pub mod wasm;
+pub mod options;
 pub mod codegen;
+pub mod builtins;
+pub mod value;
+pub mod runtime;
+pub mod types;
+pub mod cse;
+pub mod peephole;
+pub mod regalloc;
+pub mod dce;
+pub mod binary;
 
 use crate::lexer::Lexer;
 use crate::parser::Parser;
 use crate::codegen::CodeGenerator;
+use crate::diagnostic::Diagnostic;
+use crate::ast::Program;
+use crate::error::Result;
+use crate::options::{CompileOptions, Pass};
+use crate::peephole::PeepholeStats;
+use crate::sema::Analysis;
+use crate::token::SpannedToken;
+use crate::wasm::Module;
+
+// Convenience wrappers for tests and embedding. They panic with the rendered error message.
 
 pub fn compile(input: &str) -> String {
-    let lexer = Lexer::new(input);
-    let mut parser = Parser::new(lexer);
-    let program = parser.parse_program();
+    compile_with_options(input, &CompileOptions::default())
+}
+
+pub fn compile_with_options(input: &str, options: &CompileOptions) -> String {
+    compile_with_stats(input, options).0
+}
+
+// Same as `compile_with_options`, but also reports how much the module passes shrank the output
+pub fn compile_with_stats(input: &str, options: &CompileOptions) -> (String, PeepholeStats) {
+    let (module, stats) = compile_module(input, options).unwrap_or_else(|err| panic!("{}", err));
+    (module.to_wat(), stats)
+}
+
+// Fallible pipeline stages
+
+pub fn tokenize(input: &str) -> Result<Vec<SpannedToken>> {
+    tokenize_with_options(input, &CompileOptions::default())
+}
+
+pub fn tokenize_with_options(input: &str, options: &CompileOptions) -> Result<Vec<SpannedToken>> {
+    Lexer::new(input).with_doubles(options.dynamic).tokenize()
+}
+
+pub fn parse(input: &str) -> Result<Program> {
+    parse_with_options(input, &CompileOptions::default())
+}
+
+// With `--dynamic`, number literals don't have to be 32-bit integers
+pub fn parse_with_options(input: &str, options: &CompileOptions) -> Result<Program> {
+    let lexer = Lexer::new(input).with_doubles(options.dynamic);
+    let mut parser = Parser::new(lexer)?;
+    parser.parse_program()
+}
+
+// `input` in canonical formatting, comments included. Literals are kept as written, so any
+// number is accepted.
+pub fn format_source(input: &str) -> Result<String> {
+    let mut parser = Parser::new(Lexer::with_trivia(input).with_doubles(true))?;
+    let program = parser.parse_program()?;
+    Ok(format::format(&program, parser.comments(), input))
+}
+
+// Markdown documentation for the functions in `input`, from their `/** ... */` comments
+pub fn docs(input: &str) -> Result<String> {
+    let program = Parser::new(Lexer::with_trivia(input).with_doubles(true))?.parse_program()?;
+    Ok(docs::render(&program))
+}
+
+// Warnings (and denied lints) for `input`, according to `options.lints`
+pub fn lint(input: &str, options: &CompileOptions) -> Result<Vec<Diagnostic>> {
+    let program = parse_with_options(input, options)?;
+    let analysis = sema::analyze(&program, options)?;
+    Ok(lint::check(&program, &analysis, input, options))
+}
+
+pub fn compile_module(input: &str, options: &CompileOptions) -> Result<(Module, PeepholeStats)> {
+    let program = parse_with_options(input, options)?;
+    let analysis = sema::analyze(&program, options)?;
+    compile_program(&program, analysis, options)
+}
+
+// The rest of `compile_module`, for a caller that has already parsed and analyzed the program
+// to lint it
+pub fn compile_program(program: &Program, analysis: Analysis, options: &CompileOptions) -> Result<(Module, PeepholeStats)> {
+    let mut module = CodeGenerator::with_options(options.clone(), analysis).generate(program)?;
 
-    let mut codegen = CodeGenerator::new();
-    codegen.generate(&program)
+    let before = module.instruction_count();
+    for pass in &options.passes {
+        match pass {
+            Pass::Peephole => {
+                peephole::optimize(&mut module);
+            }
+            Pass::CoalesceLocals => regalloc::coalesce(&mut module, options.keep_local_names),
+            Pass::RemoveUnusedFunctions => dce::remove_unused_functions(&mut module),
+            // Applied by the code generator
+            Pass::FoldLiterals | Pass::FoldConstants | Pass::Cse => {}
+        }
+    }
+    let stats = PeepholeStats { before, after: module.instruction_count() };
+    Ok((module, stats))
 }
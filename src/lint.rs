@@ -0,0 +1,265 @@
+use crate::ast::{Expression, ExpressionKind, Program, Statement, StatementKind};
+use crate::codegen::constant_value;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::options::CompileOptions;
+use crate::sema::{Analysis, BindingKind};
+use crate::token::Span;
+
+// Style checks that run after semantic analysis. Each rule has an ID used on the command line
+// (`-W unused-variable`) and in `// humera-disable-next-line unused-variable` comments.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lint {
+    UnusedVariable,
+    UnusedParameter,
+    ShadowedBinding,
+    AssignmentInCondition,
+    ConstantCondition,
+    PreferConst,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 6] = [
+        Lint::UnusedVariable,
+        Lint::UnusedParameter,
+        Lint::ShadowedBinding,
+        Lint::AssignmentInCondition,
+        Lint::ConstantCondition,
+        Lint::PreferConst,
+    ];
+
+    pub fn id(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedParameter => "unused-parameter",
+            Lint::ShadowedBinding => "shadowed-binding",
+            Lint::AssignmentInCondition => "assignment-in-condition",
+            Lint::ConstantCondition => "constant-condition",
+            Lint::PreferConst => "prefer-const",
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.id() == id)
+    }
+
+    // Shadowing and `let` vs `const` are matters of taste, so they're opt-in
+    pub fn default_level(&self) -> LintLevel {
+        match self {
+            Lint::ShadowedBinding | Lint::PreferConst => LintLevel::Allow,
+            _ => LintLevel::Warn,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+// Level of every rule, starting from the defaults
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintConfig {
+    levels: Vec<(Lint, LintLevel)>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig { levels: Lint::ALL.iter().map(|lint| (*lint, lint.default_level())).collect() }
+    }
+}
+
+impl LintConfig {
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels.iter().find(|(l, _)| *l == lint).map_or(lint.default_level(), |(_, level)| *level)
+    }
+
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        for (l, current) in &mut self.levels {
+            if *l == lint {
+                *current = level;
+            }
+        }
+    }
+
+    pub fn set_all(&mut self, level: LintLevel) {
+        for (_, current) in &mut self.levels {
+            *current = level;
+        }
+    }
+}
+
+// Runs every enabled rule. Warnings come back with `Severity::Warning`, denied rules with `Severity::Error`.
+pub fn check(program: &Program, analysis: &Analysis, source: &str, options: &CompileOptions) -> Vec<Diagnostic> {
+    let mut linter = Linter { config: &options.lints, dynamic: options.dynamic, found: Vec::new() };
+
+    for binding in &analysis.bindings {
+        // A leading underscore marks a variable as deliberately unused
+        if binding.name.starts_with('_') {
+            continue;
+        }
+        if binding.reads == 0 {
+            match binding.kind {
+                BindingKind::Param => linter.report(
+                    Lint::UnusedParameter,
+                    format!("Parameter '{}' is never used", binding.name),
+                    binding.span,
+                    &format!("prefix it with an underscore if this is intended: `_{}`", binding.name),
+                ),
+                _ => linter.report(
+                    Lint::UnusedVariable,
+                    format!("Variable '{}' is never read", binding.name),
+                    binding.span,
+                    &format!("remove it, or prefix it with an underscore: `_{}`", binding.name),
+                ),
+            }
+        }
+        if binding.kind == BindingKind::Let && binding.writes == 0 {
+            // The declaration starts with the `let` keyword
+            let decl = binding.declaration;
+            let keyword = Span { end_line: decl.line, end_column: decl.column + 3, end: decl.start + 3, ..decl };
+            linter.report_with(
+                Lint::PreferConst,
+                Diagnostic::new(Severity::Warning, format!("'{}' is never reassigned", binding.name), binding.span)
+                    .with_help("declare it with `const`")
+                    .with_fix("change `let` to `const`", keyword, "const"),
+            );
+        }
+        if let Some(outer) = binding.shadows {
+            linter.report_with(
+                Lint::ShadowedBinding,
+                Diagnostic::new(Severity::Warning, format!("'{}' shadows a variable from an enclosing scope", binding.name), binding.span)
+                    .with_related(outer, "shadowed declaration"),
+            );
+        }
+    }
+
+    for stmt in &program.body {
+        linter.statement(stmt);
+    }
+
+    let mut found = linter.found;
+    found.retain(|diagnostic| !suppressed(source, diagnostic));
+    found.sort_by_key(|diagnostic| diagnostic.span.map(|span| span.start));
+    found
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    // Whether conditions are evaluated on tagged values
+    dynamic: bool,
+    found: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, lint: Lint, message: String, span: Span, help: &str) {
+        self.report_with(lint, Diagnostic::new(Severity::Warning, message, span).with_help(help));
+    }
+
+    fn report_with(&mut self, lint: Lint, mut diagnostic: Diagnostic) {
+        diagnostic.severity = match self.config.level(lint) {
+            LintLevel::Allow => return,
+            LintLevel::Warn => Severity::Warning,
+            LintLevel::Deny => Severity::Error,
+        };
+        diagnostic.code = Some(lint.id());
+        self.found.push(diagnostic);
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match &stmt.kind {
+            StatementKind::FunctionDeclaration { body, .. } => {
+                for s in body {
+                    self.statement(s);
+                }
+            }
+            StatementKind::If { condition, then_branch, else_branch } => {
+                self.condition(condition, false);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                self.condition(condition, true);
+                self.statement(body);
+            }
+            StatementKind::DoWhile { body, condition } => {
+                self.statement(body);
+                // `do { ... } while (0)` runs the body once on purpose
+                if !matches!(condition.kind, ExpressionKind::Number(0.0) | ExpressionKind::Boolean(false)) {
+                    self.condition(condition, true);
+                }
+            }
+            StatementKind::Switch { cases, .. } => {
+                for s in cases.iter().flat_map(|case| &case.body) {
+                    self.statement(s);
+                }
+            }
+            StatementKind::Labeled { body, .. } => self.statement(body),
+            StatementKind::Block(stmts) => {
+                for s in stmts {
+                    self.statement(s);
+                }
+            }
+            StatementKind::VariableDeclaration { .. }
+            | StatementKind::Break(_)
+            | StatementKind::Continue(_)
+            | StatementKind::Return(_)
+            | StatementKind::Expression(_) => {}
+        }
+    }
+
+    fn condition(&mut self, condition: &Expression, is_loop: bool) {
+        if let ExpressionKind::Assignment(target, _) | ExpressionKind::CompoundAssignment(target, _, _) = &condition.kind {
+            self.report(
+                Lint::AssignmentInCondition,
+                format!("Assignment to '{}' used as a condition", target.name),
+                condition.span,
+                "use `==` to compare, or move the assignment out of the condition",
+            );
+        }
+        // `while (1)` and `while (true)` are the usual ways to write an endless loop
+        let endless_loop = is_loop
+            && (matches!(condition.kind, ExpressionKind::Number(n) if n != 0.0) || matches!(condition.kind, ExpressionKind::Boolean(true)));
+        // `constant_value` has `i32` semantics, which --dynamic doesn't (`1 / 2` is 0.5, `null === 0` is false)
+        let value = if self.dynamic { literal_truth(condition) } else { constant_value(condition).map(|value| value != 0) };
+        if let Some(value) = value && !endless_loop {
+            let outcome = if value { "true" } else { "false" };
+            self.report(
+                Lint::ConstantCondition,
+                format!("Condition is always {}", outcome),
+                condition.span,
+                "the branch is decided at compile time",
+            );
+        }
+    }
+}
+
+// Whether a literal is truthy
+fn literal_truth(expr: &Expression) -> Option<bool> {
+    match &expr.kind {
+        ExpressionKind::Number(n) => Some(*n != 0.0),
+        ExpressionKind::Boolean(b) => Some(*b),
+        ExpressionKind::Null | ExpressionKind::Undefined => Some(false),
+        ExpressionKind::String(s) => Some(!s.is_empty()),
+        _ => None,
+    }
+}
+
+// Whether a `// humera-disable-next-line` comment on the line before the diagnostic turns it off.
+// With no rule IDs after it the comment silences every rule.
+fn suppressed(source: &str, diagnostic: &Diagnostic) -> bool {
+    const DIRECTIVE: &str = "// humera-disable-next-line";
+    let (Some(span), Some(code)) = (diagnostic.span, diagnostic.code) else { return false };
+    let Some(previous) = span.line.checked_sub(2).and_then(|i| source.lines().nth(i)) else { return false };
+    let Some(at) = previous.find(DIRECTIVE) else { return false };
+
+    let rules: Vec<&str> = previous[at + DIRECTIVE.len()..]
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|rule| !rule.is_empty())
+        .collect();
+    rules.is_empty() || rules.contains(&code)
+}
@@ -1,24 +1,266 @@
+// `CompileError` carries everything needed to render a diagnostic. It's only built once,
+// on the way out, so its size on the error path doesn't matter.
+#![allow(clippy::result_large_err)]
+
 use std::env;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process;
-use humera_js_compiler::compile;
+use humera_js_compiler::{compile_program, docs, format_source, parse_with_options, tokenize_with_options, binary, dump, sema};
+use humera_js_compiler::diagnostic::{self, Diagnostic, Severity};
+use humera_js_compiler::lint::{self, Lint, LintLevel};
+use humera_js_compiler::error::CompileError;
+use humera_js_compiler::options::{CompileOptions, Emit, OptLevel};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: cargo run <input_file>");
-        process::exit(1);
+const USAGE: &str = "\
+Usage: humera-js-compiler [options] <input>...
+
+Compiles a subset of JavaScript to WebAssembly. Use `-` as an input to read from stdin.
+
+Options:
+  -o <path>            Write the output to <path> (`-` for stdout). With several inputs,
+                       <path> is a directory that receives one file per input.
+                       Without -o, a single input is written to stdout and several
+                       inputs are written next to their source files.
+  --fmt                Reformat the inputs in place (or to -o / stdout for `-`)
+  --check              With --fmt, only report which inputs would change
+  --emit <kind>        What to produce: tokens, ast, ast-json, wat (default), wasm or docs
+  -O0 .. -O3, -Os      Optimization level (default -O1)
+  --keep-local-names   Name coalesced locals after the variables they hold
+  --loose-arity        Pad missing call arguments with 0 and drop extra ones, as
+                       JavaScript does, instead of reporting an error
+  --dynamic            Tag every value with its type at runtime, for strings, typeof
+                       and ===; numbers become doubles
+  --error-format <fmt> How to print errors: human (default) or json (one object per line)
+  -A, -W, -D <lint>    Allow, warn about or deny a lint rule (`all` for every rule):
+                       unused-variable, unused-parameter, shadowed-binding,
+                       assignment-in-condition, constant-condition, prefer-const
+  -q, --quiet          Don't print progress and statistics to stderr
+  -h, --help           Print this message
+
+Exit codes:
+  0  Success
+  1  Usage or I/O error
+  2  Lexer error (e.g. an unexpected character)
+  3  Parser error (e.g. a missing semicolon)
+  4  Semantic error (e.g. an undefined variable or assigning to a const)
+  5  A denied lint fired
+  6  --check found inputs that aren't formatted
+  7  Internal compiler error
+";
+
+struct Cli {
+    inputs: Vec<String>,
+    output: Option<String>,
+    emit: Emit,
+    quiet: bool,
+    json_errors: bool,
+    check: bool,
+    options: CompileOptions,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut opt_level = OptLevel::default();
+    let mut keep_local_names = false;
+    let mut loose_arity = false;
+    let mut dynamic = false;
+    // Applied in command-line order, so later flags win
+    let mut lint_levels = Vec::new();
+    let mut inputs = Vec::new();
+    let mut output = None;
+    let mut emit = Emit::default();
+    let mut quiet = false;
+    let mut json_errors = false;
+    let mut check = false;
+
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                process::exit(0);
+            }
+            "-o" => output = Some(args.next().ok_or("-o expects a path")?),
+            "--emit" => {
+                let kind = args.next().ok_or("--emit expects tokens, ast, ast-json, wat, wasm or docs")?;
+                emit = Emit::parse(&kind).ok_or(format!("Unknown --emit kind: {} (expected tokens, ast, ast-json, wat, wasm or docs)", kind))?;
+            }
+            "--error-format" => {
+                json_errors = match args.next().as_deref() {
+                    Some("human") => false,
+                    Some("json") => true,
+                    _ => return Err("--error-format expects human or json".to_string()),
+                };
+            }
+            "--fmt" => emit = Emit::Js,
+            "--check" => check = true,
+            "-q" | "--quiet" => quiet = true,
+            "--keep-local-names" => keep_local_names = true,
+            "--loose-arity" => loose_arity = true,
+            "--dynamic" => dynamic = true,
+            "-A" | "-W" | "-D" => {
+                let level = match arg.as_str() {
+                    "-A" => LintLevel::Allow,
+                    "-W" => LintLevel::Warn,
+                    _ => LintLevel::Deny,
+                };
+                let id = args.next().ok_or(format!("{} expects a lint name", arg))?;
+                let lint = match id.as_str() {
+                    "all" => None,
+                    _ => Some(Lint::parse(&id).ok_or(format!("Unknown lint: {}", id))?),
+                };
+                lint_levels.push((lint, level));
+            }
+            "-" => inputs.push(arg),
+            _ if arg.starts_with("-O") => {
+                opt_level = OptLevel::parse(&arg[2..])
+                    .ok_or(format!("Unknown optimization level: {} (expected -O0, -O1, -O2, -O3 or -Os)", arg))?;
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ => inputs.push(arg),
+        }
+    }
+
+    if inputs.is_empty() {
+        return Err("No input files".to_string());
+    }
+    if check && emit != Emit::Js {
+        return Err("--check only applies to --fmt".to_string());
+    }
+
+    let mut options = CompileOptions::new(opt_level);
+    options.keep_local_names = keep_local_names;
+    options.loose_arity = loose_arity;
+    options.dynamic = dynamic;
+    for (lint, level) in lint_levels {
+        match lint {
+            Some(lint) => options.lints.set(lint, level),
+            None => options.lints.set_all(level),
+        }
+    }
+    Ok(Cli { inputs, output, emit, quiet, json_errors, check, options })
+}
+
+// Where the result for `input` goes. None means stdout.
+fn output_path(cli: &Cli, input: &str) -> Option<PathBuf> {
+    let file_name = || {
+        let stem = Path::new(input).file_stem().unwrap_or_default();
+        PathBuf::from(stem).with_extension(cli.emit.extension())
+    };
+    match cli.output.as_deref() {
+        Some("-") => None,
+        Some(path) if cli.inputs.len() == 1 => Some(PathBuf::from(path)),
+        Some(dir) if input != "-" => Some(Path::new(dir).join(file_name())),
+        // The formatter rewrites files in place
+        None if cli.emit == Emit::Js && input != "-" => Some(PathBuf::from(input)),
+        None if cli.inputs.len() > 1 && input != "-" => Some(Path::new(input).with_file_name(file_name())),
+        _ => None,
+    }
+}
+
+fn read_input(input: &str) -> io::Result<String> {
+    if input == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        Ok(source)
+    } else {
+        std::fs::read_to_string(input)
     }
+}
+
+// Compiler output for `source`. Lint warnings go to `warn` as they're found.
+fn run(cli: &Cli, source: &str, name: &str, warn: impl Fn(&Diagnostic)) -> Result<Vec<u8>, CompileError> {
+    Ok(match cli.emit {
+        Emit::Tokens => dump::tokens(&tokenize_with_options(source, &cli.options)?).into_bytes(),
+        Emit::Ast => dump::ast(&parse_with_options(source, &cli.options)?).into_bytes(),
+        Emit::AstJson => format!("{}\n", dump::ast_json(&parse_with_options(source, &cli.options)?).pretty()).into_bytes(),
+        Emit::Js => format_source(source)?.into_bytes(),
+        Emit::Docs => docs(source)?.into_bytes(),
+        Emit::Wat | Emit::Wasm => {
+            // Parsed and analyzed once for both the lints and the code generator
+            let program = parse_with_options(source, &cli.options)?;
+            let analysis = sema::analyze(&program, &cli.options)?;
+            let warnings = lint::check(&program, &analysis, source, &cli.options);
+            for warning in &warnings {
+                warn(warning);
+            }
+            if warnings.iter().any(|w| w.severity == Severity::Error) {
+                process::exit(5);
+            }
+            let (module, stats) = compile_program(&program, analysis, &cli.options)?;
+            if !cli.quiet {
+                eprintln!("{}: {}", name, stats);
+            }
+            match cli.emit {
+                Emit::Wasm => binary::encode(&module),
+                _ => module.to_wat().into_bytes(),
+            }
+        }
+    })
+}
 
-    let filename = &args[1];
-    let input = std::fs::read_to_string(filename).unwrap_or_else(|err| {
-        eprintln!("Error reading file {}: {}", filename, err);
+fn main() {
+    let cli = parse_args(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
         process::exit(1);
     });
 
-    println!("Compiling {}...", filename);
+    // Colour diagnostics only when a person is likely to be reading them
+    let color = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let report = |diagnostic: &Diagnostic, source: &str, name: &str| {
+        if cli.json_errors {
+            eprintln!("{}", diagnostic::to_json(diagnostic, name));
+        } else {
+            eprint!("{}", diagnostic::render(diagnostic, source, name, color));
+        }
+    };
+
+    let mut unformatted = false;
+    for input in &cli.inputs {
+        let name = if input == "-" { "<stdin>" } else { input.as_str() };
+        let source = read_input(input).unwrap_or_else(|err| {
+            eprintln!("Error reading {}: {}", name, err);
+            process::exit(1);
+        });
+
+        if !cli.quiet && !cli.check {
+            let action = if cli.emit == Emit::Js { "Formatting" } else { "Compiling" };
+            eprintln!("{} {}...", action, name);
+        }
+
+        let output = run(&cli, &source, name, |warning| report(warning, &source, name)).unwrap_or_else(|err| {
+            report(&Diagnostic::from(&err), &source, name);
+            process::exit(err.kind.exit_code());
+        });
 
-    let wat = compile(&input);
-    
-    std::fs::write("output.wat", wat).unwrap();
-    println!("Successfully wrote output.wat");
+        if cli.check {
+            if output != source.as_bytes() {
+                println!("Would reformat {}", name);
+                unformatted = true;
+            }
+            continue;
+        }
+
+        let written = match output_path(&cli, input) {
+            Some(path) => {
+                if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    let _ = std::fs::create_dir_all(dir);
+                }
+                std::fs::write(&path, &output).map(|_| Some(path))
+            }
+            None => io::stdout().write_all(&output).map(|_| None),
+        };
+        match written {
+            Ok(Some(path)) if !cli.quiet => eprintln!("Wrote {}", path.display()),
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("Error writing output for {}: {}", name, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if unformatted {
+        process::exit(6);
+    }
 }
@@ -0,0 +1,134 @@
+use crate::lint::LintConfig;
+
+// Compiler configuration shared by the library API and the CLI.
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OptLevel {
+    // One-to-one translation of the source, useful for debugging codegen
+    O0,
+    #[default]
+    O1,
+    O2,
+    // -O2, plus passes that only pay off for whole programs
+    O3,
+    // -O2, plus whatever makes the output smaller
+    Os,
+}
+
+impl OptLevel {
+    // Parses the part after `-O`, e.g. "2" or "s"
+    pub fn parse(level: &str) -> Option<Self> {
+        match level {
+            "0" => Some(OptLevel::O0),
+            "1" => Some(OptLevel::O1),
+            "2" => Some(OptLevel::O2),
+            "3" => Some(OptLevel::O3),
+            "s" => Some(OptLevel::Os),
+            _ => None,
+        }
+    }
+
+    pub fn passes(&self) -> Vec<Pass> {
+        match self {
+            OptLevel::O0 => vec![],
+            OptLevel::O1 => vec![Pass::FoldLiterals, Pass::Peephole],
+            OptLevel::O2 => vec![
+                Pass::FoldConstants,
+                Pass::Cse,
+                Pass::Peephole,
+                Pass::CoalesceLocals,
+                // Coalescing turns some copies into `local.get $r0, local.set $r0`
+                Pass::Peephole,
+            ],
+            OptLevel::O3 | OptLevel::Os => [OptLevel::O2.passes(), vec![Pass::RemoveUnusedFunctions]].concat(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pass {
+    // Fold an operator applied to two literals, e.g. `2 * 3`
+    FoldLiterals,
+    // Fold whole constant subtrees, e.g. `1 + 2 * 3`
+    FoldConstants,
+    // Reuse repeated pure subexpressions through temporaries (see cse.rs)
+    Cse,
+    // Rewrite short instruction sequences (see peephole.rs)
+    Peephole,
+    // Share one wasm local between variables whose lifetimes don't overlap (see regalloc.rs)
+    CoalesceLocals,
+    // Drop functions that can't be reached from an export (see dce.rs)
+    RemoveUnusedFunctions,
+}
+
+// What the compiler writes out
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    AstJson,
+    #[default]
+    Wat,
+    Wasm,
+    // Reformatted source, for `--fmt`
+    Js,
+    Docs,
+}
+
+impl Emit {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "ast-json" => Some(Emit::AstJson),
+            "wat" => Some(Emit::Wat),
+            "wasm" => Some(Emit::Wasm),
+            "docs" => Some(Emit::Docs),
+            _ => None,
+        }
+    }
+
+    // File extension used when the output path is derived from the input
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Emit::Tokens => "tokens",
+            Emit::Ast => "ast",
+            Emit::AstJson => "json",
+            Emit::Wat => "wat",
+            Emit::Wasm => "wasm",
+            Emit::Js => "js",
+            Emit::Docs => "md",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileOptions {
+    pub opt_level: OptLevel,
+    pub passes: Vec<Pass>,
+    // Name coalesced slots after the variables they hold instead of `$r0`, `$r1`, ...
+    pub keep_local_names: bool,
+    // Calls with the wrong number of arguments behave as in JavaScript: missing arguments are 0
+    // (our `undefined`) and extra ones are evaluated and dropped. Otherwise they're an error.
+    pub loose_arity: bool,
+    // Values carry their type at runtime (see value.rs), so strings, `typeof` and `===` between
+    // different types work. Numbers are doubles instead of `i32`.
+    pub dynamic: bool,
+    pub lints: LintConfig,
+}
+
+impl CompileOptions {
+    pub fn new(opt_level: OptLevel) -> Self {
+        CompileOptions { opt_level, passes: opt_level.passes(), keep_local_names: false, loose_arity: false, dynamic: false, lints: LintConfig::default() }
+    }
+
+    pub fn has_pass(&self, pass: Pass) -> bool {
+        self.passes.contains(&pass)
+    }
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self::new(OptLevel::default())
+    }
+}
@@ -1,203 +1,429 @@
-use crate::token::{Token, SpannedToken};
+use crate::token::{Token, SpannedToken, Span, Trivia};
 use crate::lexer::Lexer;
-use crate::ast::{Program, Statement, Expression, BinaryOp, UnaryOp};
+use crate::ast::{Program, Statement, StatementKind, SwitchCase, Expression, ExpressionKind, Ident, BinaryOp, UnaryOp, UpdateOp};
+use crate::error::{CompileError, ErrorKind, Result};
 
 pub struct Parser {
     lexer: Lexer,
     current_token: SpannedToken,
+    // Span of the last consumed token, i.e. where the node being built ends
+    previous_span: Span,
 }
 
 impl Parser {
-    pub fn new(mut lexer: Lexer) -> Self {
-        let current_token = lexer.next_token();
-        Parser {
+    pub fn new(mut lexer: Lexer) -> Result<Self> {
+        let current_token = lexer.next_token()?;
+        Ok(Parser {
             lexer,
             current_token,
-        }
+            previous_span: Span::default(),
+        })
+    }
+
+    // See `Lexer::comments`
+    pub fn comments(&self) -> &[Trivia] {
+        self.lexer.comments()
     }
 
-    fn advance(&mut self) {
-        self.current_token = self.lexer.next_token();
+    fn advance(&mut self) -> Result<()> {
+        let next = self.lexer.next_token()?;
+        let previous = std::mem::replace(&mut self.current_token, next);
+        self.previous_span = previous.span;
+        Ok(())
     }
 
-    fn consume_identifier(&mut self) -> String {
+    // Span from `start` up to the end of the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.previous_span)
+    }
+
+    fn consume_identifier(&mut self) -> Result<Ident> {
         match &self.current_token.token {
             Token::Identifier(s) => {
-                let name = s.clone();
-                self.advance();
-                name
+                let ident = Ident { name: s.clone(), span: self.current_token.span };
+                self.advance()?;
+                Ok(ident)
             }
-            _ => self.error(format!("Expected identifier, found {:?}", self.current_token.token)),
+            _ => self.error("E0101", format!("Expected identifier, found {}", self.current_token.token), "expected an identifier"),
         }
     }
 
-    fn consume(&mut self, expected: Token) {
+    fn consume(&mut self, expected: Token) -> Result<()> {
         if std::mem::discriminant(&self.current_token.token) == std::mem::discriminant(&expected) {
-            self.advance();
+            self.advance()
         } else {
-            self.error(format!("Expected {:?}, found {:?}", expected, self.current_token.token));
+            let message = format!("Expected {}, found {}", expected, self.current_token.token);
+            let label = format!("expected {}", expected);
+            if expected == Token::Semi {
+                // A missing `;` belongs at the end of the statement, not on whatever follows it
+                let end = Span {
+                    line: self.previous_span.end_line,
+                    column: self.previous_span.end_column,
+                    start: self.previous_span.end,
+                    ..self.previous_span
+                };
+                let err = CompileError::new(ErrorKind::Parser, message, Some(end))
+                    .with_code("E0100")
+                    .with_label(label)
+                    .with_fix("insert `;`", end, ";");
+                return Err(err);
+            }
+            self.error("E0100", message, &label)
+        }
+    }
+
+    // Whether a statement can end here without a `;`: before a `}`, at the end of the input,
+    // or at a line break. Together with `semicolon` this is automatic semicolon insertion.
+    fn at_statement_end(&self) -> bool {
+        matches!(self.current_token.token, Token::Semi | Token::RBrace | Token::EOF) || self.current_token.newline_before
+    }
+
+    // The `;` ending a statement, which may be left out where `at_statement_end` allows
+    fn semicolon(&mut self) -> Result<()> {
+        match self.current_token.token {
+            Token::Semi => self.advance(),
+            _ if self.at_statement_end() => Ok(()),
+            _ => self.consume(Token::Semi),
         }
     }
 
-    fn error(&self, message: String) -> ! {
-        panic!(
-            "Error at line {}, column {}: {}",
-            self.current_token.span.line,
-            self.current_token.span.column,
-            message
-        );
+    fn error<T>(&self, code: &'static str, message: String, label: &str) -> Result<T> {
+        let err = CompileError::new(ErrorKind::Parser, message, Some(self.current_token.span))
+            .with_code(code)
+            .with_label(label);
+        Err(err)
     }
 
-    pub fn parse_program(&mut self) -> Program {
+    pub fn parse_program(&mut self) -> Result<Program> {
         let mut body = Vec::new();
         while self.current_token.token != Token::EOF {
-            body.push(self.parse_statement());
+            body.push(self.parse_statement()?);
         }
-        Program { body }
+        Ok(Program { body })
     }
 
-    fn parse_statement(&mut self) -> Statement {
+    // Leading trivia goes to the innermost statement that starts at the token it's attached to
+    fn parse_statement(&mut self) -> Result<Statement> {
+        let leading = std::mem::take(&mut self.current_token.leading);
+        let mut stmt = self.parse_statement_kind()?;
+        stmt.leading = leading;
+        Ok(stmt)
+    }
+
+    fn parse_statement_kind(&mut self) -> Result<Statement> {
         match self.current_token.token {
             Token::Let => self.parse_variable_declaration(false),
             Token::Const => self.parse_variable_declaration(true),
             Token::Function => self.parse_function_declaration(),
             Token::If => self.parse_if_statement(),
             Token::While => self.parse_while_statement(),
+            Token::Do => self.parse_do_while_statement(),
+            Token::Switch => self.parse_switch_statement(),
+            Token::Break | Token::Continue => self.parse_jump_statement(),
             Token::Return => self.parse_return_statement(),
             Token::LBrace => {
-                self.advance(); // consume '{'
-                let block = self.parse_block();
-                Statement::Block(block)
+                let start = self.current_token.span;
+                self.advance()?; // consume '{'
+                let block = self.parse_block()?;
+                Ok(Statement::new(StatementKind::Block(block), self.span_from(start)))
             }
             _ => self.parse_expression_statement(),
         }
     }
 
-    fn parse_variable_declaration(&mut self, is_const: bool) -> Statement {
-        self.advance(); // consume 'let' or 'const'
-        let name = self.consume_identifier();
-        self.consume(Token::Eq);
-        let init = self.parse_expression();
-        self.consume(Token::Semi);
-        Statement::VariableDeclaration { name, init, is_const }
+    fn parse_variable_declaration(&mut self, is_const: bool) -> Result<Statement> {
+        let start = self.current_token.span;
+        self.advance()?; // consume 'let' or 'const'
+        let name = self.consume_identifier()?;
+        self.consume(Token::Eq)?;
+        let init = self.parse_assignment()?;
+        self.semicolon()?;
+        Ok(Statement::new(StatementKind::VariableDeclaration { name, init, is_const }, self.span_from(start)))
     }
 
-    fn parse_function_declaration(&mut self) -> Statement {
-        self.advance(); // consume 'function'
-        let name = self.consume_identifier();
-        self.consume(Token::LParen);
+    fn parse_function_declaration(&mut self) -> Result<Statement> {
+        let start = self.current_token.span;
+        self.advance()?; // consume 'function'
+        let name = self.consume_identifier()?;
+        self.consume(Token::LParen)?;
         
         let mut params = Vec::new();
         if self.current_token.token != Token::RParen {
             loop {
-                params.push(self.consume_identifier());
+                params.push(self.consume_identifier()?);
                 if self.current_token.token == Token::Comma {
-                    self.advance();
+                    self.advance()?;
                 } else {
                     break;
                 }
             }
         }
-        self.consume(Token::RParen);
-        self.consume(Token::LBrace);
-        let body = self.parse_block();
+        self.consume(Token::RParen)?;
+        self.consume(Token::LBrace)?;
+        let body = self.parse_block()?;
         
-        Statement::FunctionDeclaration { name, params, body }
+        Ok(Statement::new(StatementKind::FunctionDeclaration { name, params, body }, self.span_from(start)))
     }
 
-    fn parse_block(&mut self) -> Vec<Statement> {
+    fn parse_block(&mut self) -> Result<Vec<Statement>> {
         let mut statements = Vec::new();
         while self.current_token.token != Token::RBrace && self.current_token.token != Token::EOF {
-            statements.push(self.parse_statement());
+            statements.push(self.parse_statement()?);
         }
-        self.consume(Token::RBrace);
-        statements
+        self.consume(Token::RBrace)?;
+        Ok(statements)
     }
 
-    fn parse_if_statement(&mut self) -> Statement {
-        self.advance(); // consume 'if'
-        self.consume(Token::LParen);
-        let condition = self.parse_expression();
-        self.consume(Token::RParen);
+    fn parse_if_statement(&mut self) -> Result<Statement> {
+        let start = self.current_token.span;
+        self.advance()?; // consume 'if'
+        self.consume(Token::LParen)?;
+        let condition = self.parse_expression()?;
+        self.consume(Token::RParen)?;
         
-        let then_branch = Box::new(self.parse_statement());
+        let then_branch = Box::new(self.parse_statement()?);
         let else_branch = if self.current_token.token == Token::Else {
-            self.advance();
-            Some(Box::new(self.parse_statement()))
+            self.advance()?;
+            Some(Box::new(self.parse_statement()?))
         } else {
             None
         };
 
-        Statement::If { condition, then_branch, else_branch }
+        Ok(Statement::new(StatementKind::If { condition, then_branch, else_branch }, self.span_from(start)))
+    }
+
+    fn parse_while_statement(&mut self) -> Result<Statement> {
+        let start = self.current_token.span;
+        self.advance()?; // consume 'while'
+        self.consume(Token::LParen)?;
+        let condition = self.parse_expression()?;
+        self.consume(Token::RParen)?;
+        let body = Box::new(self.parse_statement()?);
+        Ok(Statement::new(StatementKind::While { condition, body }, self.span_from(start)))
+    }
+
+    fn parse_do_while_statement(&mut self) -> Result<Statement> {
+        let start = self.current_token.span;
+        self.advance()?; // consume 'do'
+        let body = Box::new(self.parse_statement()?);
+        self.consume(Token::While)?;
+        self.consume(Token::LParen)?;
+        let condition = self.parse_expression()?;
+        self.consume(Token::RParen)?;
+        // The `;` after `do ... while (...)` is optional even on the same line
+        if self.current_token.token == Token::Semi {
+            self.advance()?;
+        }
+        Ok(Statement::new(StatementKind::DoWhile { body, condition }, self.span_from(start)))
+    }
+
+    fn parse_switch_statement(&mut self) -> Result<Statement> {
+        let start = self.current_token.span;
+        self.advance()?; // consume 'switch'
+        self.consume(Token::LParen)?;
+        let discriminant = self.parse_expression()?;
+        self.consume(Token::RParen)?;
+        self.consume(Token::LBrace)?;
+
+        let mut cases: Vec<SwitchCase> = Vec::new();
+        while self.current_token.token != Token::RBrace && self.current_token.token != Token::EOF {
+            let case_start = self.current_token.span;
+            let test = match self.current_token.token {
+                Token::Case => {
+                    self.advance()?;
+                    Some(self.parse_expression()?)
+                }
+                Token::Default => {
+                    if cases.iter().any(|case| case.test.is_none()) {
+                        return self.error("E0105", "Multiple `default` clauses in switch".to_string(), "second `default`");
+                    }
+                    self.advance()?;
+                    None
+                }
+                _ => return self.error("E0100", format!("Expected `case` or `default`, found {}", self.current_token.token), "expected `case` or `default`"),
+            };
+            self.consume(Token::Colon)?;
+
+            let mut body = Vec::new();
+            while !matches!(self.current_token.token, Token::Case | Token::Default | Token::RBrace | Token::EOF) {
+                body.push(self.parse_statement()?);
+            }
+            cases.push(SwitchCase { test, body, span: self.span_from(case_start) });
+        }
+        self.consume(Token::RBrace)?;
+        Ok(Statement::new(StatementKind::Switch { discriminant, cases }, self.span_from(start)))
     }
 
-    fn parse_while_statement(&mut self) -> Statement {
-        self.advance(); // consume 'while'
-        self.consume(Token::LParen);
-        let condition = self.parse_expression();
-        self.consume(Token::RParen);
-        let body = Box::new(self.parse_statement());
-        Statement::While { condition, body }
+    // `break` or `continue`, with an optional label
+    fn parse_jump_statement(&mut self) -> Result<Statement> {
+        let start = self.current_token.span;
+        let is_break = self.current_token.token == Token::Break;
+        self.advance()?;
+        // A label must be on the same line: `break\nfoo` is `break; foo`
+        let label = match self.current_token.token {
+            Token::Identifier(_) if !self.current_token.newline_before => Some(self.consume_identifier()?),
+            _ => None,
+        };
+        self.semicolon()?;
+        let kind = if is_break { StatementKind::Break(label) } else { StatementKind::Continue(label) };
+        Ok(Statement::new(kind, self.span_from(start)))
     }
 
-    fn parse_return_statement(&mut self) -> Statement {
-        self.advance(); // consume 'return'
-        let value = if self.current_token.token == Token::Semi {
+    fn parse_return_statement(&mut self) -> Result<Statement> {
+        let start = self.current_token.span;
+        self.advance()?; // consume 'return'
+        // `return\nx` returns nothing and leaves `x` as the next statement
+        let value = if self.at_statement_end() {
             None
         } else {
-            Some(self.parse_expression())
+            Some(self.parse_expression()?)
         };
-        self.consume(Token::Semi);
-        Statement::Return(value)
+        self.semicolon()?;
+        Ok(Statement::new(StatementKind::Return(value), self.span_from(start)))
     }
 
-    fn parse_expression_statement(&mut self) -> Statement {
-        let expr = self.parse_expression();
-        self.consume(Token::Semi);
-        Statement::Expression(expr)
+    fn parse_expression_statement(&mut self) -> Result<Statement> {
+        let start = self.current_token.span;
+        let expr = self.parse_expression()?;
+
+        // `name:` starts a labeled statement. `(name):` doesn't.
+        if let ExpressionKind::Identifier(name) = &expr.kind && expr.span == start && self.current_token.token == Token::Colon {
+            let label = Ident { name: name.clone(), span: expr.span };
+            self.advance()?;
+            let body = Box::new(self.parse_statement()?);
+            return Ok(Statement::new(StatementKind::Labeled { label, body }, self.span_from(start)));
+        }
+        self.semicolon()?;
+        Ok(Statement::new(StatementKind::Expression(expr), self.span_from(start)))
     }
 
     // Expression Parsing (Precedence Climbing)
 
-    fn parse_expression(&mut self) -> Expression {
-        self.parse_assignment()
+    // The comma operator. Where a comma means something else (arguments, initializers), use
+    // `parse_assignment`.
+    fn parse_expression(&mut self) -> Result<Expression> {
+        let first = self.parse_assignment()?;
+        if self.current_token.token != Token::Comma {
+            return Ok(first);
+        }
+
+        let start = first.span;
+        let mut exprs = vec![first];
+        while self.current_token.token == Token::Comma {
+            self.advance()?;
+            exprs.push(self.parse_assignment()?);
+        }
+        Ok(Expression::new(ExpressionKind::Sequence(exprs), self.span_from(start)))
     }
 
-    fn parse_assignment(&mut self) -> Expression {
-        let expr = self.parse_equality();
-        
-        if self.current_token.token == Token::Eq {
-            self.advance();
-            let value = self.parse_assignment(); // Right-associative
+    fn parse_assignment(&mut self) -> Result<Expression> {
+        let expr = self.parse_conditional()?;
+
+        let compound = compound_op(&self.current_token.token);
+        if self.current_token.token == Token::Eq || compound.is_some() {
+            self.advance()?;
+            let value = Box::new(self.parse_assignment()?); // Right-associative
             
-            if let Expression::Identifier(name) = expr {
-                return Expression::Assignment(name, Box::new(value));
-            } else {
-                self.error(format!("Invalid assignment target: {:?}", expr));
-            }
+            let span = expr.span.to(value.span);
+            let target = Self::assignment_target(expr)?;
+            let kind = match compound {
+                Some(op) => ExpressionKind::CompoundAssignment(target, op, value),
+                None => ExpressionKind::Assignment(target, value),
+            };
+            return Ok(Expression::new(kind, span));
         }
         
-        expr
+        Ok(expr)
+    }
+
+    // The variable an assignment or `++`/`--` writes to
+    fn assignment_target(expr: Expression) -> Result<Ident> {
+        if let ExpressionKind::Identifier(name) = expr.kind {
+            Ok(Ident { name, span: expr.span })
+        } else {
+            let err = CompileError::new(ErrorKind::Parser, "Invalid assignment target".to_string(), Some(expr.span))
+                .with_code("E0103")
+                .with_label("cannot assign to this expression")
+                .with_help("only variables can be assigned to");
+            Err(err)
+        }
+    }
+
+    // Builds `left op right`, spanning both operands
+    fn binary(left: Expression, op: BinaryOp, right: Expression) -> Expression {
+        let span = left.span.to(right.span);
+        Expression::new(ExpressionKind::Binary(Box::new(left), op, Box::new(right)), span)
+    }
+
+    // Right-associative: `a ? b : c ? d : e` is `a ? b : (c ? d : e)`. Either arm may be an assignment.
+    fn parse_conditional(&mut self) -> Result<Expression> {
+        let condition = self.parse_bitwise_or()?;
+        if self.current_token.token != Token::Question {
+            return Ok(condition);
+        }
+
+        self.advance()?;
+        let then = self.parse_assignment()?;
+        self.consume(Token::Colon)?;
+        let otherwise = self.parse_assignment()?;
+        let span = condition.span.to(otherwise.span);
+        Ok(Expression::new(ExpressionKind::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)), span))
+    }
+
+    // `|`, `^` and `&` bind looser than comparisons, as in JavaScript (and C)
+    fn parse_bitwise_or(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_bitwise_xor()?;
+
+        while self.current_token.token == Token::Pipe {
+            self.advance()?;
+            let right = self.parse_bitwise_xor()?;
+            expr = Self::binary(expr, BinaryOp::BitOr, right);
+        }
+        Ok(expr)
+    }
+
+    fn parse_bitwise_xor(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_bitwise_and()?;
+
+        while self.current_token.token == Token::Caret {
+            self.advance()?;
+            let right = self.parse_bitwise_and()?;
+            expr = Self::binary(expr, BinaryOp::BitXor, right);
+        }
+        Ok(expr)
     }
 
-    fn parse_equality(&mut self) -> Expression {
-        let mut expr = self.parse_comparison();
+    fn parse_bitwise_and(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_equality()?;
 
-        while matches!(self.current_token.token, Token::EqEq | Token::BangEq) {
+        while self.current_token.token == Token::Amp {
+            self.advance()?;
+            let right = self.parse_equality()?;
+            expr = Self::binary(expr, BinaryOp::BitAnd, right);
+        }
+        Ok(expr)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_comparison()?;
+
+        while matches!(self.current_token.token, Token::EqEq | Token::BangEq | Token::EqEqEq | Token::BangEqEq) {
             let op = match self.current_token.token {
                 Token::EqEq => BinaryOp::Eq,
                 Token::BangEq => BinaryOp::Ne,
+                Token::EqEqEq => BinaryOp::StrictEq,
+                Token::BangEqEq => BinaryOp::StrictNe,
                 _ => unreachable!(),
             };
-            self.advance();
-            let right = self.parse_comparison();
-            expr = Expression::Binary(Box::new(expr), op, Box::new(right));
+            self.advance()?;
+            let right = self.parse_comparison()?;
+            expr = Self::binary(expr, op, right);
         }
-        expr
+        Ok(expr)
     }
 
-    fn parse_comparison(&mut self) -> Expression {
-        let mut expr = self.parse_term();
+    fn parse_comparison(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_shift()?;
 
         while matches!(self.current_token.token, Token::Lt | Token::LtEq | Token::Gt | Token::GtEq) {
             let op = match self.current_token.token {
@@ -207,15 +433,32 @@ impl Parser {
                 Token::GtEq => BinaryOp::Ge,
                 _ => unreachable!(),
             };
-            self.advance();
-            let right = self.parse_term();
-            expr = Expression::Binary(Box::new(expr), op, Box::new(right));
+            self.advance()?;
+            let right = self.parse_shift()?;
+            expr = Self::binary(expr, op, right);
+        }
+        Ok(expr)
+    }
+
+    fn parse_shift(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_term()?;
+
+        while matches!(self.current_token.token, Token::Shl | Token::Shr | Token::UShr) {
+            let op = match self.current_token.token {
+                Token::Shl => BinaryOp::Shl,
+                Token::Shr => BinaryOp::Shr,
+                Token::UShr => BinaryOp::UShr,
+                _ => unreachable!(),
+            };
+            self.advance()?;
+            let right = self.parse_term()?;
+            expr = Self::binary(expr, op, right);
         }
-        expr
+        Ok(expr)
     }
 
-    fn parse_term(&mut self) -> Expression {
-        let mut expr = self.parse_factor();
+    fn parse_term(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_factor()?;
 
         while matches!(self.current_token.token, Token::Plus | Token::Minus) {
             let op = match self.current_token.token {
@@ -223,15 +466,15 @@ impl Parser {
                 Token::Minus => BinaryOp::Sub,
                 _ => unreachable!(),
             };
-            self.advance();
-            let right = self.parse_factor();
-            expr = Expression::Binary(Box::new(expr), op, Box::new(right));
+            self.advance()?;
+            let right = self.parse_factor()?;
+            expr = Self::binary(expr, op, right);
         }
-        expr
+        Ok(expr)
     }
 
-    fn parse_factor(&mut self) -> Expression {
-        let mut expr = self.parse_unary();
+    fn parse_factor(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_exponent()?;
 
         while matches!(self.current_token.token, Token::Star | Token::Slash | Token::Percent) {
             let op = match self.current_token.token {
@@ -240,64 +483,183 @@ impl Parser {
                 Token::Percent => BinaryOp::Mod,
                 _ => unreachable!(),
             };
-            self.advance();
-            let right = self.parse_unary();
-            expr = Expression::Binary(Box::new(expr), op, Box::new(right));
+            self.advance()?;
+            let right = self.parse_exponent()?;
+            expr = Self::binary(expr, op, right);
         }
-        expr
+        Ok(expr)
     }
 
-    fn parse_unary(&mut self) -> Expression {
-        if matches!(self.current_token.token, Token::Bang | Token::Minus) {
+    // `**` is right-associative and binds tighter than unary operators on its right, but
+    // JavaScript rejects one on its left: `-2 ** 2` could mean either grouping.
+    fn parse_exponent(&mut self) -> Result<Expression> {
+        let unary = matches!(self.current_token.token, Token::Bang | Token::Minus | Token::Tilde | Token::Typeof);
+        let base = self.parse_unary()?;
+        if self.current_token.token != Token::StarStar {
+            return Ok(base);
+        }
+        if unary {
+            let err = CompileError::new(ErrorKind::Parser, "Unary operator before `**` needs parentheses".to_string(), Some(base.span))
+                .with_code("E0104")
+                .with_label("parenthesize this or the whole `**` expression")
+                .with_help("write `(-x) ** y` or `-(x ** y)`");
+            return Err(err);
+        }
+
+        self.advance()?;
+        let exponent = self.parse_exponent()?;
+        Ok(Self::binary(base, BinaryOp::Pow, exponent))
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression> {
+        if let Some(op) = update_op(&self.current_token.token) {
+            let start = self.current_token.span;
+            self.advance()?;
+            let operand = self.parse_unary()?;
+            let span = start.to(operand.span);
+            let target = Self::assignment_target(operand)?;
+            return Ok(Expression::new(ExpressionKind::Update { target, op, prefix: true }, span));
+        }
+        if matches!(self.current_token.token, Token::Bang | Token::Minus | Token::Tilde | Token::Typeof) {
+            let start = self.current_token.span;
             let op = match self.current_token.token {
                 Token::Bang => UnaryOp::Not,
                 Token::Minus => UnaryOp::Neg,
+                Token::Tilde => UnaryOp::BitNot,
+                Token::Typeof => UnaryOp::Typeof,
                 _ => unreachable!(),
             };
-            self.advance();
-            let right = self.parse_unary();
-            return Expression::Unary(op, Box::new(right));
+            self.advance()?;
+            // The one literal that's only in range when negated
+            if op == UnaryOp::Neg && self.current_token.token == Token::Number(2147483648.0) {
+                self.advance()?;
+                return Ok(Expression::new(ExpressionKind::Number(-2147483648.0), self.span_from(start)));
+            }
+            let right = self.parse_unary()?;
+            let span = start.to(right.span);
+            return Ok(Expression::new(ExpressionKind::Unary(op, Box::new(right)), span));
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expression> {
+        let mut expr = self.parse_primary()?;
+
+        // `a\n++b` is `a; ++b`, not `a++; b`
+        while let Some(op) = update_op(&self.current_token.token).filter(|_| !self.current_token.newline_before) {
+            self.advance()?;
+            let span = self.span_from(expr.span);
+            let target = Self::assignment_target(expr)?;
+            expr = Expression::new(ExpressionKind::Update { target, op, prefix: false }, span);
         }
-        self.parse_primary()
+        Ok(expr)
     }
 
-    fn parse_primary(&mut self) -> Expression {
+    fn parse_primary(&mut self) -> Result<Expression> {
+        let start = self.current_token.span;
         match &self.current_token.token {
             Token::Number(n) => {
                 let val = *n;
-                self.advance();
-                Expression::Number(val)
+                if val > i32::MAX as f64 && !self.lexer.doubles() {
+                    let err = CompileError::new(ErrorKind::Lexer, format!("Number literal out of range: {}", n), Some(start))
+                        .with_code("E0002")
+                        .with_help("without `--dynamic`, number literals must fit in an i32, from -2147483648 to 2147483647");
+                    return Err(err);
+                }
+                self.advance()?;
+                Ok(Expression::new(ExpressionKind::Number(val), start))
+            }
+            Token::String(s) => {
+                let kind = ExpressionKind::String(s.clone());
+                self.advance()?;
+                Ok(Expression::new(kind, start))
+            }
+            Token::True | Token::False | Token::Null | Token::Undefined => {
+                let kind = match self.current_token.token {
+                    Token::True => ExpressionKind::Boolean(true),
+                    Token::False => ExpressionKind::Boolean(false),
+                    Token::Null => ExpressionKind::Null,
+                    _ => ExpressionKind::Undefined,
+                };
+                self.advance()?;
+                Ok(Expression::new(kind, start))
             }
             Token::Identifier(s) => {
                 let name = s.clone();
-                self.advance();
-                
+                self.advance()?;
+
+                // `Math.abs(x)` is a call to a builtin named `Math.abs`
+                if name == "Math" && self.current_token.token == Token::Dot {
+                    self.advance()?;
+                    let member = self.consume_identifier()?;
+                    let callee = Ident { name: format!("Math.{}", member.name), span: start.to(member.span) };
+                    self.consume(Token::LParen)?;
+                    let args = self.parse_arguments()?;
+                    return Ok(Expression::new(ExpressionKind::Call(callee, args), self.span_from(start)));
+                }
+
                 if self.current_token.token == Token::LParen {
-                    self.advance();
-                    let mut args = Vec::new();
-                    if self.current_token.token != Token::RParen {
-                        loop {
-                            args.push(self.parse_expression());
-                            if self.current_token.token == Token::Comma {
-                                self.advance();
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                    self.consume(Token::RParen);
-                    Expression::Call(name, args)
+                    self.advance()?;
+                    let args = self.parse_arguments()?;
+                    let callee = Ident { name, span: start };
+                    Ok(Expression::new(ExpressionKind::Call(callee, args), self.span_from(start)))
                 } else {
-                    Expression::Identifier(name)
+                    Ok(Expression::new(ExpressionKind::Identifier(name), start))
                 }
             }
             Token::LParen => {
-                self.advance();
-                let expr = self.parse_expression();
-                self.consume(Token::RParen);
-                expr
+                self.advance()?;
+                let mut expr = self.parse_expression()?;
+                self.consume(Token::RParen)?;
+                // The parentheses belong to the expression's source range
+                expr.span = self.span_from(start);
+                Ok(expr)
+            }
+            _ => self.error("E0102", format!("Expected expression, found {}", self.current_token.token), "expected an expression"),
+        }
+    }
+
+    // Call arguments after the `(`, up to and including the `)`
+    fn parse_arguments(&mut self) -> Result<Vec<Expression>> {
+        let mut args = Vec::new();
+        if self.current_token.token != Token::RParen {
+            loop {
+                args.push(self.parse_assignment()?);
+                if self.current_token.token == Token::Comma {
+                    self.advance()?;
+                } else {
+                    break;
+                }
             }
-            _ => self.error(format!("Expected expression, found {:?}", self.current_token.token)),
         }
+        self.consume(Token::RParen)?;
+        Ok(args)
+    }
+}
+
+// The operator of a compound assignment token like `&=`
+fn compound_op(token: &Token) -> Option<BinaryOp> {
+    match token {
+        Token::PlusEq => Some(BinaryOp::Add),
+        Token::MinusEq => Some(BinaryOp::Sub),
+        Token::StarEq => Some(BinaryOp::Mul),
+        Token::StarStarEq => Some(BinaryOp::Pow),
+        Token::SlashEq => Some(BinaryOp::Div),
+        Token::PercentEq => Some(BinaryOp::Mod),
+        Token::AmpEq => Some(BinaryOp::BitAnd),
+        Token::PipeEq => Some(BinaryOp::BitOr),
+        Token::CaretEq => Some(BinaryOp::BitXor),
+        Token::ShlEq => Some(BinaryOp::Shl),
+        Token::ShrEq => Some(BinaryOp::Shr),
+        Token::UShrEq => Some(BinaryOp::UShr),
+        _ => None,
+    }
+}
+
+fn update_op(token: &Token) -> Option<UpdateOp> {
+    match token {
+        Token::PlusPlus => Some(UpdateOp::Increment),
+        Token::MinusMinus => Some(UpdateOp::Decrement),
+        _ => None,
     }
 }
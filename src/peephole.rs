@@ -0,0 +1,114 @@
+use crate::wasm::{Instruction, Module};
+use std::fmt;
+
+// Peephole optimizer. Slides a small window over every instruction list in the
+// module and rewrites wasteful sequences that codegen produces, e.g.
+//
+//   local.set $x, local.get $x  ->  local.tee $x
+//   i32.const 0, i32.const 5, i32.sub  ->  i32.const -5
+//   i32.lt_s, i32.eqz  ->  i32.ge_s
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PeepholeStats {
+    pub before: usize,
+    pub after: usize,
+}
+
+impl PeepholeStats {
+    pub fn removed(&self) -> usize {
+        self.before - self.after
+    }
+}
+
+impl fmt::Display for PeepholeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {} instructions ({} removed)", self.before, self.after, self.removed())
+    }
+}
+
+pub fn optimize(module: &mut Module) -> PeepholeStats {
+    let before = module.instruction_count();
+    for func in &mut module.functions {
+        optimize_body(&mut func.body);
+    }
+    PeepholeStats { before, after: module.instruction_count() }
+}
+
+pub fn optimize_body(body: &mut Vec<Instruction>) {
+    // Inner bodies first so their rewrites are visible to the outer window
+    for instr in body.iter_mut() {
+        match instr {
+            Instruction::Block { body, .. } | Instruction::Loop { body, .. } => optimize_body(body),
+            Instruction::If { then_body, else_body, .. } => {
+                optimize_body(then_body);
+                if let Some(else_body) = else_body {
+                    optimize_body(else_body);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Keep applying rules until nothing changes; each rewrite shrinks the list so this terminates
+    while rewrite_once(body) {}
+}
+
+// Applies the first matching rule. Returns false when the body is already optimal.
+fn rewrite_once(body: &mut Vec<Instruction>) -> bool {
+    use Instruction::*;
+
+    for i in 0..body.len() {
+        // Everything after an unconditional branch is unreachable
+        if matches!(body[i], Return | Br(_) | BrTable { .. }) && i + 1 < body.len() {
+            body.truncate(i + 1);
+            return true;
+        }
+
+        let window = &body[i..];
+        let (remove, replacement): (usize, Option<Instruction>) = match window {
+            // local.set $x, local.get $x -> local.tee $x
+            [LocalSet(a), LocalGet(b), ..] if a == b => (2, Some(LocalTee(a.clone()))),
+
+            // Copying a local onto itself
+            [LocalGet(a), LocalSet(b), ..] if a == b => (2, None),
+            [LocalGet(a), LocalTee(b), ..] if a == b => (2, Some(LocalGet(a.clone()))),
+
+            // A tee whose value is dropped is just a set
+            [LocalTee(name), Drop, ..] => (2, Some(LocalSet(name.clone()))),
+
+            // Pushing a value with no side effects only to drop it
+            [I32Const(_) | I64Const(_) | F64Const(_) | LocalGet(_), Drop, ..] => (2, None),
+
+            // Negating a literal: 0 - n
+            [I32Const(0), I32Const(n), I32Sub, ..] => (3, Some(I32Const(n.wrapping_neg()))),
+
+            // !!x where only truthiness matters
+            [I32Eqz, I32Eqz, BrIf(_) | If { .. } | I32Eqz, ..] => (2, None),
+
+            // !!x where x is already 0 or 1
+            [cmp, I32Eqz, I32Eqz, ..] if cmp.is_boolean() => (3, Some(cmp.clone())),
+
+            // Negated comparison -> inverse comparison
+            [cmp, I32Eqz, ..] if invert(cmp).is_some() => (2, invert(cmp)),
+
+            _ => continue,
+        };
+
+        body.splice(i..i + remove, replacement);
+        return true;
+    }
+    false
+}
+
+fn invert(cmp: &Instruction) -> Option<Instruction> {
+    use Instruction::*;
+    Some(match cmp {
+        I32Eq => I32Ne,
+        I32Ne => I32Eq,
+        I32LtS => I32GeS,
+        I32GeS => I32LtS,
+        I32GtS => I32LeS,
+        I32LeS => I32GtS,
+        _ => return None,
+    })
+}
@@ -0,0 +1,177 @@
+use crate::wasm::{Function, Instruction, Local, Module};
+use std::collections::HashMap;
+
+// Local slot reuse. Every `let` gets its own `$name_N` local from codegen, so a
+// function with many block-scoped temporaries declares dozens of locals. This
+// pass computes a live range for each local and lets locals whose ranges never
+// overlap share a single slot.
+//
+// Ranges are intervals over a linear numbering of the instructions. Control flow
+// is structured, so the only thing that can carry a value "backwards" is a loop:
+// any range that crosses a loop boundary is widened to cover the whole loop.
+
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    start: usize,
+    end: usize,
+}
+
+impl Range {
+    fn overlaps(&self, other: &Range) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    fn contains(&self, other: &Range) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+}
+
+// What a linear walk over a function body learns about its locals
+#[derive(Default)]
+struct Liveness {
+    ranges: HashMap<String, Range>,
+    // Locals whose first access (in linear order) reads the value
+    read_first: Vec<String>,
+    loops: Vec<Range>,
+    position: usize,
+}
+
+impl Liveness {
+    fn analyze(body: &[Instruction]) -> Self {
+        let mut liveness = Liveness::default();
+        liveness.walk(body);
+        for name in &liveness.read_first {
+            // It may observe the zero every local starts with, so keep it live from the start
+            if let Some(range) = liveness.ranges.get_mut(name) {
+                range.start = 0;
+            }
+        }
+        liveness.widen_over_loops();
+        liveness
+    }
+
+    fn walk(&mut self, body: &[Instruction]) {
+        for instr in body {
+            let position = self.position;
+            self.position += 1;
+            match instr {
+                Instruction::LocalGet(name) => self.access(name, position, true),
+                Instruction::LocalSet(name) | Instruction::LocalTee(name) => self.access(name, position, false),
+                Instruction::Block { body, .. } => self.walk(body),
+                Instruction::Loop { body, .. } => {
+                    self.walk(body);
+                    self.loops.push(Range { start: position, end: self.position - 1 });
+                }
+                Instruction::If { then_body, else_body, .. } => {
+                    self.walk(then_body);
+                    if let Some(else_body) = else_body {
+                        self.walk(else_body);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn access(&mut self, name: &str, position: usize, is_read: bool) {
+        match self.ranges.get_mut(name) {
+            Some(range) => range.end = position,
+            None => {
+                self.ranges.insert(name.to_string(), Range { start: position, end: position });
+                if is_read {
+                    self.read_first.push(name.to_string());
+                }
+            }
+        }
+    }
+
+    fn widen_over_loops(&mut self) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for range in self.ranges.values_mut() {
+                for lp in &self.loops {
+                    if range.overlaps(lp) && !lp.contains(range) && !range.contains(lp) {
+                        range.start = range.start.min(lp.start);
+                        range.end = range.end.max(lp.end);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn coalesce(module: &mut Module, keep_names: bool) {
+    for func in &mut module.functions {
+        coalesce_function(func, keep_names);
+    }
+}
+
+fn coalesce_function(func: &mut Function, keep_names: bool) {
+    let liveness = Liveness::analyze(&func.body);
+
+    // Locals that are never touched simply disappear
+    let mut live: Vec<(&Local, Range)> = func.locals.iter()
+        .filter_map(|local| liveness.ranges.get(&local.name).map(|range| (local, *range)))
+        .collect();
+    live.sort_by_key(|(_, range)| range.start);
+
+    // Linear scan: reuse the first slot of the same type that is already free
+    let mut slots: Vec<(Vec<String>, Local, usize)> = Vec::new(); // (members, slot, end of last member)
+    for (local, range) in live {
+        match slots.iter_mut().find(|(_, slot, end)| slot.ty == local.ty && *end < range.start) {
+            Some((members, _, end)) => {
+                members.push(local.name.clone());
+                *end = range.end;
+            }
+            None => slots.push((vec![local.name.clone()], local.clone(), range.end)),
+        }
+    }
+
+    let params: Vec<&str> = func.params.iter().map(|p| p.name.as_str()).collect();
+    let mut renames = HashMap::new();
+    let mut locals = Vec::new();
+    let mut next = 0;
+    for (members, slot, _) in slots {
+        let name = if keep_names {
+            // Readable for debugging: the slot is named after everything stored in it
+            members.join(".")
+        } else {
+            loop {
+                let candidate = format!("r{}", next);
+                next += 1;
+                if !params.contains(&candidate.as_str()) {
+                    break candidate;
+                }
+            }
+        };
+        for member in members {
+            renames.insert(member, name.clone());
+        }
+        locals.push(Local::new(&name, slot.ty));
+    }
+
+    rename(&mut func.body, &renames);
+    func.locals = locals;
+}
+
+fn rename(body: &mut [Instruction], renames: &HashMap<String, String>) {
+    for instr in body {
+        match instr {
+            Instruction::LocalGet(name) | Instruction::LocalSet(name) | Instruction::LocalTee(name) => {
+                if let Some(new_name) = renames.get(name) {
+                    *name = new_name.clone();
+                }
+            }
+            Instruction::Block { body, .. } | Instruction::Loop { body, .. } => rename(body, renames),
+            Instruction::If { then_body, else_body, .. } => {
+                rename(then_body, renames);
+                if let Some(else_body) = else_body {
+                    rename(else_body, renames);
+                }
+            }
+            _ => {}
+        }
+    }
+}
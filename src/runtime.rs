@@ -0,0 +1,790 @@
+use crate::value::{Strings, BOXED, FALSE, NULL, STRING, TRUE, UNDEFINED};
+use crate::wasm::{Function, Instruction, Local, ValType};
+
+// Runtime support for dynamic values (see value.rs), added to the module as wasm functions when
+// code calls them. Strings built at runtime go on a heap after the data segment, which only ever
+// grows: `heap` is the address of its first free byte.
+
+pub const HEAP: &str = "heap";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Runtime {
+    // value -> i32: whether `if` takes the value as true
+    Truthy,
+    // value -> f64
+    ToNumber,
+    // f64 -> i32, wrapping modulo 2^32 like the operands of `|`
+    ToInt32,
+    // value -> value: the string a value converts to
+    ToString,
+    // value, value -> i32: `===`
+    StrictEquals,
+    // value, value -> i32: `==`, which converts between types
+    LooseEquals,
+    // value, value -> value: `+`, which concatenates if either side is a string
+    Add,
+    // value, value -> i32: -1, 0 or 1 as the first value is below, equal to or above the
+    // second, and 2 if they are unordered (NaN). Strings compare by their bytes.
+    Compare,
+    // value -> value: the string `typeof` gives
+    Typeof,
+    // f64, f64 -> f64: `%`, which has no wasm instruction for doubles
+    Rem,
+    // f64, f64 -> f64: `**`. Only whole exponents are supported; others give NaN.
+    Pow,
+    // f64 -> f64: `Math.sign`
+    Sign,
+    // i64, i32 -> f64: m * 10^k rounded to the nearest double, for m below 10^19
+    ScaleDigits,
+    // f64, i32 -> i64: x * 10^k rounded to the nearest integer
+    RoundScaled,
+    // f64 -> value
+    NumberToString,
+    // i32 -> f64: the number a string (by address) converts to
+    StringToNumber,
+    // i32, i32 -> i32: whether two strings (by address) have the same text
+    StringEquals,
+    // i32, i32 -> i32: `Compare` for two strings (by address)
+    CompareStrings,
+    // string value, string value -> string value
+    Concat,
+    // i32, i64, i32 -> i32: writes the given number of decimal digits of the i64 to an
+    // address, and returns the address after them
+    WriteDigits,
+    // i32 -> i32: reserves that many bytes on the heap and returns their address
+    Alloc,
+}
+
+// Names and types of a function's params or locals
+type Locals = Vec<(&'static str, ValType)>;
+
+impl Runtime {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Runtime::Truthy => "runtime.truthy",
+            Runtime::ToNumber => "runtime.to_number",
+            Runtime::ToInt32 => "runtime.to_int32",
+            Runtime::ToString => "runtime.to_string",
+            Runtime::StrictEquals => "runtime.strict_equals",
+            Runtime::LooseEquals => "runtime.loose_equals",
+            Runtime::Add => "runtime.add",
+            Runtime::Compare => "runtime.compare",
+            Runtime::Typeof => "runtime.typeof",
+            Runtime::Rem => "runtime.rem",
+            Runtime::Pow => "runtime.pow",
+            Runtime::Sign => "runtime.sign",
+            Runtime::ScaleDigits => "runtime.scale_digits",
+            Runtime::RoundScaled => "runtime.round_scaled",
+            Runtime::NumberToString => "runtime.number_to_string",
+            Runtime::StringToNumber => "runtime.string_to_number",
+            Runtime::StringEquals => "runtime.string_equals",
+            Runtime::CompareStrings => "runtime.compare_strings",
+            Runtime::Concat => "runtime.concat",
+            Runtime::WriteDigits => "runtime.write_digits",
+            Runtime::Alloc => "runtime.alloc",
+        }
+    }
+
+    // The other runtime functions this one calls
+    pub fn calls(&self) -> &'static [Runtime] {
+        match self {
+            Runtime::ToNumber => &[Runtime::StringToNumber],
+            Runtime::ToString => &[Runtime::NumberToString],
+            Runtime::StrictEquals => &[Runtime::StringEquals],
+            Runtime::LooseEquals => &[Runtime::StrictEquals, Runtime::ToNumber],
+            Runtime::Add => &[Runtime::ToString, Runtime::Concat, Runtime::ToNumber],
+            Runtime::Compare => &[Runtime::CompareStrings, Runtime::ToNumber],
+            Runtime::ScaleDigits | Runtime::RoundScaled => &[Runtime::Pow],
+            Runtime::NumberToString => &[Runtime::ScaleDigits, Runtime::RoundScaled, Runtime::Alloc, Runtime::WriteDigits],
+            Runtime::StringToNumber => &[Runtime::ScaleDigits],
+            Runtime::Concat => &[Runtime::Alloc],
+            _ => &[],
+        }
+    }
+
+    pub fn function(&self, strings: &mut Strings) -> Function {
+        use Instruction::*;
+        use ValType::{F64, I32, I64};
+
+        let (params, result, locals, body): (Locals, ValType, Locals, Vec<Instruction>) = match self {
+            Runtime::Truthy => (vec![("v", I64)], I32, vec![], [
+                // Numbers other than 0 and NaN
+                is_number("v"),
+                vec![when(vec![
+                    get("v"), F64ReinterpretI64, F64Const(0.0), F64Ne,
+                    get("v"), F64ReinterpretI64, get("v"), F64ReinterpretI64, F64Eq,
+                    I32And, Return,
+                ])],
+                has_tag("v", FALSE),
+                vec![when(vec![get("v"), I32WrapI64, Return])],
+                // Strings that aren't empty
+                has_tag("v", STRING),
+                vec![when(vec![get("v"), I32WrapI64, I32Load(0), I32Const(0), I32Ne, Return])],
+                // undefined and null
+                vec![I32Const(0)],
+            ].concat()),
+            Runtime::ToNumber => (vec![("v", I64)], F64, vec![], [
+                is_number("v"),
+                vec![when(vec![get("v"), F64ReinterpretI64, Return])],
+                has_tag("v", STRING),
+                vec![when(vec![get("v"), I32WrapI64, call(Runtime::StringToNumber), Return])],
+                vec![get("v"), I64Const(NULL), I64Eq, when(vec![F64Const(0.0), Return])],
+                has_tag("v", FALSE),
+                vec![when(vec![get("v"), I32WrapI64, F64ConvertI32U, Return])],
+                // undefined
+                vec![F64Const(f64::NAN)],
+            ].concat()),
+            // x - floor(x / 2^32) * 2^32 of the truncated value is exact, and NaN for NaN and the infinities
+            Runtime::ToInt32 => (vec![("x", F64)], I32, vec![], vec![
+                get("x"), F64Trunc, set("x"),
+                get("x"), get("x"), F64Const(4294967296.0), F64Div, F64Floor, F64Const(4294967296.0), F64Mul, F64Sub,
+                I32TruncSatF64U,
+            ]),
+            Runtime::ToString => (vec![("v", I64)], I64, vec![], [
+                is_number("v"),
+                vec![when(vec![get("v"), F64ReinterpretI64, call(Runtime::NumberToString), Return])],
+                has_tag("v", STRING),
+                vec![when(vec![get("v"), Return])],
+                vec![get("v"), I64Const(UNDEFINED), I64Eq, when(vec![I64Const(strings.value("undefined")), Return])],
+                vec![get("v"), I64Const(NULL), I64Eq, when(vec![I64Const(strings.value("null")), Return])],
+                vec![get("v"), I64Const(TRUE), I64Eq, when(vec![I64Const(strings.value("true")), Return])],
+                vec![I64Const(strings.value("false"))],
+            ].concat()),
+            // Numbers compare as doubles (NaN isn't equal to itself, 0 is -0), strings by their text
+            // and everything else by its bits
+            Runtime::StrictEquals => (vec![("a", I64), ("b", I64)], I32, vec![], [
+                is_number("a"),
+                is_number("b"),
+                vec![
+                    I32And,
+                    when(vec![get("a"), F64ReinterpretI64, get("b"), F64ReinterpretI64, F64Eq, Return]),
+                    get("a"), get("b"), I64Eq,
+                    when(vec![I32Const(1), Return]),
+                ],
+                has_tag("a", STRING),
+                has_tag("b", STRING),
+                vec![
+                    I32And,
+                    when(vec![get("a"), I32WrapI64, get("b"), I32WrapI64, call(Runtime::StringEquals), Return]),
+                    I32Const(0),
+                ],
+            ].concat()),
+            // Values of the same type compare like `===`. `null` and `undefined` only equal each
+            // other, and any other mix of numbers, booleans and strings compares as numbers.
+            Runtime::LooseEquals => (vec![("a", I64), ("b", I64)], I32, vec![], [
+                vec![get("a"), get("b"), call(Runtime::StrictEquals), when(vec![I32Const(1), Return])],
+                is_nullish("a"),
+                is_nullish("b"),
+                vec![I32Or],
+                vec![when([is_nullish("a"), is_nullish("b"), vec![I32And, Return]].concat())],
+                has_tag("a", STRING),
+                has_tag("b", STRING),
+                vec![
+                    I32And,
+                    when(vec![I32Const(0), Return]),
+                    get("a"), call(Runtime::ToNumber), get("b"), call(Runtime::ToNumber), F64Eq,
+                ],
+            ].concat()),
+            // There are no objects, so ToPrimitive leaves both sides as they are
+            Runtime::Add => (vec![("a", I64), ("b", I64)], I64, vec![], [
+                has_tag("a", STRING),
+                has_tag("b", STRING),
+                vec![
+                    I32Or,
+                    when(vec![
+                        get("a"), call(Runtime::ToString), get("b"), call(Runtime::ToString), call(Runtime::Concat),
+                        Return,
+                    ]),
+                    get("a"), call(Runtime::ToNumber), get("b"), call(Runtime::ToNumber), F64Add, I64ReinterpretF64,
+                ],
+            ].concat()),
+            Runtime::Compare => (vec![("a", I64), ("b", I64)], I32, vec![("x", F64), ("y", F64)], [
+                has_tag("a", STRING),
+                has_tag("b", STRING),
+                vec![
+                    I32And,
+                    when(vec![get("a"), I32WrapI64, get("b"), I32WrapI64, call(Runtime::CompareStrings), Return]),
+                    get("a"), call(Runtime::ToNumber), set("x"),
+                    get("b"), call(Runtime::ToNumber), set("y"),
+                    get("x"), get("y"), F64Lt, when(vec![I32Const(-1), Return]),
+                    get("x"), get("y"), F64Gt, when(vec![I32Const(1), Return]),
+                    I32Const(0), I32Const(2), get("x"), get("y"), F64Eq, Select,
+                ],
+            ].concat()),
+            Runtime::Typeof => (vec![("v", I64)], I64, vec![], [
+                is_number("v"),
+                vec![when(vec![I64Const(strings.value("number")), Return])],
+                vec![get("v"), I64Const(UNDEFINED), I64Eq, when(vec![I64Const(strings.value("undefined")), Return])],
+                vec![get("v"), I64Const(NULL), I64Eq, when(vec![I64Const(strings.value("object")), Return])],
+                has_tag("v", FALSE),
+                vec![when(vec![I64Const(strings.value("boolean")), Return])],
+                vec![I64Const(strings.value("string"))],
+            ].concat()),
+            // x - trunc(x / y) * y with the sign of x, which is exact while x is an integer below 2^53.
+            // A finite x is left alone by an infinite y.
+            Runtime::Rem => (vec![("x", F64), ("y", F64)], F64, vec![], vec![
+                get("y"), F64Abs, F64Const(f64::INFINITY), F64Eq,
+                get("x"), get("x"), F64Sub, F64Const(0.0), F64Eq,
+                I32And,
+                when(vec![get("x"), Return]),
+                get("x"), get("x"), get("y"), F64Div, F64Trunc, get("y"), F64Mul, F64Sub,
+                get("x"), F64Copysign,
+            ]),
+            // Repeated squaring over the exponent's bits, then 1 / result for a negative exponent
+            Runtime::Pow => (vec![("base", F64), ("exp", F64)], F64, vec![("n", I64), ("result", F64)], vec![
+                get("exp"), F64Trunc, get("exp"), F64Ne,
+                when(vec![F64Const(f64::NAN), Return]),
+                get("exp"), F64Abs, I64TruncSatF64S, set("n"),
+                F64Const(1.0), set("result"),
+                repeat("done", "next", vec![
+                    get("n"), I64Eqz, BrIf("done".to_string()),
+                    get("n"), I64Const(1), I64And, I32WrapI64,
+                    when(vec![get("result"), get("base"), F64Mul, set("result")]),
+                    get("base"), get("base"), F64Mul, set("base"),
+                    get("n"), I64Const(1), I64ShrU, set("n"),
+                ]),
+                get("exp"), F64Const(0.0), F64Lt,
+                If { result: Some(F64), then_body: vec![F64Const(1.0), get("result"), F64Div], else_body: Some(vec![get("result")]) },
+            ]),
+            // x > 0 ? 1 : x < 0 ? -1 : x, which keeps NaN and -0
+            Runtime::Sign => (vec![("x", F64)], F64, vec![], vec![
+                F64Const(1.0),
+                F64Const(-1.0), get("x"), get("x"), F64Const(0.0), F64Lt, Select,
+                get("x"), F64Const(0.0), F64Gt, Select,
+            ]),
+            Runtime::ScaleDigits => (vec![("m", I64), ("k", I32)], F64, wide_locals(), [
+                // m as a double-double: the rounded double, and what rounding it lost
+                vec![
+                    get("m"), F64ConvertI64U, set("hi"),
+                    get("m"), get("hi"), I64TruncSatF64U, I64Sub, F64ConvertI64S, set("lo"),
+                ],
+                scale_wide(),
+                // hi + lo rounds correctly, but overflow leaves lo NaN
+                vec![
+                    get("hi"), get("hi"), get("lo"), F64Add,
+                    get("hi"), F64Abs, F64Const(f64::INFINITY), F64Eq, Select,
+                ],
+            ].concat()),
+            Runtime::RoundScaled => (vec![("x", F64), ("k", I32)], I64, [wide_locals(), vec![("n", F64)]].concat(), [
+                vec![get("x"), set("hi")],
+                scale_wide(),
+                // The nearest integer to hi, corrected by the rounded hi - n + lo, which is at most
+                // one away
+                vec![
+                    get("hi"), F64Nearest, tee("n"), I64TruncSatF64U,
+                    get("hi"), get("n"), F64Sub, get("lo"), F64Add, F64Nearest, I64TruncSatF64S,
+                    I64Add,
+                ],
+            ].concat()),
+            Runtime::NumberToString => number_to_string(strings),
+            Runtime::StringToNumber => string_to_number(),
+            Runtime::StringEquals => (vec![("a", I32), ("b", I32)], I32, vec![("i", I32)], vec![
+                get("a"), get("b"), I32Eq, when(vec![I32Const(1), Return]),
+                get("a"), I32Load(0), get("b"), I32Load(0), I32Ne, when(vec![I32Const(0), Return]),
+                repeat("done", "next", vec![
+                    get("i"), get("a"), I32Load(0), I32Eq, BrIf("done".to_string()),
+                    get("a"), get("i"), I32Add, I32Load8U(4), get("b"), get("i"), I32Add, I32Load8U(4), I32Ne,
+                    when(vec![I32Const(0), Return]),
+                    get("i"), I32Const(1), I32Add, set("i"),
+                ]),
+                I32Const(1),
+            ]),
+            // The first byte that differs decides, and otherwise the shorter string is below
+            Runtime::CompareStrings => (vec![("a", I32), ("b", I32)], I32, vec![("n", I32), ("i", I32), ("x", I32), ("y", I32)], vec![
+                get("a"), I32Load(0), get("b"), I32Load(0), get("a"), I32Load(0), get("b"), I32Load(0), I32LtU, Select, set("n"),
+                repeat("done", "next", vec![
+                    get("i"), get("n"), I32Eq, BrIf("done".to_string()),
+                    get("a"), get("i"), I32Add, I32Load8U(4), tee("x"),
+                    get("b"), get("i"), I32Add, I32Load8U(4), tee("y"),
+                    I32Ne,
+                    when(vec![get("x"), get("y"), I32GtU, get("x"), get("y"), I32LtU, I32Sub, Return]),
+                    get("i"), I32Const(1), I32Add, set("i"),
+                ]),
+                get("a"), I32Load(0), get("b"), I32Load(0), I32GtU, get("a"), I32Load(0), get("b"), I32Load(0), I32LtU, I32Sub,
+            ]),
+            Runtime::Concat => (vec![("a", I64), ("b", I64)], I64, vec![("m", I32), ("n", I32), ("result", I32)], vec![
+                get("a"), I32WrapI64, I32Load(0), set("m"),
+                get("b"), I32WrapI64, I32Load(0), set("n"),
+                get("m"), I32Eqz, when(vec![get("b"), Return]),
+                get("n"), I32Eqz, when(vec![get("a"), Return]),
+                get("m"), get("n"), I32Add, I32Const(4), I32Add, call(Runtime::Alloc), tee("result"),
+                get("m"), get("n"), I32Add, I32Store(0),
+                get("result"), I32Const(4), I32Add, get("a"), I32WrapI64, I32Const(4), I32Add, get("m"), MemoryCopy,
+                get("result"), I32Const(4), I32Add, get("m"), I32Add, get("b"), I32WrapI64, I32Const(4), I32Add, get("n"), MemoryCopy,
+                get("result"), I64ExtendI32U, I64Const(STRING), I64Or,
+            ]),
+            // Right to left, dividing by 10 each time
+            Runtime::WriteDigits => (vec![("at", I32), ("n", I64), ("count", I32)], I32, vec![("end", I32)], vec![
+                get("at"), get("count"), I32Add, set("end"),
+                repeat("done", "next", vec![
+                    get("count"), I32Eqz, BrIf("done".to_string()),
+                    get("count"), I32Const(1), I32Sub, set("count"),
+                    get("at"), get("count"), I32Add,
+                    get("n"), I64Const(10), I64RemU, I32WrapI64, I32Const(b'0' as i32), I32Add,
+                    I32Store8(0),
+                    get("n"), I64Const(10), I64DivU, set("n"),
+                ]),
+                get("end"),
+            ]),
+            // The heap grows by whole pages when it runs out. If memory can't grow, the first
+            // store past its end traps.
+            Runtime::Alloc => (vec![("size", I32)], I32, vec![("address", I32)], vec![
+                GlobalGet(HEAP.to_string()), tee("address"),
+                get("size"), I32Add, I32Const(3), I32Add, I32Const(-4), I32And, GlobalSet(HEAP.to_string()),
+                GlobalGet(HEAP.to_string()), MemorySize, I32Const(16), I32Shl, I32GtU,
+                when(vec![
+                    GlobalGet(HEAP.to_string()), MemorySize, I32Const(16), I32Shl, I32Sub,
+                    I32Const(65535), I32Add, I32Const(16), I32ShrU,
+                    MemoryGrow, Drop,
+                ]),
+                get("address"),
+            ]),
+        };
+
+        Function {
+            name: self.name().to_string(),
+            params: params.into_iter().map(|(name, ty)| Local::new(name, ty)).collect(),
+            result,
+            locals: locals.into_iter().map(|(name, ty)| Local::new(name, ty)).collect(),
+            body,
+        }
+    }
+}
+
+// Scaling by powers of ten works on a double-double: hi + lo, with lo holding what rounding hi
+// lost. Each step multiplies or divides by 10^j for j up to 22, which is exact as a double, and
+// Dekker's product gives the rounding error of that exactly, so about 100 bits stay right.
+fn wide_locals() -> Locals {
+    use ValType::{F64, I32};
+    vec![
+        ("hi", F64), ("lo", F64), ("j", I32), ("c", F64), ("big", I32), ("q", F64), ("p", F64), ("e", F64),
+        ("t", F64), ("ah", F64), ("al", F64), ("bh", F64), ("bl", F64),
+    ]
+}
+
+// Multiplies hi + lo by 10^k, stopping early once hi is 0 or infinite. Near the top of the
+// range the partial products of a step could overflow, so the step works on hi + lo scaled down
+// by 2^64, which is exact.
+fn scale_wide() -> Vec<Instruction> {
+    use Instruction::*;
+    let power = || vec![F64Const(10.0), get("j"), F64ConvertI32S, call(Runtime::Pow), set("c")];
+    let by = |factor: f64| vec![
+        get("hi"), F64Const(factor), F64Mul, set("hi"),
+        get("lo"), F64Const(factor), F64Mul, set("lo"),
+    ];
+    let guarded = |step: Vec<Instruction>| [
+        vec![
+            get("hi"), F64Abs, get("hi"), get("c"), F64Mul, F64Abs, F64Max, F64Const(2f64.powi(1000)), F64Gt, tee("big"),
+            when(by(2f64.powi(-64))),
+        ],
+        step,
+        vec![get("big"), when(by(2f64.powi(64)))],
+    ].concat();
+    vec![repeat("scaled", "scale", vec![
+        get("k"), I32Eqz,
+        get("hi"), F64Const(0.0), F64Eq, I32Or,
+        get("hi"), F64Abs, F64Const(f64::INFINITY), F64Eq, I32Or,
+        BrIf("scaled".to_string()),
+        get("k"), I32Const(0), I32GtS,
+        If {
+            result: None,
+            // j = (k - 1) mod 22 + 1
+            then_body: [
+                vec![get("k"), I32Const(1), I32Sub, I32Const(22), I32RemS, I32Const(1), I32Add, set("j")],
+                power(),
+                guarded([
+                    two_product("hi", "c"),
+                    vec![
+                        get("e"), get("lo"), get("c"), F64Mul, F64Add, set("e"),
+                        get("p"), get("e"), F64Add, set("hi"),
+                        get("e"), get("hi"), get("p"), F64Sub, F64Sub, set("lo"),
+                    ],
+                ].concat()),
+                vec![get("k"), get("j"), I32Sub, set("k")],
+            ].concat(),
+            // j = min(-k, 22). The quotient, then the exact remainder divided again for the low part.
+            else_body: Some([
+                vec![I32Const(0), get("k"), I32Sub, tee("j"), I32Const(22), get("j"), I32Const(22), I32LtS, Select, set("j")],
+                power(),
+                guarded([
+                    vec![get("hi"), get("c"), F64Div, set("q")],
+                    two_product("q", "c"),
+                    vec![
+                        get("hi"), get("p"), F64Sub, get("e"), F64Sub, get("lo"), F64Add, get("c"), F64Div, set("e"),
+                        get("q"), get("e"), F64Add, set("hi"),
+                        get("e"), get("hi"), get("q"), F64Sub, F64Sub, set("lo"),
+                    ],
+                ].concat()),
+                vec![get("k"), get("j"), I32Add, set("k")],
+            ].concat()),
+        },
+    ])]
+}
+
+// p = a * b rounded and e = a * b - p exactly: splitting both factors into halves of 26 bits makes
+// their partial products exact
+fn two_product(a: &str, b: &str) -> Vec<Instruction> {
+    use Instruction::*;
+    let split = |x: &str, high: &str, low: &str| vec![
+        get(x), F64Const(134217729.0), F64Mul, tee("t"), get("t"), get(x), F64Sub, F64Sub, tee(high),
+        get(x), F64Sub, F64Neg, set(low),
+    ];
+    [
+        split(a, "ah", "al"),
+        split(b, "bh", "bl"),
+        vec![
+            get(a), get(b), F64Mul, set("p"),
+            get("ah"), get("bh"), F64Mul, get("p"), F64Sub,
+            get("ah"), get("bl"), F64Mul, F64Add,
+            get("al"), get("bh"), F64Mul, F64Add,
+            get("al"), get("bl"), F64Mul, F64Add,
+            set("e"),
+        ],
+    ].concat()
+}
+
+// Number::toString: the fewest significant digits that read back as the same double, and of
+// those the closest, in fixed notation from 1e-6 up to 1e21 and in exponential notation
+// (`1.5e+300`) outside of that. The digits are found by trying 1, 2, ... up to 17 of them.
+// Scaling is done with about 100 bits, so the digits are only wrong when abs is within 2^-100
+// of halfway between two candidates.
+fn number_to_string(strings: &mut Strings) -> (Locals, ValType, Locals, Vec<Instruction>) {
+    use Instruction::*;
+    use ValType::{F64, I32, I64};
+
+    let locals = vec![
+        // |x|, its decimal exponent and the number of digits tried
+        ("abs", F64), ("e", I32), ("p", I32),
+        // The digits as an integer, the exponent of the last one, how many there are, and
+        // where the decimal point goes after the first `point` of them
+        ("m", I64), ("q", I32), ("count", I32), ("point", I32),
+        ("t", I64), ("buffer", I32), ("at", I32),
+    ];
+    let write_digits = |at: Vec<Instruction>| [at, vec![get("m"), get("count"), call(Runtime::WriteDigits)]].concat();
+    let store = |offset: u32, byte: u8| vec![get("at"), I32Const(byte as i32), I32Store8(offset)];
+    let advance = |by: Vec<Instruction>| [vec![get("at")], by, vec![I32Add, set("at")]].concat();
+
+    let body = [
+        vec![
+            get("x"), get("x"), F64Ne, when(vec![I64Const(strings.value("NaN")), Return]),
+            get("x"), F64Const(0.0), F64Eq, when(vec![I64Const(strings.value("0")), Return]),
+            get("x"), F64Abs, F64Const(f64::INFINITY), F64Eq,
+            when(vec![
+                I64Const(strings.value("Infinity")), I64Const(strings.value("-Infinity")), get("x"), F64Const(0.0), F64Gt, Select,
+                Return,
+            ]),
+            get("x"), F64Abs, set("abs"),
+            // 10^e <= abs < 10^(e + 1), with the powers rounded to doubles. If abs is a rounded
+            // power of ten, e can be one too high, but then a single digit already reads back.
+            repeat("up_done", "up", vec![
+                get("abs"), I64Const(1), get("e"), I32Const(1), I32Add, call(Runtime::ScaleDigits), F64Lt, BrIf("up_done".to_string()),
+                get("e"), I32Const(1), I32Add, set("e"),
+            ]),
+            repeat("down_done", "down", vec![
+                get("abs"), I64Const(1), get("e"), call(Runtime::ScaleDigits), F64Ge, BrIf("down_done".to_string()),
+                get("e"), I32Const(1), I32Sub, set("e"),
+            ]),
+            // m = abs * 10^(p - 1 - e) rounded, so q = e + 1 - p is the exponent of its last digit.
+            // These are the closest p digits, and 17 always read back.
+            I32Const(1), set("p"),
+            repeat("digits_done", "digits", vec![
+                get("e"), I32Const(1), I32Add, get("p"), I32Sub, set("q"),
+                get("abs"), I32Const(0), get("q"), I32Sub, call(Runtime::RoundScaled), set("m"),
+                get("m"), get("q"), call(Runtime::ScaleDigits), get("abs"), F64Eq, BrIf("digits_done".to_string()),
+                // Below a power of two the doubles are closer together, so the digits above can
+                // read back when the closest ones, below, don't
+                get("m"), I64Const(1), I64Add, get("q"), call(Runtime::ScaleDigits), get("abs"), F64Eq,
+                when(vec![get("m"), I64Const(1), I64Add, set("m"), Br("digits_done".to_string())]),
+                get("p"), I32Const(17), I32Eq, BrIf("digits_done".to_string()),
+                get("p"), I32Const(1), I32Add, set("p"),
+            ]),
+            // Only 17 digits can have trailing zeros
+            repeat("strip_done", "strip", vec![
+                get("m"), I64Const(10), I64RemU, I64Eqz, get("m"), I64Eqz, I32Eqz, I32And, I32Eqz, BrIf("strip_done".to_string()),
+                get("m"), I64Const(10), I64DivU, set("m"),
+                get("q"), I32Const(1), I32Add, set("q"),
+            ]),
+            get("m"), set("t"),
+            repeat("count_done", "count", vec![
+                get("count"), I32Const(1), I32Add, set("count"),
+                get("t"), I64Const(10), I64DivU, tee("t"), I64Eqz, BrIf("count_done".to_string()),
+            ]),
+            get("count"), get("q"), I32Add, set("point"),
+
+            // Room for a sign, 21 digits and some zeros or the exponent
+            I32Const(36), call(Runtime::Alloc), tee("buffer"), I32Const(4), I32Add, set("at"),
+            get("x"), F64Const(0.0), F64Lt,
+            when([store(0, b'-'), advance(vec![I32Const(1)])].concat()),
+        ],
+        // 123 and 12300
+        vec![
+            get("count"), get("point"), I32LeS, get("point"), I32Const(21), I32LeS, I32And,
+            If {
+                result: None,
+                then_body: [
+                    write_digits(vec![get("at")]), vec![set("at")],
+                    vec![get("at"), I32Const(b'0' as i32), get("point"), get("count"), I32Sub, MemoryFill],
+                    advance(vec![get("point"), get("count"), I32Sub]),
+                ].concat(),
+                // 1.23
+                else_body: Some(vec![
+                    get("point"), I32Const(0), I32GtS, get("point"), I32Const(21), I32LeS, I32And,
+                    If {
+                        result: None,
+                        then_body: [
+                            write_digits(vec![get("at"), I32Const(1), I32Add]), vec![Drop],
+                            vec![get("at"), get("at"), I32Const(1), I32Add, get("point"), MemoryCopy],
+                            vec![get("at"), get("point"), I32Add, I32Const(b'.' as i32), I32Store8(0)],
+                            advance(vec![get("count"), I32Const(1), I32Add]),
+                        ].concat(),
+                        // 0.00123
+                        else_body: Some(vec![
+                            get("point"), I32Const(-6), I32GtS, get("point"), I32Const(1), I32LtS, I32And,
+                            If {
+                                result: None,
+                                then_body: [
+                                    store(0, b'0'),
+                                    store(1, b'.'),
+                                    vec![get("at"), I32Const(2), I32Add, I32Const(b'0' as i32), I32Const(0), get("point"), I32Sub, MemoryFill],
+                                    write_digits(vec![get("at"), I32Const(2), I32Add, get("point"), I32Sub]), vec![set("at")],
+                                ].concat(),
+                                // 1.23e+21 and 1.23e-7
+                                else_body: Some([
+                                    write_digits(vec![get("at"), I32Const(1), I32Add]), vec![Drop],
+                                    vec![get("at"), get("at"), I32Load8U(1), I32Store8(0)],
+                                    vec![
+                                        get("count"), I32Const(1), I32GtU,
+                                        If {
+                                            result: None,
+                                            then_body: [store(1, b'.'), advance(vec![get("count"), I32Const(1), I32Add])].concat(),
+                                            else_body: Some(advance(vec![I32Const(1)])),
+                                        },
+                                    ],
+                                    store(0, b'e'),
+                                    vec![
+                                        get("at"), I32Const(b'+' as i32), I32Const(b'-' as i32), get("point"), I32Const(0), I32GtS, Select,
+                                        I32Store8(1),
+                                    ],
+                                    // |point - 1| has 1 to 3 digits
+                                    vec![
+                                        get("point"), I32Const(1), I32Sub, set("point"),
+                                        I32Const(0), get("point"), I32Sub, get("point"), get("point"), I32Const(0), I32LtS, Select, set("point"),
+                                        get("point"), I64ExtendI32U, set("m"),
+                                        I32Const(1), get("point"), I32Const(9), I32GtU, I32Add, get("point"), I32Const(99), I32GtU, I32Add,
+                                        set("count"),
+                                    ],
+                                    write_digits(vec![get("at"), I32Const(2), I32Add]), vec![set("at")],
+                                ].concat()),
+                            },
+                        ]),
+                    },
+                ]),
+            },
+        ],
+        vec![
+            get("buffer"), get("at"), get("buffer"), I32Sub, I32Const(4), I32Sub, I32Store(0),
+            get("buffer"), I64ExtendI32U, I64Const(STRING), I64Or,
+        ],
+    ].concat();
+    (vec![("x", F64)], I64, locals, body)
+}
+
+// StringToNumber: surrounding whitespace is ignored, an empty string is 0, and otherwise the
+// text has to be a decimal number (`-1.5e3`, `.5`), `Infinity` with an optional sign, or an
+// integer with a `0x`, `0o` or `0b` prefix. Anything else is NaN. The first 18 significant
+// digits of a decimal are kept, which decides the double unless the rest are right at halfway
+// between two; prefixed integers are gathered into a double, so past 2^53 they may be off.
+fn string_to_number() -> (Locals, ValType, Locals, Vec<Instruction>) {
+    use Instruction::*;
+    use ValType::{F64, I32, I64};
+
+    let locals = vec![
+        ("i", I32), ("end", I32), ("c", I32), ("d", I32), ("radix", I32), ("value", F64),
+        ("sign", F64), ("m", I64), ("exp", I32), ("digits", I32), ("exp_sign", I32), ("exp_value", I32),
+    ];
+    let nan = || when(vec![F64Const(f64::NAN), Return]);
+    let byte = |offset: u32| vec![get("i"), I32Load8U(offset)];
+    let more = || vec![get("i"), get("end"), I32LtU];
+    let next = || vec![get("i"), I32Const(1), I32Add, set("i")];
+    // Tab, line feed, vertical tab, form feed, carriage return and space
+    let is_space = |c: Vec<Instruction>| [c, vec![
+        tee("c"), I32Const(b' ' as i32), I32Eq, get("c"), I32Const(9), I32Sub, I32Const(5), I32LtU, I32Or,
+    ]].concat();
+    // Leaves the value of the decimal digit at `i` in `d`, and whether there is one
+    let digit = || [more(), vec![
+        If { result: Some(I32), then_body: [byte(0), vec![I32Const(b'0' as i32), I32Sub, tee("d"), I32Const(10), I32LtU]].concat(), else_body: Some(vec![I32Const(0)]) },
+    ]].concat();
+    // m = m * 10 + d for each digit, counting them in `digits` and adding `step` to `exp` for each.
+    // Once m has 18 digits the rest are dropped, which moves the decimal point one further.
+    let mantissa = |done: &str, next_label: &str, step: i32| repeat(done, next_label, [
+        digit(), vec![I32Eqz, BrIf(done.to_string())],
+        vec![
+            get("m"), I64Const(100_000_000_000_000_000), I64DivU, I64Eqz,
+            If {
+                result: None,
+                then_body: vec![
+                    get("m"), I64Const(10), I64Mul, get("d"), I64ExtendI32U, I64Add, set("m"),
+                    get("exp"), I32Const(step), I32Add, set("exp"),
+                ],
+                else_body: Some(vec![get("exp"), I32Const(step + 1), I32Add, set("exp")]),
+            },
+        ],
+        vec![get("digits"), I32Const(1), I32Add, set("digits")],
+        next(),
+    ].concat());
+
+    let body = [
+        vec![
+            get("s"), I32Const(4), I32Add, tee("i"), get("s"), I32Load(0), I32Add, set("end"),
+            repeat("start_done", "start", [
+                more(), vec![I32Eqz, BrIf("start_done".to_string())],
+                is_space(byte(0)), vec![I32Eqz, BrIf("start_done".to_string())],
+                next(),
+            ].concat()),
+            repeat("end_done", "end", [
+                more(), vec![I32Eqz, BrIf("end_done".to_string())],
+                is_space(vec![get("end"), I32Const(1), I32Sub, I32Load8U(0)]), vec![I32Eqz, BrIf("end_done".to_string())],
+                vec![get("end"), I32Const(1), I32Sub, set("end")],
+            ].concat()),
+            get("i"), get("end"), I32Eq, when(vec![F64Const(0.0), Return]),
+        ],
+        // 0x, 0o and 0b, then at least one digit of that radix
+        vec![
+            get("end"), get("i"), I32Sub, I32Const(2), I32GtU,
+            If {
+                result: Some(I32),
+                then_body: [byte(0), vec![I32Const(b'0' as i32), I32Eq]].concat(),
+                else_body: Some(vec![I32Const(0)]),
+            },
+            when([
+                byte(1), vec![I32Const(0x20), I32Or, set("c")],
+                vec![
+                    I32Const(16), I32Const(8), I32Const(2), I32Const(0),
+                    get("c"), I32Const(b'b' as i32), I32Eq, Select,
+                    get("c"), I32Const(b'o' as i32), I32Eq, Select,
+                    get("c"), I32Const(b'x' as i32), I32Eq, Select,
+                    set("radix"),
+                ],
+            ].concat()),
+            get("radix"),
+            when([
+                vec![get("i"), I32Const(2), I32Add, set("i")],
+                vec![repeat("radix_done", "radix_next", [
+                    more(), vec![I32Eqz, BrIf("radix_done".to_string())],
+                    // 0-9, then a-z for 10 and up, and anything else too big for any radix
+                    byte(0), vec![set("c")],
+                    vec![
+                        get("c"), I32Const(b'0' as i32), I32Sub,
+                        get("c"), I32Const(0x20), I32Or, I32Const(b'a' as i32), I32Sub, tee("d"), I32Const(10), I32Add,
+                        I32Const(99), get("d"), I32Const(26), I32LtU, Select,
+                        get("c"), I32Const(b'0' as i32), I32Sub, I32Const(10), I32LtU, Select,
+                        tee("d"), get("radix"), I32LtU, I32Eqz, nan(),
+                        get("value"), get("radix"), F64ConvertI32U, F64Mul, get("d"), F64ConvertI32U, F64Add, set("value"),
+                    ],
+                    next(),
+                ].concat())],
+                vec![get("value"), Return],
+            ].concat()),
+        ],
+        vec![F64Const(1.0), set("sign")],
+        more(),
+        vec![If {
+            result: None,
+            then_body: [
+                byte(0), vec![set("c")],
+                vec![get("c"), I32Const(b'-' as i32), I32Eq, when(vec![F64Const(-1.0), set("sign")])],
+                vec![get("c"), I32Const(b'-' as i32), I32Eq, get("c"), I32Const(b'+' as i32), I32Eq, I32Or],
+                vec![when(next())],
+            ].concat(),
+            else_body: None,
+        }],
+        // Infinity, compared as one 8-byte load
+        vec![
+            get("end"), get("i"), I32Sub, I32Const(8), I32Eq,
+            If {
+                result: Some(I32),
+                then_body: vec![get("i"), I64Load(0), I64Const(i64::from_le_bytes(*b"Infinity")), I64Eq],
+                else_body: Some(vec![I32Const(0)]),
+            },
+            when(vec![get("sign"), F64Const(f64::INFINITY), F64Mul, Return]),
+        ],
+        vec![mantissa("whole_done", "whole", 0)],
+        more(),
+        vec![If { result: Some(I32), then_body: [byte(0), vec![I32Const(b'.' as i32), I32Eq]].concat(), else_body: Some(vec![I32Const(0)]) }],
+        vec![when([next(), vec![mantissa("fraction_done", "fraction", -1)]].concat())],
+        vec![get("digits"), I32Eqz, nan()],
+        // An exponent needs at least one digit, and is capped so it can't overflow
+        more(),
+        vec![If { result: Some(I32), then_body: [byte(0), vec![I32Const(0x20), I32Or, I32Const(b'e' as i32), I32Eq]].concat(), else_body: Some(vec![I32Const(0)]) }],
+        vec![when([
+            next(),
+            vec![I32Const(1), set("exp_sign")],
+            more(),
+            vec![If {
+                result: None,
+                then_body: [
+                    byte(0), vec![set("c")],
+                    vec![get("c"), I32Const(b'-' as i32), I32Eq, when(vec![I32Const(-1), set("exp_sign")])],
+                    vec![get("c"), I32Const(b'-' as i32), I32Eq, get("c"), I32Const(b'+' as i32), I32Eq, I32Or],
+                    vec![when(next())],
+                ].concat(),
+                else_body: None,
+            }],
+            digit(), vec![I32Eqz, nan()],
+            vec![repeat("exp_done", "exp_next", [
+                digit(), vec![I32Eqz, BrIf("exp_done".to_string())],
+                vec![
+                    get("exp_value"), I32Const(10), I32Mul, get("d"), I32Add, tee("exp_value"),
+                    I32Const(100000), I32GtS, when(vec![I32Const(100000), set("exp_value")]),
+                ],
+                next(),
+            ].concat())],
+            vec![get("exp"), get("exp_value"), get("exp_sign"), I32Mul, I32Add, set("exp")],
+        ].concat())],
+        vec![get("i"), get("end"), I32Ne, nan()],
+        vec![get("m"), get("exp"), call(Runtime::ScaleDigits), get("sign"), F64Copysign],
+    ].concat();
+    (vec![("s", I32)], F64, locals, body)
+}
+
+fn get(name: &str) -> Instruction {
+    Instruction::LocalGet(name.to_string())
+}
+
+fn set(name: &str) -> Instruction {
+    Instruction::LocalSet(name.to_string())
+}
+
+fn tee(name: &str) -> Instruction {
+    Instruction::LocalTee(name.to_string())
+}
+
+fn call(runtime: Runtime) -> Instruction {
+    Instruction::Call(runtime.name().to_string())
+}
+
+fn when(then_body: Vec<Instruction>) -> Instruction {
+    Instruction::If { result: None, then_body, else_body: None }
+}
+
+// `body` over and over, until it branches to `done`
+fn repeat(done: &str, next: &str, mut body: Vec<Instruction>) -> Instruction {
+    body.push(Instruction::Br(next.to_string()));
+    Instruction::Block { label: done.to_string(), body: vec![Instruction::Loop { label: next.to_string(), body }] }
+}
+
+fn is_number(name: &str) -> Vec<Instruction> {
+    use Instruction::*;
+    vec![get(name), I64Const(50), I64ShrU, I64Const(BOXED), I64Ne]
+}
+
+fn has_tag(name: &str, tag: i64) -> Vec<Instruction> {
+    use Instruction::*;
+    vec![get(name), I64Const(48), I64ShrU, I64Const(tag >> 48), I64Eq]
+}
+
+// `null` and `undefined` only differ in the lowest bit
+fn is_nullish(name: &str) -> Vec<Instruction> {
+    use Instruction::*;
+    vec![get(name), I64Const(1), I64ShrU, I64Const(UNDEFINED >> 1), I64Eq]
+}
@@ -0,0 +1,628 @@
+use crate::ast::{Expression, ExpressionKind, Ident, Program, Statement, StatementKind, UnaryOp};
+use crate::builtins::Builtin;
+use crate::error::{CompileError, ErrorKind, Result};
+use crate::options::CompileOptions;
+use crate::token::Span;
+use std::collections::{HashMap, HashSet};
+
+// Semantic analysis: resolves every identifier to the declaration it refers to and checks the
+// rules codegen relies on (no undefined names, no const reassignment, no duplicate declarations,
+// calls match the callee's parameter count). The result is a side table keyed by source position,
+// so codegen can look any identifier up without having to handle failure.
+
+pub type BindingId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BindingKind {
+    Param,
+    Let,
+    Const,
+}
+
+// A declared variable or parameter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    // Unique within the function, so blocks can reuse JS names
+    pub wasm_name: String,
+    pub kind: BindingKind,
+    // The name where it was declared, and the whole declaration
+    pub span: Span,
+    pub declaration: Span,
+    // How often the variable is read and assigned after its declaration
+    pub reads: usize,
+    pub writes: usize,
+    // Declaration of a variable with the same name in an enclosing scope, hidden by this one
+    pub shadows: Option<Span>,
+}
+
+impl Binding {
+    pub fn is_const(&self) -> bool {
+        self.kind == BindingKind::Const
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSignature {
+    pub name: String,
+    pub params: usize,
+    pub span: Span,
+}
+
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub bindings: Vec<Binding>,
+    pub functions: Vec<FunctionSignature>,
+    // Identifier occurrences (declarations and uses), by the byte offset they start at
+    resolved: HashMap<usize, BindingId>,
+    // `let`/`const` bindings of each function in declaration order. Top-level code is under None.
+    locals: HashMap<Option<String>, Vec<BindingId>>,
+}
+
+impl Analysis {
+    // The binding an identifier refers to. `span` is the identifier's span, as found in the AST.
+    // Every identifier in an analyzed program has one.
+    pub fn binding(&self, span: Span) -> Option<&Binding> {
+        self.resolved.get(&span.start).map(|id| &self.bindings[*id])
+    }
+
+    pub fn locals(&self, function: Option<&str>) -> impl Iterator<Item = &Binding> {
+        self.locals.get(&function.map(str::to_string))
+            .into_iter()
+            .flatten()
+            .map(|id| &self.bindings[*id])
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionSignature> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+}
+
+pub fn analyze(program: &Program, options: &CompileOptions) -> Result<Analysis> {
+    let mut resolver = Resolver {
+        loose_arity: options.loose_arity,
+        dynamic: options.dynamic,
+        analysis: Analysis::default(),
+        scopes: Vec::new(),
+        function: None,
+        local_counter: 0,
+        param_names: HashSet::new(),
+        targets: Vec::new(),
+        labels: Vec::new(),
+        error: None,
+    };
+    resolver.analyze(program);
+    match resolver.error {
+        Some(error) => Err(error),
+        None => Ok(resolver.analysis),
+    }
+}
+
+// A name in a scope. `let`/`const` names are known from the start of their block but
+// can't be used until the declaration runs (the temporal dead zone).
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Uninitialized { declared_at: Span },
+    Declared(BindingId),
+    // Declared in an earlier case of the enclosing `switch`, which may not be the one that ran
+    OtherCase(BindingId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TargetKind {
+    Loop,
+    Switch,
+    // Any other labeled statement, which only `break label` can leave
+    Block,
+}
+
+struct JumpTarget {
+    labels: Vec<Ident>,
+    kind: TargetKind,
+}
+
+struct Resolver {
+    loose_arity: bool,
+    dynamic: bool,
+    analysis: Analysis,
+    // Innermost last. Each scope maps JS names to bindings.
+    scopes: Vec<HashMap<String, Slot>>,
+    // Function whose body is being resolved, None for top-level code
+    function: Option<String>,
+    local_counter: usize,
+    // Wasm names taken by parameters, which the `name_N` of a local must not repeat
+    param_names: HashSet<String>,
+    // Enclosing statements `break` and `continue` can refer to, innermost last
+    targets: Vec<JumpTarget>,
+    // Labels of the statement about to be visited, for `a: b: while (...)`
+    labels: Vec<Ident>,
+    // First error found. Resolution carries on so the rest of the tree is still visited.
+    error: Option<CompileError>,
+}
+
+impl Resolver {
+    fn analyze(&mut self, program: &Program) {
+        // Functions are hoisted: every call can see every top-level function
+        for stmt in &program.body {
+            if let StatementKind::FunctionDeclaration { name, params, .. } = &stmt.kind {
+                if let Some(first) = self.analysis.function(&name.name) {
+                    let first = first.span;
+                    self.report(duplicate(name, first));
+                    continue;
+                }
+                self.analysis.functions.push(FunctionSignature {
+                    name: name.name.clone(),
+                    params: params.len(),
+                    span: name.span,
+                });
+            }
+        }
+
+        // Function bodies get their own scope. Top-level variables live in `main`, so they aren't visible here.
+        for stmt in &program.body {
+            if let StatementKind::FunctionDeclaration { name, params, body } = &stmt.kind {
+                self.function = Some(name.name.clone());
+                self.scopes.push(HashMap::new());
+                for param in params {
+                    self.declare(param, BindingKind::Param, param.span);
+                }
+                self.block(body.iter());
+                self.scopes.pop();
+            }
+        }
+
+        self.function = None;
+        self.scopes.push(HashMap::new());
+        self.block(program.body.iter().filter(|s| !matches!(s.kind, StatementKind::FunctionDeclaration { .. })));
+        self.scopes.pop();
+    }
+
+    // Resolves `stmts` in the innermost scope, after making their declarations known to it
+    fn block<'s>(&mut self, stmts: impl Iterator<Item = &'s Statement> + Clone) {
+        self.hoist(stmts.clone());
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+    }
+
+    // Makes the declarations in `stmts` known to the innermost scope
+    fn hoist<'s>(&mut self, stmts: impl Iterator<Item = &'s Statement>) {
+        let scope = self.scopes.last_mut().expect("no scope");
+        for stmt in stmts {
+            if let StatementKind::VariableDeclaration { name, .. } = &stmt.kind {
+                scope.entry(name.name.clone()).or_insert(Slot::Uninitialized { declared_at: name.span });
+            }
+        }
+    }
+
+    // A statement in its own scope: block bodies, and single statements used as `if`/`while` bodies
+    fn scoped(&mut self, stmt: &Statement) {
+        self.scopes.push(HashMap::new());
+        match &stmt.kind {
+            StatementKind::Block(stmts) => self.block(stmts.iter()),
+            _ => self.block(std::iter::once(stmt)),
+        }
+        self.scopes.pop();
+    }
+
+    fn report(&mut self, error: CompileError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    fn declare(&mut self, name: &Ident, kind: BindingKind, declaration: Span) {
+        let scope = self.scopes.last().expect("no scope");
+        if let Some(Slot::Declared(first) | Slot::OtherCase(first)) = scope.get(&name.name) {
+            let first = self.analysis.bindings[*first].span;
+            self.report(duplicate(name, first));
+        }
+
+        // Params keep their JS name. Locals are `name_N` with a counter shared by the whole
+        // program, so they can't collide with each other, but a param can be called `a_0` too.
+        let wasm_name = match kind {
+            BindingKind::Param => {
+                self.param_names.insert(name.name.clone());
+                name.name.clone()
+            }
+            _ => loop {
+                self.local_counter += 1;
+                let wasm_name = format!("{}_{}", name.name, self.local_counter - 1);
+                if !self.param_names.contains(&wasm_name) {
+                    break wasm_name;
+                }
+            },
+        };
+        let shadows = self.scopes.iter().rev().skip(1).find_map(|scope| match scope.get(&name.name)? {
+            Slot::Declared(id) | Slot::OtherCase(id) => Some(self.analysis.bindings[*id].span),
+            Slot::Uninitialized { declared_at } => Some(*declared_at),
+        });
+        let id = self.analysis.bindings.len();
+        self.analysis.bindings.push(Binding {
+            name: name.name.clone(),
+            wasm_name,
+            kind,
+            span: name.span,
+            declaration,
+            reads: 0,
+            writes: 0,
+            shadows,
+        });
+        if kind != BindingKind::Param {
+            self.analysis.locals.entry(self.function.clone()).or_default().push(id);
+        }
+        self.analysis.resolved.insert(name.span.start, id);
+        self.scopes.last_mut().expect("no scope").insert(name.name.clone(), Slot::Declared(id));
+    }
+
+    // Records what `name` refers to, reporting it if there is nothing
+    fn resolve(&mut self, name: &str, span: Span) -> Option<BindingId> {
+        match self.scopes.iter().rev().find_map(|scope| scope.get(name).copied()) {
+            Some(Slot::Declared(id)) => {
+                self.analysis.resolved.insert(span.start, id);
+                Some(id)
+            }
+            Some(Slot::Uninitialized { declared_at }) => {
+                self.report(semantic_error("E0206", format!("Cannot access '{}' before initialization", name), span)
+                    .with_label("used before its declaration")
+                    .with_related(declared_at, format!("'{}' declared here", name))
+                    .with_help("move the declaration before the first use"));
+                None
+            }
+            Some(Slot::OtherCase(id)) => {
+                let declared_at = self.analysis.bindings[id].span;
+                self.report(semantic_error("E0206", format!("Cannot access '{}' before initialization", name), span)
+                    .with_label("declared in another case, which may not have run")
+                    .with_related(declared_at, format!("'{}' declared here", name))
+                    .with_help("declare it before the `switch`"));
+                None
+            }
+            None => {
+                let visible: Vec<&str> = self.scopes.iter()
+                    .flat_map(|scope| scope.iter())
+                    .filter(|(_, slot)| matches!(slot, Slot::Declared(_)))
+                    .map(|(name, _)| name.as_str())
+                    .collect();
+                let error = semantic_error("E0200", format!("Undefined variable: {}", name), span)
+                    .with_label("not found in this scope");
+                let error = with_suggestion(error, name, span, visible, "variable");
+                self.report(error);
+                None
+            }
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match &stmt.kind {
+            StatementKind::VariableDeclaration { name, init, is_const } => {
+                self.expression(init);
+                let kind = if *is_const { BindingKind::Const } else { BindingKind::Let };
+                self.declare(name, kind, stmt.span);
+            }
+            StatementKind::FunctionDeclaration { name, .. } => {
+                self.report(semantic_error("E0205", format!("Function '{}' must be declared at the top level", name.name), name.span)
+                    .with_label("nested function declarations aren't supported")
+                    .with_help("move the function out of the enclosing block or function"));
+            }
+            StatementKind::If { condition, then_branch, else_branch } => {
+                self.expression(condition);
+                self.scoped(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.scoped(else_branch);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                self.expression(condition);
+                self.enter(TargetKind::Loop);
+                self.scoped(body);
+                self.targets.pop();
+            }
+            StatementKind::DoWhile { body, condition } => {
+                self.enter(TargetKind::Loop);
+                self.scoped(body);
+                self.targets.pop();
+                self.expression(condition);
+            }
+            StatementKind::Switch { discriminant, cases } => {
+                self.expression(discriminant);
+                self.enter(TargetKind::Switch);
+                // The cases share one scope, but a case's declarations only run if the switch
+                // jumps to it or falls through from above, so later cases can't use them
+                self.scopes.push(HashMap::new());
+                for case in cases {
+                    if let Some(test) = &case.test {
+                        self.expression(test);
+                    }
+                }
+                self.hoist(cases.iter().flat_map(|case| &case.body));
+                for case in cases {
+                    for stmt in &case.body {
+                        self.statement(stmt);
+                    }
+                    for slot in self.scopes.last_mut().expect("no scope").values_mut() {
+                        if let Slot::Declared(id) = *slot {
+                            *slot = Slot::OtherCase(id);
+                        }
+                    }
+                }
+                self.scopes.pop();
+                self.targets.pop();
+            }
+            StatementKind::Labeled { label, body } => {
+                let outer = self.targets.iter().flat_map(|t| &t.labels).chain(&self.labels).find(|l| l.name == label.name);
+                if let Some(outer) = outer {
+                    let outer = outer.span;
+                    self.report(semantic_error("E0209", format!("Label '{}' is already declared", label.name), label.span)
+                        .with_label("duplicate label")
+                        .with_related(outer, "enclosing statement with the same label"));
+                }
+                self.labels.push(label.clone());
+                match body.kind {
+                    // These take the labels as their own
+                    StatementKind::Labeled { .. } | StatementKind::While { .. } | StatementKind::DoWhile { .. } | StatementKind::Switch { .. } => {
+                        self.statement(body);
+                    }
+                    _ => {
+                        self.enter(TargetKind::Block);
+                        self.scoped(body);
+                        self.targets.pop();
+                    }
+                }
+            }
+            StatementKind::Break(label) => self.jump(stmt, label.as_ref(), true),
+            StatementKind::Continue(label) => self.jump(stmt, label.as_ref(), false),
+            StatementKind::Return(value) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            StatementKind::Block(_) => self.scoped(stmt),
+            StatementKind::Expression(expr) => self.discarded(expr),
+        }
+    }
+
+    // Pushes a jump target carrying the labels collected for it
+    fn enter(&mut self, kind: TargetKind) {
+        let labels = std::mem::take(&mut self.labels);
+        self.targets.push(JumpTarget { labels, kind });
+    }
+
+    // Checks that a `break` (or `continue`) has somewhere to go
+    fn jump(&mut self, stmt: &Statement, label: Option<&Ident>, is_break: bool) {
+        let keyword = if is_break { "break" } else { "continue" };
+        let Some(label) = label else {
+            let valid = self.targets.iter().any(|t| t.kind == TargetKind::Loop || (is_break && t.kind == TargetKind::Switch));
+            if !valid {
+                let place = if is_break { "a loop or switch" } else { "a loop" };
+                self.report(semantic_error("E0207", format!("'{}' outside of {}", keyword, place), stmt.span)
+                    .with_label(format!("not inside {}", place)));
+            }
+            return;
+        };
+
+        let target = self.targets.iter().rev().find_map(|t| Some((t.kind, t.labels.iter().find(|l| l.name == label.name)?)));
+        match target {
+            None => {
+                let visible: Vec<&str> = self.targets.iter().flat_map(|t| &t.labels).map(|l| l.name.as_str()).collect();
+                let error = semantic_error("E0208", format!("Undefined label: {}", label.name), label.span)
+                    .with_label("no enclosing statement has this label");
+                let error = with_suggestion(error, &label.name, label.span, visible, "label");
+                self.report(error);
+            }
+            Some((kind, declared)) if !is_break && kind != TargetKind::Loop => {
+                let declared = declared.span;
+                self.report(semantic_error("E0207", format!("Label '{}' does not refer to a loop", label.name), label.span)
+                    .with_label("`continue` needs a loop")
+                    .with_related(declared, "label declared here"));
+            }
+            Some(_) => {}
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Number(_) | ExpressionKind::Boolean(_) | ExpressionKind::Null | ExpressionKind::Undefined => {}
+            ExpressionKind::String(_) => {
+                if !self.dynamic {
+                    self.report(needs_dynamic("String literals need", expr.span));
+                }
+            }
+            ExpressionKind::Unary(UnaryOp::Typeof, right) => {
+                if !self.dynamic {
+                    self.report(needs_dynamic("`typeof` needs", expr.span));
+                }
+                self.expression(right);
+            }
+            ExpressionKind::Identifier(name) => {
+                if let Some(id) = self.resolve(name, expr.span) {
+                    self.analysis.bindings[id].reads += 1;
+                }
+            }
+            ExpressionKind::Binary(left, _, right) => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExpressionKind::Unary(_, right) => self.expression(right),
+            ExpressionKind::Assignment(target, value) => {
+                self.expression(value);
+                self.assign(target, expr.span, false);
+            }
+            ExpressionKind::CompoundAssignment(target, _, value) => {
+                self.expression(value);
+                self.assign(target, expr.span, true);
+            }
+            ExpressionKind::Update { target, .. } => self.assign(target, expr.span, true),
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                self.expression(condition);
+                self.expression(then);
+                self.expression(otherwise);
+            }
+            ExpressionKind::Sequence(exprs) => {
+                for e in exprs {
+                    self.expression(e);
+                }
+            }
+            ExpressionKind::Call(callee, args) if let Some(builtin) = Builtin::parse(&callee.name) => {
+                let (min, max) = builtin.arity();
+                // `Math.min()` is Infinity and `Math.max()` -Infinity, which only --dynamic has
+                let no_operands = args.is_empty() && matches!(builtin, Builtin::Min | Builtin::Max);
+                if no_operands {
+                    if !self.dynamic {
+                        let result = if builtin == Builtin::Min { "Infinity" } else { "-Infinity" };
+                        self.report(semantic_error(
+                            "E0211",
+                            format!("'{}' with no arguments needs --dynamic", callee.name),
+                            expr.span,
+                        )
+                        .with_label(format!("this is {}, which an `i32` can't hold", result))
+                        .with_help("pass at least one argument, or compile with --dynamic"));
+                    }
+                } else if (args.len() < min || max.is_some_and(|max| args.len() > max)) && !self.loose_arity {
+                    let expected = match max {
+                        Some(max) if max == min => format!("{} {}", min, arguments(min)),
+                        _ => format!("at least {} {}", min, arguments(min)),
+                    };
+                    self.report(semantic_error(
+                        "E0203",
+                        format!("Function '{}' expects {}, found {}", callee.name, expected, args.len()),
+                        expr.span,
+                    )
+                    .with_label(format!("called with {} {}", args.len(), arguments(args.len()))));
+                }
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            ExpressionKind::Call(callee, args) => {
+                match self.analysis.function(&callee.name) {
+                    None => {
+                        let error = semantic_error("E0202", format!("Undefined function: {}", callee.name), callee.span)
+                            .with_label("no function with this name");
+                        let error = if callee.name.starts_with("Math.") {
+                            let builtins = Builtin::ALL.iter().map(|builtin| builtin.name());
+                            with_suggestion(error, &callee.name, callee.span, builtins, "function")
+                        } else {
+                            let functions = self.analysis.functions.iter().map(|f| f.name.as_str());
+                            with_suggestion(error, &callee.name, callee.span, functions, "function")
+                        };
+                        self.report(error);
+                    }
+                    Some(function) if function.params != args.len() && !self.loose_arity => {
+                        let function = function.clone();
+                        self.report(semantic_error(
+                            "E0203",
+                            format!("Function '{}' expects {} {}, found {}", function.name, function.params, arguments(function.params), args.len()),
+                            expr.span,
+                        )
+                        .with_label(format!("called with {} {}", args.len(), arguments(args.len())))
+                        .with_related(function.span, format!("'{}' declared here", function.name))
+                        .with_help("use --loose-arity to pad missing arguments with 0 and ignore extra ones, as JavaScript does"));
+                    }
+                    Some(_) => {}
+                }
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+        }
+    }
+
+    // An expression whose value is thrown away, as in `x++;`. Updating a variable there doesn't
+    // count as reading it, so a variable that is only ever incremented is still unused.
+    fn discarded(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::CompoundAssignment(target, _, value) => {
+                self.expression(value);
+                self.assign(target, expr.span, false);
+            }
+            ExpressionKind::Update { target, .. } => self.assign(target, expr.span, false),
+            ExpressionKind::Sequence(exprs) => {
+                for e in exprs {
+                    self.discarded(e);
+                }
+            }
+            _ => self.expression(expr),
+        }
+    }
+
+    // A write to `target` by the expression at `span`. `reads` is for compound assignments and
+    // `++`/`--` whose result is used, which also read the old value.
+    fn assign(&mut self, target: &Ident, span: Span, reads: bool) {
+        let Some(id) = self.resolve(&target.name, target.span) else { return };
+        if reads {
+            self.analysis.bindings[id].reads += 1;
+        }
+        self.analysis.bindings[id].writes += 1;
+        let binding = &self.analysis.bindings[id];
+        if binding.is_const() {
+            // The declaration starts with the `const` keyword
+            let decl = binding.declaration;
+            let keyword = Span { end_line: decl.line, end_column: decl.column + 5, end: decl.start + 5, ..decl };
+            let related = binding.span;
+            self.report(semantic_error("E0201", format!("Assignment to constant variable '{}'", target.name), span)
+                .with_label("cannot assign to a constant")
+                .with_related(related, format!("'{}' declared as const here", target.name))
+                .with_help(format!("declare '{}' with `let` if it needs to change", target.name))
+                .with_fix("change `const` to `let`", keyword, "let"));
+        }
+    }
+}
+
+fn arguments(n: usize) -> &'static str {
+    if n == 1 { "argument" } else { "arguments" }
+}
+
+// Adds a "did you mean" help and fix when one of `candidates` is close enough to `name` to be a typo
+fn with_suggestion<'a>(
+    error: CompileError,
+    name: &str,
+    span: Span,
+    candidates: impl IntoIterator<Item = &'a str>,
+    what: &str,
+) -> CompileError {
+    // Allow roughly one edit per three characters
+    let limit = (name.chars().count() / 3).max(1);
+    let best = candidates.into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min();
+    match best {
+        Some((_, candidate)) => error
+            .with_help(format!("a {} with a similar name exists: `{}`", what, candidate))
+            .with_fix(format!("replace with `{}`", candidate), span, candidate),
+        None => error,
+    }
+}
+
+// Levenshtein distance, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn duplicate(name: &Ident, first: Span) -> CompileError {
+    semantic_error("E0204", format!("Duplicate declaration of '{}'", name.name), name.span)
+        .with_label("already declared in this scope")
+        .with_related(first, format!("'{}' first declared here", name.name))
+}
+
+// Strings only exist when values carry their type
+fn needs_dynamic(what: &str, span: Span) -> CompileError {
+    semantic_error("E0210", format!("{} --dynamic", what), span)
+        .with_label("every value is an `i32` without --dynamic")
+        .with_help("compile with --dynamic so values carry their type at runtime")
+}
+
+fn semantic_error(code: &'static str, message: String, span: Span) -> CompileError {
+    CompileError::new(ErrorKind::Semantic, message, Some(span)).with_code(code)
+}
@@ -1,12 +1,45 @@
-#[derive(Debug, Clone, Copy, PartialEq)]
+use std::fmt;
+
+// A range of source text. `line`/`column` are where it starts, `end_line`/`end_column`
+// where it ends (exclusive), and `start`/`end` are the same range as byte offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct Span {
     pub line: usize,
     pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// A point in the source
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
 }
 
 impl Span {
-    pub fn new(line: usize, column: usize) -> Self {
-        Span { line, column }
+    pub fn new(start: Position, end: Position) -> Self {
+        Span {
+            line: start.line,
+            column: start.column,
+            end_line: end.line,
+            end_column: end.column,
+            start: start.offset,
+            end: end.offset,
+        }
+    }
+
+    // Span covering both `self` and `other`, assuming `self` comes first
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            end_line: other.end_line,
+            end_column: other.end_column,
+            end: other.end,
+            ..*self
+        }
     }
 }
 
@@ -14,27 +47,156 @@ impl Span {
 pub struct SpannedToken {
     pub token: Token,
     pub span: Span,
+    // Only filled in by `Lexer::with_trivia`. Trailing trivia runs up to the end of the token's
+    // line; everything after that leads the next token.
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+    // A line break (possibly inside a comment) separates this token from the one before it.
+    // The parser needs this for automatic semicolon insertion.
+    pub newline_before: bool,
+}
+
+impl SpannedToken {
+    pub fn new(token: Token, span: Span) -> Self {
+        SpannedToken { token, span, leading: Vec::new(), trailing: Vec::new(), newline_before: false }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    LineComment,
+    BlockComment,
+    // `/** ... */`, documenting the declaration that follows
+    DocComment,
+}
+
+// Source text between tokens that doesn't change the program. Comments include their delimiters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span,
+}
+
+impl Trivia {
+    pub fn is_comment(&self) -> bool {
+        self.kind != TriviaKind::Whitespace
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Number(i32),
+    // Up to 2^31, which only fits in an i32 once negated, or any double with
+    // `Lexer::with_doubles`
+    Number(f64),
     Identifier(String),
+    // With its escapes already applied
+    String(String),
 
     // Key words
     Let, Const, If, Else, While,
     Function, Return,
+    Do, Switch, Case, Default, Break, Continue,
+    True, False, Null, Undefined, Typeof,
 
     // Delimiters
     LParen, RParen,   // ( )
     LBrace, RBrace,   // { }
-    Comma, Semi,      // , ;
+    Comma, Semi, Dot, // , ; .
+    Question, Colon,  // ? :
 
     // Operators
     Plus, Minus, Star, Slash, Percent, //  + - * / %
+    StarStar,                          //  **
     Eq, EqEq, Bang, BangEq,            //  = == ! !=
+    EqEqEq, BangEqEq,                  //  === !==
     Lt, LtEq, Gt, GtEq,                //  < <= > >=
+    Amp, Pipe, Caret, Tilde,           //  & | ^ ~
+    Shl, Shr, UShr,                    //  << >> >>>
+    PlusPlus, MinusMinus,              //  ++ --
+    PlusEq, MinusEq, StarEq,           //  += -= *=
+    SlashEq, PercentEq, StarStarEq,    //  /= %= **=
+    AmpEq, PipeEq, CaretEq,            //  &= |= ^=
+    ShlEq, ShrEq, UShrEq,              //  <<= >>= >>>=
 
     // EOF
     EOF
 }
+
+// Human-readable names, as used in error messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Token::Number(n) => return write!(f, "number `{}`", n),
+            Token::Identifier(name) => return write!(f, "identifier `{}`", name),
+            Token::String(s) => return write!(f, "string {:?}", s),
+            Token::EOF => return write!(f, "end of file"),
+            Token::Let => "let",
+            Token::Const => "const",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::While => "while",
+            Token::Function => "function",
+            Token::Return => "return",
+            Token::Do => "do",
+            Token::Switch => "switch",
+            Token::Case => "case",
+            Token::Default => "default",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Null => "null",
+            Token::Undefined => "undefined",
+            Token::Typeof => "typeof",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::Comma => ",",
+            Token::Semi => ";",
+            Token::Dot => ".",
+            Token::Question => "?",
+            Token::Colon => ":",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::StarStar => "**",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Eq => "=",
+            Token::EqEq => "==",
+            Token::Bang => "!",
+            Token::BangEq => "!=",
+            Token::EqEqEq => "===",
+            Token::BangEqEq => "!==",
+            Token::Lt => "<",
+            Token::LtEq => "<=",
+            Token::Gt => ">",
+            Token::GtEq => ">=",
+            Token::Amp => "&",
+            Token::Pipe => "|",
+            Token::Caret => "^",
+            Token::Tilde => "~",
+            Token::Shl => "<<",
+            Token::Shr => ">>",
+            Token::UShr => ">>>",
+            Token::PlusPlus => "++",
+            Token::MinusMinus => "--",
+            Token::PlusEq => "+=",
+            Token::MinusEq => "-=",
+            Token::StarEq => "*=",
+            Token::SlashEq => "/=",
+            Token::PercentEq => "%=",
+            Token::StarStarEq => "**=",
+            Token::AmpEq => "&=",
+            Token::PipeEq => "|=",
+            Token::CaretEq => "^=",
+            Token::ShlEq => "<<=",
+            Token::ShrEq => ">>=",
+            Token::UShrEq => ">>>=",
+        };
+        write!(f, "`{}`", symbol)
+    }
+}
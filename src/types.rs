@@ -0,0 +1,253 @@
+use crate::ast::{BinaryOp, Expression, ExpressionKind, Program, Statement, StatementKind, UnaryOp};
+use crate::builtins::Builtin;
+use crate::sema::{Analysis, BindingKind};
+use crate::token::Span;
+use crate::value::Type;
+use std::collections::HashMap;
+
+// Type inference for `--dynamic`. A variable has a static type if every value assigned to it
+// has that type, and a function if every value it returns has, so codegen can skip the runtime
+// for them: `let i = 0; while (i < n) i++` compares doubles instead of calling `runtime.compare`.
+// Parameters are never typed, since callers can pass anything.
+//
+// Inference is optimistic: it starts out knowing nothing about any variable and keeps joining in
+// the types of what is assigned to them until nothing changes, so `i = i + 1` in a loop still
+// leaves `i` a number.
+
+// What is known about the values of a variable, function or expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Known {
+    // No value seen yet
+    Nothing,
+    Is(Type),
+    // Values of more than one type, or of a type that can't be known
+    Varies,
+}
+
+impl Known {
+    fn join(self, other: Known) -> Known {
+        match (self, other) {
+            (Known::Nothing, known) | (known, Known::Nothing) => known,
+            (Known::Is(a), Known::Is(b)) if a == b => Known::Is(a),
+            _ => Known::Varies,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Types {
+    // By where the variable's name is declared
+    variables: HashMap<usize, Known>,
+    functions: HashMap<String, Known>,
+}
+
+impl Types {
+    // The type `expr` evaluates to, if it's the same every time
+    pub fn of(&self, expr: &Expression, analysis: &Analysis) -> Option<Type> {
+        match self.infer(expr, analysis) {
+            Known::Is(ty) => Some(ty),
+            Known::Nothing | Known::Varies => None,
+        }
+    }
+
+    fn infer(&self, expr: &Expression, analysis: &Analysis) -> Known {
+        match &expr.kind {
+            ExpressionKind::Number(_) => Known::Is(Type::Number),
+            ExpressionKind::Boolean(_) => Known::Is(Type::Boolean),
+            ExpressionKind::String(_) => Known::Is(Type::String),
+            ExpressionKind::Null => Known::Is(Type::Null),
+            ExpressionKind::Undefined => Known::Is(Type::Undefined),
+            ExpressionKind::Identifier(_) => self.variable(expr.span, analysis),
+            ExpressionKind::Binary(left, op, right) => {
+                binary(op, self.infer(left, analysis), self.infer(right, analysis))
+            }
+            ExpressionKind::Unary(UnaryOp::Not, _) => Known::Is(Type::Boolean),
+            ExpressionKind::Unary(UnaryOp::Typeof, _) => Known::Is(Type::String),
+            ExpressionKind::Unary(..) | ExpressionKind::Update { .. } => Known::Is(Type::Number),
+            ExpressionKind::Assignment(_, value) => self.infer(value, analysis),
+            ExpressionKind::CompoundAssignment(target, op, value) => {
+                binary(op, self.variable(target.span, analysis), self.infer(value, analysis))
+            }
+            ExpressionKind::Conditional(_, then, otherwise) => {
+                self.infer(then, analysis).join(self.infer(otherwise, analysis))
+            }
+            ExpressionKind::Sequence(exprs) => {
+                exprs.last().map_or(Known::Varies, |last| self.infer(last, analysis))
+            }
+            ExpressionKind::Call(callee, _) if Builtin::parse(&callee.name).is_some() => Known::Is(Type::Number),
+            ExpressionKind::Call(callee, _) => self.functions.get(&callee.name).copied().unwrap_or(Known::Nothing),
+        }
+    }
+
+    // `span` is where the variable is used
+    fn variable(&self, span: Span, analysis: &Analysis) -> Known {
+        let Some(binding) = analysis.binding(span) else { return Known::Varies };
+        if binding.kind == BindingKind::Param {
+            return Known::Varies;
+        }
+        self.variables.get(&binding.span.start).copied().unwrap_or(Known::Nothing)
+    }
+}
+
+// The type of `left op right`
+fn binary(op: &BinaryOp, left: Known, right: Known) -> Known {
+    match op {
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::StrictEq | BinaryOp::StrictNe
+            | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge => Known::Is(Type::Boolean),
+        // A string on either side makes `+` concatenate
+        BinaryOp::Add => match (left, right) {
+            (Known::Nothing, _) | (_, Known::Nothing) => Known::Nothing,
+            (Known::Is(Type::String), _) | (_, Known::Is(Type::String)) => Known::Is(Type::String),
+            (Known::Is(_), Known::Is(_)) => Known::Is(Type::Number),
+            _ => Known::Varies,
+        },
+        _ => Known::Is(Type::Number),
+    }
+}
+
+pub fn infer(program: &Program, analysis: &Analysis) -> Types {
+    let mut types = Types::default();
+    loop {
+        let mut inference = Inference { known: &types, analysis, found: Types::default(), function: None };
+        for stmt in &program.body {
+            inference.statement(stmt);
+        }
+        if inference.found == types {
+            return types;
+        }
+        types = inference.found;
+    }
+}
+
+// One round of inference: the types of everything assigned and returned, given what the
+// previous round found
+struct Inference<'a> {
+    known: &'a Types,
+    analysis: &'a Analysis,
+    found: Types,
+    // The function whose body is being walked, None for top-level code
+    function: Option<String>,
+}
+
+impl Inference<'_> {
+    fn statement(&mut self, stmt: &Statement) {
+        match &stmt.kind {
+            StatementKind::VariableDeclaration { name, init, .. } => {
+                self.expression(init);
+                let known = self.known.infer(init, self.analysis);
+                self.assign(name.span, known);
+            }
+            StatementKind::FunctionDeclaration { name, body, .. } => {
+                self.function = Some(name.name.clone());
+                for s in body {
+                    self.statement(s);
+                }
+                // Falling off the end returns undefined
+                if !body.last().is_some_and(always_returns) {
+                    self.returns(Known::Is(Type::Undefined));
+                }
+                self.function = None;
+            }
+            StatementKind::If { condition, then_branch, else_branch } => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            StatementKind::While { condition, body } | StatementKind::DoWhile { body, condition } => {
+                self.expression(condition);
+                self.statement(body);
+            }
+            StatementKind::Switch { discriminant, cases } => {
+                self.expression(discriminant);
+                for case in cases {
+                    if let Some(test) = &case.test {
+                        self.expression(test);
+                    }
+                    for s in &case.body {
+                        self.statement(s);
+                    }
+                }
+            }
+            StatementKind::Labeled { body, .. } => self.statement(body),
+            StatementKind::Block(stmts) => {
+                for s in stmts {
+                    self.statement(s);
+                }
+            }
+            StatementKind::Return(value) => {
+                let known = match value {
+                    Some(value) => {
+                        self.expression(value);
+                        self.known.infer(value, self.analysis)
+                    }
+                    None => Known::Is(Type::Undefined),
+                };
+                self.returns(known);
+            }
+            StatementKind::Expression(expr) => self.expression(expr),
+            StatementKind::Break(_) | StatementKind::Continue(_) => {}
+        }
+    }
+
+    // Finds the assignments in `expr`
+    fn expression(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Assignment(target, value) => {
+                self.expression(value);
+                let known = self.known.infer(value, self.analysis);
+                self.assign(target.span, known);
+            }
+            ExpressionKind::CompoundAssignment(target, _, value) => {
+                self.expression(value);
+                let known = self.known.infer(expr, self.analysis);
+                self.assign(target.span, known);
+            }
+            ExpressionKind::Update { target, .. } => self.assign(target.span, Known::Is(Type::Number)),
+            ExpressionKind::Binary(left, _, right) => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExpressionKind::Unary(_, operand) => self.expression(operand),
+            ExpressionKind::Conditional(condition, then, otherwise) => {
+                self.expression(condition);
+                self.expression(then);
+                self.expression(otherwise);
+            }
+            ExpressionKind::Sequence(exprs) | ExpressionKind::Call(_, exprs) => {
+                for e in exprs {
+                    self.expression(e);
+                }
+            }
+            ExpressionKind::Identifier(_) | ExpressionKind::Number(_) | ExpressionKind::Boolean(_)
+            | ExpressionKind::Null | ExpressionKind::Undefined | ExpressionKind::String(_) => {}
+        }
+    }
+
+    // `span` is the name of the variable assigned to
+    fn assign(&mut self, span: Span, known: Known) {
+        let Some(binding) = self.analysis.binding(span) else { return };
+        let entry = self.found.variables.entry(binding.span.start).or_insert(Known::Nothing);
+        *entry = entry.join(known);
+    }
+
+    fn returns(&mut self, known: Known) {
+        // Top-level code is `main`, which nothing calls
+        let Some(function) = &self.function else { return };
+        let entry = self.found.functions.entry(function.clone()).or_insert(Known::Nothing);
+        *entry = entry.join(known);
+    }
+}
+
+// Whether a statement can't finish without returning
+fn always_returns(stmt: &Statement) -> bool {
+    match &stmt.kind {
+        StatementKind::Return(_) => true,
+        StatementKind::Block(stmts) => stmts.last().is_some_and(always_returns),
+        StatementKind::If { then_branch, else_branch: Some(else_branch), .. } => {
+            always_returns(then_branch) && always_returns(else_branch)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+// With `--dynamic`, a value is a 64-bit word that says what type it has, so `typeof` and `===`
+// can tell `1`, `true`, `null` and `undefined` apart. It's NaN-boxing: a number is stored as the
+// bits of its f64, and everything else hides in NaN patterns with a payload. Wasm arithmetic only
+// ever produces NaNs without one, so they can't be mistaken for a number:
+//
+//   0x7FFC_0000_0000_0000   undefined
+//   0x7FFC_0000_0000_0001   null
+//   0x7FFD_0000_0000_000b   false (b = 0) and true (b = 1)
+//   0x7FFE_0000_aaaa_aaaa   the string at address a of linear memory (see `Strings`)
+
+pub const UNDEFINED: i64 = 0x7FFC << 48;
+pub const NULL: i64 = UNDEFINED | 1;
+pub const FALSE: i64 = 0x7FFD << 48;
+pub const TRUE: i64 = FALSE | 1;
+pub const STRING: i64 = 0x7FFE << 48;
+
+// Every non-number has these top 14 bits, which leaves 2 bits of the top 16 for the tag
+pub const BOXED: i64 = 0x7FFC >> 2;
+
+pub fn number(n: f64) -> i64 {
+    n.to_bits() as i64
+}
+
+pub fn boolean(b: bool) -> i64 {
+    if b { TRUE } else { FALSE }
+}
+
+// What a value is known to be at compile time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Number,
+    Boolean,
+    String,
+    Undefined,
+    Null,
+}
+
+impl Type {
+    // What `typeof` says
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Number => "number",
+            Type::Boolean => "boolean",
+            Type::String => "string",
+            Type::Undefined => "undefined",
+            Type::Null => "object",
+        }
+    }
+}
+
+// The string literals of a module, laid out in linear memory as a 4-byte length followed by the
+// UTF-8 bytes. Each text is stored once; strings built at run time go on the heap after them
+// (see `runtime::HEAP`), so equal strings can still have different addresses.
+#[derive(Debug, Default)]
+pub struct Strings {
+    data: Vec<u8>,
+    addresses: HashMap<String, u32>,
+}
+
+impl Strings {
+    // The value of the string `s`
+    pub fn value(&mut self, s: &str) -> i64 {
+        if let Some(address) = self.addresses.get(s) {
+            return STRING | *address as i64;
+        }
+        // The length is read with an aligned `i32.load`
+        self.data.resize(self.data.len().next_multiple_of(4), 0);
+        let address = self.data.len() as u32;
+        self.data.extend((s.len() as u32).to_le_bytes());
+        self.data.extend(s.as_bytes());
+        self.addresses.insert(s.to_string(), address);
+        STRING | address as i64
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
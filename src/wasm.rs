@@ -0,0 +1,394 @@
+use std::fmt;
+
+// Structured form of the WebAssembly we emit. Codegen builds a `Module`, the
+// optimization passes rewrite it, and only at the very end is it rendered to WAT.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValType {
+    I32,
+    // Tagged values under `--dynamic`
+    I64,
+    F64,
+}
+
+impl fmt::Display for ValType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValType::I32 => write!(f, "i32"),
+            ValType::I64 => write!(f, "i64"),
+            ValType::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    LocalGet(String),
+    LocalSet(String),
+    LocalTee(String),
+    GlobalGet(String),
+    GlobalSet(String),
+
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32DivS,
+    I32RemS,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32GtS,
+    I32LeS,
+    I32GeS,
+    I32LtU,
+    I32GtU,
+    I32Eqz,
+    I32And,
+    I32Or,
+    I32Xor,
+    I32Shl,
+    I32ShrS,
+    I32ShrU,
+    I32Clz,
+    // For `Math.sqrt`: there is no integer square root
+    F64ConvertI32S,
+    F64Sqrt,
+    I32TruncSatF64S,
+    // Reads the `i32` at the address on the stack plus `offset`
+    I32Load(u32),
+    // The same for a byte, and the stores: address, then value
+    I32Load8U(u32),
+    I64Load(u32),
+    I32Store(u32),
+    I32Store8(u32),
+    // Size of linear memory in 64 KiB pages, and growing it by a number of pages
+    MemorySize,
+    MemoryGrow,
+    // destination, source, length
+    MemoryCopy,
+    // destination, byte, length
+    MemoryFill,
+
+    // Tagged values (see value.rs) and the doubles they hold
+    I64Eq,
+    I64Ne,
+    I64Eqz,
+    I64And,
+    I64Or,
+    I64ShrU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivU,
+    I64RemU,
+    I64ExtendI32U,
+    I32WrapI64,
+    I64TruncSatF64S,
+    I64TruncSatF64U,
+    F64ConvertI64S,
+    F64ConvertI64U,
+    I64ReinterpretF64,
+    F64ReinterpretI64,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    F64Min,
+    F64Max,
+    F64Copysign,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Gt,
+    F64Le,
+    F64Ge,
+    F64Neg,
+    F64Abs,
+    F64Floor,
+    F64Ceil,
+    F64Trunc,
+    F64Nearest,
+    F64ConvertI32U,
+    I32TruncSatF64U,
+
+    Call(String),
+    Drop,
+    // Picks the first of two values if the condition on top of the stack is non-zero
+    Select,
+    Return,
+    Br(String),
+    BrIf(String),
+    // Branches to `labels[n]` for the `n` on top of the stack, or to `default` if it's out of range
+    BrTable { labels: Vec<String>, default: String },
+
+    // Structured control flow. Bodies are nested so passes never have to match up `end`s.
+    Block { label: String, body: Vec<Instruction> },
+    Loop { label: String, body: Vec<Instruction> },
+    // `result` is the type an `if` used as an expression leaves on the stack
+    If { result: Option<ValType>, then_body: Vec<Instruction>, else_body: Option<Vec<Instruction>> },
+}
+
+impl Instruction {
+    // Number of instructions including everything nested inside blocks
+    pub fn count(&self) -> usize {
+        match self {
+            Instruction::Block { body, .. } | Instruction::Loop { body, .. } => 1 + count(body),
+            Instruction::If { then_body, else_body, .. } => {
+                1 + count(then_body) + else_body.as_deref().map_or(0, count)
+            }
+            _ => 1,
+        }
+    }
+
+    // True for instructions that always leave 0 or 1 on the stack
+    pub fn is_boolean(&self) -> bool {
+        matches!(
+            self,
+            Instruction::I32Eq | Instruction::I32Ne
+                | Instruction::I32LtS | Instruction::I32GtS
+                | Instruction::I32LeS | Instruction::I32GeS
+                | Instruction::I32LtU | Instruction::I32GtU
+                | Instruction::I32Eqz
+                | Instruction::I64Eq | Instruction::I64Ne | Instruction::I64Eqz
+                | Instruction::F64Eq | Instruction::F64Ne
+                | Instruction::F64Lt | Instruction::F64Gt
+                | Instruction::F64Le | Instruction::F64Ge
+        )
+    }
+}
+
+pub fn count(body: &[Instruction]) -> usize {
+    body.iter().map(Instruction::count).sum()
+}
+
+impl fmt::Display for Instruction {
+    // Renders a single (non-structured) instruction
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::I32Const(n) => write!(f, "i32.const {}", n),
+            Instruction::I64Const(n) => write!(f, "i64.const {}", n),
+            Instruction::F64Const(x) if x.is_nan() => write!(f, "f64.const nan"),
+            Instruction::F64Const(x) if x.is_infinite() => write!(f, "f64.const {}inf", if *x < 0.0 { "-" } else { "" }),
+            Instruction::F64Const(x) => write!(f, "f64.const {}", x),
+            Instruction::LocalGet(name) => write!(f, "local.get ${}", name),
+            Instruction::LocalSet(name) => write!(f, "local.set ${}", name),
+            Instruction::LocalTee(name) => write!(f, "local.tee ${}", name),
+            Instruction::GlobalGet(name) => write!(f, "global.get ${}", name),
+            Instruction::GlobalSet(name) => write!(f, "global.set ${}", name),
+            Instruction::I32Add => write!(f, "i32.add"),
+            Instruction::I32Sub => write!(f, "i32.sub"),
+            Instruction::I32Mul => write!(f, "i32.mul"),
+            Instruction::I32DivS => write!(f, "i32.div_s"),
+            Instruction::I32RemS => write!(f, "i32.rem_s"),
+            Instruction::I32Eq => write!(f, "i32.eq"),
+            Instruction::I32Ne => write!(f, "i32.ne"),
+            Instruction::I32LtS => write!(f, "i32.lt_s"),
+            Instruction::I32GtS => write!(f, "i32.gt_s"),
+            Instruction::I32LeS => write!(f, "i32.le_s"),
+            Instruction::I32GeS => write!(f, "i32.ge_s"),
+            Instruction::I32LtU => write!(f, "i32.lt_u"),
+            Instruction::I32GtU => write!(f, "i32.gt_u"),
+            Instruction::I32Eqz => write!(f, "i32.eqz"),
+            Instruction::I32And => write!(f, "i32.and"),
+            Instruction::I32Or => write!(f, "i32.or"),
+            Instruction::I32Xor => write!(f, "i32.xor"),
+            Instruction::I32Shl => write!(f, "i32.shl"),
+            Instruction::I32ShrS => write!(f, "i32.shr_s"),
+            Instruction::I32ShrU => write!(f, "i32.shr_u"),
+            Instruction::I32Clz => write!(f, "i32.clz"),
+            Instruction::F64ConvertI32S => write!(f, "f64.convert_i32_s"),
+            Instruction::F64Sqrt => write!(f, "f64.sqrt"),
+            Instruction::I32TruncSatF64S => write!(f, "i32.trunc_sat_f64_s"),
+            Instruction::I32Load(offset) => write!(f, "i32.load offset={}", offset),
+            Instruction::I32Load8U(offset) => write!(f, "i32.load8_u offset={}", offset),
+            Instruction::I64Load(offset) => write!(f, "i64.load offset={}", offset),
+            Instruction::I32Store(offset) => write!(f, "i32.store offset={}", offset),
+            Instruction::I32Store8(offset) => write!(f, "i32.store8 offset={}", offset),
+            Instruction::MemorySize => write!(f, "memory.size"),
+            Instruction::MemoryGrow => write!(f, "memory.grow"),
+            Instruction::MemoryCopy => write!(f, "memory.copy"),
+            Instruction::MemoryFill => write!(f, "memory.fill"),
+            Instruction::I64Eq => write!(f, "i64.eq"),
+            Instruction::I64Ne => write!(f, "i64.ne"),
+            Instruction::I64Eqz => write!(f, "i64.eqz"),
+            Instruction::I64And => write!(f, "i64.and"),
+            Instruction::I64Or => write!(f, "i64.or"),
+            Instruction::I64ShrU => write!(f, "i64.shr_u"),
+            Instruction::I64Add => write!(f, "i64.add"),
+            Instruction::I64Sub => write!(f, "i64.sub"),
+            Instruction::I64Mul => write!(f, "i64.mul"),
+            Instruction::I64DivU => write!(f, "i64.div_u"),
+            Instruction::I64RemU => write!(f, "i64.rem_u"),
+            Instruction::I64ExtendI32U => write!(f, "i64.extend_i32_u"),
+            Instruction::I32WrapI64 => write!(f, "i32.wrap_i64"),
+            Instruction::I64TruncSatF64S => write!(f, "i64.trunc_sat_f64_s"),
+            Instruction::I64TruncSatF64U => write!(f, "i64.trunc_sat_f64_u"),
+            Instruction::F64ConvertI64S => write!(f, "f64.convert_i64_s"),
+            Instruction::F64ConvertI64U => write!(f, "f64.convert_i64_u"),
+            Instruction::I64ReinterpretF64 => write!(f, "i64.reinterpret_f64"),
+            Instruction::F64ReinterpretI64 => write!(f, "f64.reinterpret_i64"),
+            Instruction::F64Add => write!(f, "f64.add"),
+            Instruction::F64Sub => write!(f, "f64.sub"),
+            Instruction::F64Mul => write!(f, "f64.mul"),
+            Instruction::F64Div => write!(f, "f64.div"),
+            Instruction::F64Min => write!(f, "f64.min"),
+            Instruction::F64Max => write!(f, "f64.max"),
+            Instruction::F64Copysign => write!(f, "f64.copysign"),
+            Instruction::F64Eq => write!(f, "f64.eq"),
+            Instruction::F64Ne => write!(f, "f64.ne"),
+            Instruction::F64Lt => write!(f, "f64.lt"),
+            Instruction::F64Gt => write!(f, "f64.gt"),
+            Instruction::F64Le => write!(f, "f64.le"),
+            Instruction::F64Ge => write!(f, "f64.ge"),
+            Instruction::F64Neg => write!(f, "f64.neg"),
+            Instruction::F64Abs => write!(f, "f64.abs"),
+            Instruction::F64Floor => write!(f, "f64.floor"),
+            Instruction::F64Ceil => write!(f, "f64.ceil"),
+            Instruction::F64Trunc => write!(f, "f64.trunc"),
+            Instruction::F64Nearest => write!(f, "f64.nearest"),
+            Instruction::F64ConvertI32U => write!(f, "f64.convert_i32_u"),
+            Instruction::I32TruncSatF64U => write!(f, "i32.trunc_sat_f64_u"),
+            Instruction::Call(name) => write!(f, "call ${}", name),
+            Instruction::Drop => write!(f, "drop"),
+            Instruction::Select => write!(f, "select"),
+            Instruction::Return => write!(f, "return"),
+            Instruction::Br(label) => write!(f, "br ${}", label),
+            Instruction::BrIf(label) => write!(f, "br_if ${}", label),
+            Instruction::BrTable { labels, default } => {
+                write!(f, "br_table")?;
+                for label in labels.iter().chain([default]) {
+                    write!(f, " ${}", label)?;
+                }
+                Ok(())
+            }
+            Instruction::Block { label, .. } => write!(f, "block ${}", label),
+            Instruction::Loop { label, .. } => write!(f, "loop ${}", label),
+            Instruction::If { .. } => write!(f, "if"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Local {
+    pub name: String,
+    pub ty: ValType,
+}
+
+impl Local {
+    pub fn new(name: &str, ty: ValType) -> Self {
+        Local { name: name.to_string(), ty }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<Local>,
+    pub result: ValType,
+    pub locals: Vec<Local>,
+    pub body: Vec<Instruction>,
+}
+
+impl Function {
+    pub fn instruction_count(&self) -> usize {
+        count(&self.body)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    // (export name, function name)
+    pub exports: Vec<(String, String)>,
+    // Whether the module has a linear memory (exported as "memory"), and its initial contents
+    // from address 0
+    pub memory: bool,
+    pub data: Vec<u8>,
+    // Mutable `i32` globals and their initial values
+    pub globals: Vec<(String, i32)>,
+}
+
+impl Module {
+    pub fn instruction_count(&self) -> usize {
+        self.functions.iter().map(Function::instruction_count).sum()
+    }
+
+    pub fn to_wat(&self) -> String {
+        let mut out = String::from("(module\n");
+        for func in &self.functions {
+            out.push_str(&format!("  (func ${} ", func.name));
+            for param in &func.params {
+                out.push_str(&format!("(param ${} {}) ", param.name, param.ty));
+            }
+            out.push_str(&format!("(result {})\n", func.result));
+            for local in &func.locals {
+                out.push_str(&format!("    (local ${} {})\n", local.name, local.ty));
+            }
+            write_body(&mut out, &func.body, 2);
+            out.push_str("  )\n");
+        }
+        if self.memory {
+            out.push_str(&format!("  (memory {})\n", self.memory_pages()));
+            if !self.data.is_empty() {
+                out.push_str(&format!("  (data (i32.const 0) \"{}\")\n", escape_data(&self.data)));
+            }
+            out.push_str("  (export \"memory\" (memory 0))\n");
+        }
+        for (name, value) in &self.globals {
+            out.push_str(&format!("  (global ${} (mut i32) (i32.const {}))\n", name, value));
+        }
+        for (export, func) in &self.exports {
+            out.push_str(&format!("  (export \"{}\" (func ${}))\n", export, func));
+        }
+        out.push_str(")\n");
+        out
+    }
+
+    // 64 KiB pages needed to hold `data`
+    pub fn memory_pages(&self) -> u32 {
+        self.data.len().div_ceil(65536).max(1) as u32
+    }
+}
+
+// Bytes as a WAT string: printable ASCII as is, everything else as `\hh`
+fn escape_data(data: &[u8]) -> String {
+    data.iter()
+        .map(|&byte| match byte {
+            b'"' | b'\\' | ..0x20 | 0x7F.. => format!("\\{:02x}", byte),
+            _ => (byte as char).to_string(),
+        })
+        .collect()
+}
+
+fn write_body(out: &mut String, body: &[Instruction], depth: usize) {
+    let indent = "  ".repeat(depth);
+    for instr in body {
+        match instr {
+            Instruction::Block { label, body } | Instruction::Loop { label, body } => {
+                let keyword = if matches!(instr, Instruction::Block { .. }) { "block" } else { "loop" };
+                out.push_str(&format!("{}({} ${}\n", indent, keyword, label));
+                write_body(out, body, depth + 1);
+                out.push_str(&format!("{})\n", indent));
+            }
+            Instruction::If { result, then_body, else_body } => {
+                match result {
+                    Some(ty) => out.push_str(&format!("{}(if (result {})\n", indent, ty)),
+                    None => out.push_str(&format!("{}(if\n", indent)),
+                }
+                out.push_str(&format!("{}  (then\n", indent));
+                write_body(out, then_body, depth + 2);
+                out.push_str(&format!("{}  )\n", indent));
+                if let Some(else_body) = else_body {
+                    out.push_str(&format!("{}  (else\n", indent));
+                    write_body(out, else_body, depth + 2);
+                    out.push_str(&format!("{}  )\n", indent));
+                }
+                out.push_str(&format!("{})\n", indent));
+            }
+            _ => out.push_str(&format!("{}{}\n", indent, instr)),
+        }
+    }
+}
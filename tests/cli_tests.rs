@@ -0,0 +1,113 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+// Runs the compiler binary with `args`, feeding `stdin` to it
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_humera-js-compiler"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start compiler");
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_stdin_to_stdout() {
+    let output = run(&["--quiet", "-"], "let x = 1; x;");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("(module"));
+    assert!(output.stderr.is_empty(), "--quiet still printed: {}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_emit_wasm() {
+    let output = run(&["-q", "--emit", "wasm", "-"], "1 + 2;");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(&output.stdout[..8], b"\0asm\x01\0\0\0");
+}
+
+#[test]
+fn test_output_file() {
+    let path = std::env::temp_dir().join(format!("humera-cli-test-{}.wat", std::process::id()));
+    let output = run(&["-q", "-o", path.to_str().unwrap(), "-"], "let x = 1;");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    let wat = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(wat.contains("(local $x_0 i32)"));
+}
+
+#[test]
+fn test_output_dir() {
+    let dir = std::env::temp_dir().join(format!("humera-cli-dir-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let a = dir.join("a.js");
+    let b = dir.join("b.js");
+    std::fs::write(&a, "let unused = 1;").unwrap();
+    std::fs::write(&b, "function f(x) { return x * 2; } f(21);").unwrap();
+    let out = dir.join("out");
+    let output = run(&["-q", "-o", out.to_str().unwrap(), a.to_str().unwrap(), b.to_str().unwrap()], "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr);
+    // Each file is linted once, before it's compiled
+    assert_eq!(stderr.matches("is never read").count(), 1, "{}", stderr);
+    assert!(stderr.contains("a.js"), "{}", stderr);
+    let a_wat = std::fs::read_to_string(out.join("a.wat")).unwrap();
+    let b_wat = std::fs::read_to_string(out.join("b.wat")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(a_wat.contains("(local $unused_0 i32)"));
+    assert!(b_wat.contains("(func $f (param $x i32) (result i32)"), "{}", b_wat);
+}
+
+#[test]
+fn test_error_snippet() {
+    let output = run(&["-q", "-"], "let x = 1;\nx = y;");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(stderr.contains("error[E0200]: Undefined variable: y\n --> <stdin>:2:5\n"), "{}", stderr);
+    assert!(stderr.contains("2 | x = y;\n  |     ^ not found in this scope\n"), "{}", stderr);
+    assert!(!stderr.contains('\x1b'), "colour written to a pipe: {}", stderr);
+}
+
+#[test]
+fn test_error_format_json() {
+    let output = run(&["-q", "--error-format", "json", "-"], "let x = 1;\nx = y;");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+    assert!(stderr.starts_with(r#"{"code":"E0200","severity":"error","message":"Undefined variable: y","file":"<stdin>","#), "{}", stderr);
+    assert!(stderr.contains(r#""span":{"line":2,"column":5,"endLine":2,"endColumn":6,"start":15,"end":16}"#), "{}", stderr);
+}
+
+#[test]
+fn test_exit_codes() {
+    assert_eq!(run(&["-q", "-"], "let x = 1 @ 2;").status.code(), Some(2), "lexer error");
+    assert_eq!(run(&["-q", "-"], "let x = 1 2;").status.code(), Some(3), "parser error");
+    assert_eq!(run(&["-q", "-"], "x = 1;").status.code(), Some(4), "semantic error");
+    assert_eq!(run(&["-q", "-D", "unused-variable", "-"], "let x = 1;").status.code(), Some(5), "denied lint");
+    assert_eq!(run(&["--fmt", "--check", "-"], "let x=1;").status.code(), Some(6), "unformatted input");
+    assert_eq!(run(&["--no-such-flag"], "").status.code(), Some(1), "usage error");
+}
+
+#[test]
+fn test_fmt_check() {
+    let output = run(&["-q", "--fmt", "-"], "let x=1;if(x){x=2;}");
+    assert_eq!(output.status.code(), Some(0));
+    let formatted = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(formatted, "let x = 1;\nif (x) {\n  x = 2;\n}\n");
+
+    let output = run(&["--fmt", "--check", "-"], &formatted);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    let output = run(&["--fmt", "--check", "-"], "let x=1;");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Would reformat <stdin>\n");
+}
@@ -1,4 +1,16 @@
-use humera_js_compiler::compile;
+use humera_js_compiler::{binary, compile, compile_module, compile_with_options, compile_with_stats, docs, dump, format_source, lint, parse, parse_with_options, tokenize};
+use humera_js_compiler::diagnostic::Severity;
+use humera_js_compiler::lint::{Lint, LintLevel};
+use humera_js_compiler::diagnostic::{self, Diagnostic};
+use humera_js_compiler::error::ErrorKind;
+use humera_js_compiler::options::{CompileOptions, OptLevel, Pass};
+use humera_js_compiler::lexer::Lexer;
+use humera_js_compiler::parser::Parser;
+use humera_js_compiler::token::TriviaKind;
+use humera_js_compiler::codegen::CodeGenerator;
+use humera_js_compiler::sema::Analysis;
+
+mod support;
 
 fn assert_contains(output: &str, pattern: &str) {
     assert!(output.contains(pattern), "Output did not contain '{}'.\nOutput:\n{}", pattern, output);
@@ -92,3 +104,1175 @@ fn test_const_reassignment() {
     ";
     compile(input);
 }
+
+#[test]
+fn test_peephole_set_get_becomes_tee() {
+    let input = "
+        let x = 5;
+        let y = x + 1;
+    ";
+    let output = compile(input);
+
+    assert_contains(&output, "local.tee $x_0");
+    assert!(!output.contains("local.set $x_0"), "set/get pair was not fused:\n{}", output);
+}
+
+#[test]
+fn test_peephole_folds_negated_literal() {
+    let input = "let x = -5;";
+    let output = compile(input);
+
+    assert_contains(&output, "i32.const -5");
+    assert!(!output.contains("i32.sub"), "negation was not folded:\n{}", output);
+}
+
+#[test]
+fn test_peephole_inverts_loop_condition() {
+    let input = "
+        let i = 0;
+        while (i < 10) {
+            i = i + 1;
+        }
+    ";
+    let output = compile(input);
+
+    // `i < 10` followed by the loop's i32.eqz becomes a single i32.ge_s
+    assert_contains(&output, "i32.ge_s");
+    assert!(!output.contains("i32.eqz"), "eqz was not folded into the comparison:\n{}", output);
+    // The assignment statement's tee + drop becomes a plain set
+    assert!(!output.contains("drop"), "tee/drop pair was not simplified:\n{}", output);
+}
+
+#[test]
+fn test_peephole_removes_double_not() {
+    let input = "
+        let a = 1;
+        if (!!a) {
+            a = 2;
+        }
+    ";
+    let output = compile(input);
+
+    assert!(!output.contains("i32.eqz"), "double negation was not removed:\n{}", output);
+}
+
+#[test]
+fn test_peephole_stats() {
+    let input = "
+        function f(n) {
+            let r = n;
+            return r;
+            r = 0;
+        }
+        f(-3);
+    ";
+    let (output, stats) = compile_with_stats(input, &CompileOptions::default());
+
+    assert!(stats.after < stats.before, "expected fewer instructions: {}", stats);
+    assert_eq!(stats.removed(), stats.before - stats.after);
+    // Code after `return` is unreachable and gets dropped
+    assert!(!output.contains("local.set $r_0"), "dead code survived:\n{}", output);
+}
+
+#[test]
+fn test_opt_level_o0_is_one_to_one() {
+    let input = "let x = 2 * 3; let y = -x;";
+    let output = compile_with_options(input, &CompileOptions::new(OptLevel::O0));
+
+    // No folding and no peephole rewrites
+    assert_contains(&output, "i32.const 2\n    i32.const 3\n    i32.mul");
+    assert_contains(&output, "local.set $x_0\n    i32.const 0\n    local.get $x_0\n    i32.sub");
+}
+
+#[test]
+fn test_opt_level_o2_folds_constant_trees() {
+    let input = "let x = 1 + 2 * 3 - -4;";
+    let output = compile_with_options(input, &CompileOptions::new(OptLevel::O2));
+
+    assert_contains(&output, "i32.const 11");
+    assert!(!output.contains("i32.add"), "constant tree was not folded:\n{}", output);
+}
+
+#[test]
+fn test_folding_keeps_traps() {
+    // Whatever -O0 does, trap or value, the folding levels do too
+    let outcome = |input: &str, level: OptLevel| {
+        std::panic::catch_unwind(|| support::run(input, &CompileOptions::new(level))).ok()
+    };
+    for input in ["5 % 0;", "5 / 0;", "(1 + 4) % (2 - 2);", "-2147483648 / -1;", "-2147483648 % -1;", "let x = 1 ? 7 / 0 : 0; x;"] {
+        let expected = outcome(input, OptLevel::O0);
+        for level in [OptLevel::O1, OptLevel::O2] {
+            assert_eq!(outcome(input, level), expected, "{} at {:?}", input, level);
+        }
+    }
+    assert_eq!(outcome("-2147483648 % -1;", OptLevel::O2), Some("0".to_string()));
+    assert_eq!(outcome("5 % 0;", OptLevel::O2), None);
+}
+
+#[test]
+fn test_opt_level_parse() {
+    assert_eq!(OptLevel::parse("0"), Some(OptLevel::O0));
+    assert_eq!(OptLevel::parse("3"), Some(OptLevel::O3));
+    assert_eq!(OptLevel::parse("s"), Some(OptLevel::Os));
+    assert_eq!(OptLevel::parse("4"), None);
+}
+
+#[test]
+fn test_os_removes_unused_functions() {
+    let source = "function unused() { return helper(); }\nfunction helper() { return 1; }\nfunction used() { return 2; }\nused();";
+    let output = compile_with_options(source, &CompileOptions::new(OptLevel::O2));
+    assert_contains(&output, "(func $unused");
+    for level in [OptLevel::O3, OptLevel::Os] {
+        let output = compile_with_options(source, &CompileOptions::new(level));
+        assert_contains(&output, "(func $used");
+        assert!(!output.contains("(func $unused"), "{}", output);
+        assert!(!output.contains("(func $helper"), "{}", output);
+    }
+    // Runtime functions only the removed code needed go with it
+    let options = CompileOptions { dynamic: true, ..CompileOptions::new(OptLevel::Os) };
+    let output = compile_with_options("function unused(a) { return a + \"\"; }\n1;", &options);
+    assert!(!output.contains("runtime.concat"), "{}", output);
+    assert_eq!(support::run(source, &options), "2");
+}
+
+#[test]
+fn test_coalesce_locals_shares_slots() {
+    let input = "
+        function f(n) {
+            let total = 0;
+            while (n > 0) {
+                { let a = n * 2; total = total + a; }
+                { let b = n * 3; total = total + b; }
+                n = n - 1;
+            }
+            return total;
+        }
+        f(3);
+    ";
+    let output = compile_with_options(input, &CompileOptions::new(OptLevel::O2));
+
+    // `a` and `b` never overlap, but `total` is live across the whole loop
+    assert_contains(&output, "(local $r0 i32)");
+    assert_contains(&output, "(local $r1 i32)");
+    assert!(!output.contains("$r2"), "expected two slots:\n{}", output);
+    assert!(!output.contains("$a_"), "locals were not renamed:\n{}", output);
+}
+
+#[test]
+fn test_coalesce_locals_keeps_loop_carried_values_apart() {
+    let input = "
+        let i = 0;
+        let sum = 0;
+        while (i < 3) {
+            let step = i + 1;
+            sum = sum + step;
+            i = step;
+        }
+        sum;
+    ";
+    let output = compile_with_options(input, &CompileOptions::new(OptLevel::O2));
+
+    // `i` and `sum` are both read on the next iteration, so nothing can share with them
+    assert_contains(&output, "(local $r2 i32)");
+}
+
+#[test]
+fn test_coalesce_locals_keep_names() {
+    let input = "
+        { let a = 1; a; }
+        { let b = 2; b; }
+    ";
+    let mut options = CompileOptions::new(OptLevel::O2);
+    options.keep_local_names = true;
+    let output = compile_with_options(input, &options);
+
+    assert_contains(&output, "(local $a_0.b_1 i32)");
+}
+
+fn without_pass(level: OptLevel, pass: Pass) -> CompileOptions {
+    let mut options = CompileOptions::new(level);
+    options.passes.retain(|p| *p != pass);
+    options
+}
+
+#[test]
+fn test_cse_reuses_repeated_product() {
+    let input = "
+        function f(a, b) {
+            return a * b + a * b;
+        }
+        f(2, 3);
+    ";
+    let options = CompileOptions::new(OptLevel::O2);
+    let (output, with_cse) = compile_with_stats(input, &options);
+    let (_, without_cse) = compile_with_stats(input, &without_pass(OptLevel::O2, Pass::Cse));
+
+    assert_eq!(output.matches("i32.mul").count(), 1, "product was computed twice:\n{}", output);
+    assert_contains(&output, "local.tee");
+    assert!(with_cse.after < without_cse.after, "CSE did not reduce instructions: {} vs {}", with_cse, without_cse);
+}
+
+#[test]
+fn test_cse_nested_subexpressions() {
+    let input = "
+        function f(a, b, c) {
+            return (a - b) * c + (a - b) * c - -(a - b);
+        }
+        f(1, 2, 3);
+    ";
+    let output = compile_with_options(input, &CompileOptions::new(OptLevel::O2));
+
+    assert_eq!(output.matches("i32.mul").count(), 1, "{}", output);
+    // `a - b` is computed once; the other `i32.sub`s are the outer subtraction and the negation
+    assert_eq!(output.matches("i32.sub").count(), 3, "{}", output);
+}
+
+#[test]
+fn test_cse_stops_at_calls_and_assignments() {
+    let input = "
+        function g() { return 0; }
+        function f(a, b) {
+            let x = a * b + (a = 1) + a * b;
+            let y = a * b + g() + a * b;
+            return x + y;
+        }
+        f(2, 3);
+    ";
+    let output = compile_with_options(input, &CompileOptions::new(OptLevel::O2));
+
+    // Every product follows a barrier, so each one is recomputed
+    assert_eq!(output.matches("i32.mul").count(), 4, "{}", output);
+}
+
+#[test]
+fn test_cse_temps_keep_clear_of_locals() {
+    // `let cse` is the local `cse_0`, which a CSE temporary named the same way would overwrite
+    let input = "function g(a, b) { let cse = 100; return a * b + a * b + cse; } g(2, 3);";
+    for level in [OptLevel::O0, OptLevel::O2] {
+        assert_eq!(support::run(input, &CompileOptions::new(level)), "112", "at {:?}", level);
+    }
+}
+
+#[test]
+fn test_error_kinds() {
+    let options = CompileOptions::default();
+    let kind = |input: &str| compile_module(input, &options).unwrap_err().kind;
+
+    assert_eq!(kind("let x = #;"), ErrorKind::Lexer);
+    assert_eq!(kind("let = 1;"), ErrorKind::Parser);
+    assert_eq!(kind("let x = y;"), ErrorKind::Semantic);
+    assert_eq!(kind("const x = 1; x = 2;"), ErrorKind::Semantic);
+    assert_eq!(kind("foo(1);"), ErrorKind::Semantic);
+}
+
+#[test]
+fn test_parse_error_position() {
+    let err = compile_module("let x = 1;\nlet y = 2 3", &CompileOptions::default()).unwrap_err();
+
+    assert_eq!(err.to_string(), "Error at line 2, column 10: Expected `;`, found number `3`");
+}
+
+#[test]
+fn test_binary_encoding() {
+    let (module, _) = compile_module("function f(a) { return a; } f(-200);", &CompileOptions::default()).unwrap();
+    let bytes = binary::encode(&module);
+
+    assert_eq!(&bytes[..8], b"\0asm\x01\0\0\0");
+    // i32.const -200 as signed LEB128
+    assert!(bytes.windows(3).any(|w| w == [0x41, 0xB8, 0x7E]), "{:?}", bytes);
+}
+
+#[test]
+fn test_run_example_programs() {
+    // The programs/ examples, run by the test interpreter, give what their comments promise
+    for (path, expected) in [("programs/factorial.js", "120"), ("programs/gcd.js", "6"), ("programs/ackermann.js", "125")] {
+        let source = std::fs::read_to_string(path).unwrap();
+        for level in [OptLevel::O0, OptLevel::O2] {
+            assert_eq!(support::run(&source, &CompileOptions::new(level)), expected, "{} at {:?}", path, level);
+        }
+    }
+}
+
+#[test]
+fn test_dump_tokens() {
+    let tokens = tokenize("let x = 1;\nx;").unwrap();
+    let output = dump::tokens(&tokens);
+
+    assert_contains(&output, "1:1     Let\n");
+    assert_contains(&output, "1:5     Identifier(\"x\")\n");
+    assert_contains(&output, "2:1     Identifier(\"x\")\n");
+    assert_contains(&output, "2:3     EOF\n");
+}
+
+#[test]
+fn test_dump_ast_tree() {
+    let program = parse("function f(a, b) { if (a < b) { return -a; } return b; }").unwrap();
+    let output = dump::ast(&program);
+
+    assert_eq!(output, "\
+Program
+  FunctionDeclaration f(a, b)
+    If
+      Binary <
+        Identifier a
+        Identifier b
+      Block
+        Return
+          Unary -
+            Identifier a
+    Return
+      Identifier b
+");
+}
+
+#[test]
+fn test_dump_ast_json() {
+    let program = parse("x = y;").unwrap();
+    let output = dump::ast_json(&program).to_string();
+
+    assert_eq!(output, concat!(
+        r#"{"type":"Program","body":[{"type":"ExpressionStatement","expression":"#,
+        r#"{"type":"Assignment","#,
+        r#""target":{"type":"Identifier","name":"x","span":{"line":1,"column":1,"endLine":1,"endColumn":2,"start":0,"end":1}},"#,
+        r#""value":{"type":"Identifier","name":"y","span":{"line":1,"column":5,"endLine":1,"endColumn":6,"start":4,"end":5}},"#,
+        r#""span":{"line":1,"column":1,"endLine":1,"endColumn":6,"start":0,"end":5}},"#,
+        r#""span":{"line":1,"column":1,"endLine":1,"endColumn":7,"start":0,"end":6}}]}"#,
+    ));
+}
+
+#[test]
+fn test_ast_spans() {
+    use humera_js_compiler::ast::{ExpressionKind, StatementKind};
+
+    let source = "let x = 1;\nfunction f(a) {\n  return (a + 2) * 3;\n}";
+    let program = parse(source).unwrap();
+
+    let func = &program.body[1];
+    assert_eq!((func.span.line, func.span.column, func.span.end_line, func.span.end_column), (2, 1, 4, 2));
+    assert_eq!(&source[func.span.start..func.span.end], "function f(a) {\n  return (a + 2) * 3;\n}");
+
+    let StatementKind::FunctionDeclaration { params, body, .. } = &func.kind else { panic!() };
+    assert_eq!(&source[params[0].span.start..params[0].span.end], "a");
+    let StatementKind::Return(Some(value)) = &body[0].kind else { panic!() };
+    assert_eq!(&source[value.span.start..value.span.end], "(a + 2) * 3");
+    let ExpressionKind::Binary(left, _, right) = &value.kind else { panic!() };
+    assert_eq!(&source[left.span.start..left.span.end], "(a + 2)");
+    assert_eq!((right.span.line, right.span.column), (3, 20));
+}
+
+#[test]
+fn test_semantic_error_position() {
+    let err = compile_module("let x = 1;\nx = y + 1;", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.to_string(), "Error at line 2, column 5: Undefined variable: y");
+
+    let err = compile_module("const c = 1;\n  c = 2;", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.to_string(), "Error at line 2, column 3: Assignment to constant variable 'c'");
+
+    let err = compile_module("g(1);", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.to_string(), "Error at line 1, column 1: Undefined function: g");
+}
+
+#[test]
+fn test_diagnostic_rendering() {
+    let source = "const c = 1;\nlet y = 2;\nc = y;";
+    let err = compile_module(source, &CompileOptions::default()).unwrap_err();
+    let output = diagnostic::render(&Diagnostic::from(&err), source, "input.js", false);
+
+    assert_eq!(output, "\
+error[E0201]: Assignment to constant variable 'c'
+ --> input.js:3:1
+  |
+1 | const c = 1;
+  |       - 'c' declared as const here
+2 | let y = 2;
+3 | c = y;
+  | ^^^^^ cannot assign to a constant
+  |
+  = help: declare 'c' with `let` if it needs to change
+");
+
+    // Missing semicolons point just past the end of the statement
+    let source = "let x = 1 let y = 2;";
+    let err = parse(source).unwrap_err();
+    let output = diagnostic::render(&Diagnostic::from(&err), source, "input.js", true);
+    assert_contains(&output, "Expected `;`, found `let`");
+    assert_contains(&output, "input.js:1:10");
+    assert_contains(&output, "\x1b[1;31m^ expected `;`\x1b[0m");
+}
+
+#[test]
+fn test_diagnostic_json() {
+    let source = "let x = 1 x";
+    let err = parse(source).unwrap_err();
+    let json = diagnostic::to_json(&Diagnostic::from(&err), "input.js").to_string();
+
+    assert_eq!(json, concat!(
+        r#"{"code":"E0100","severity":"error","message":"Expected `;`, found identifier `x`","file":"input.js","#,
+        r#""span":{"line":1,"column":10,"endLine":1,"endColumn":10,"start":9,"end":9},"label":"expected `;`","#,
+        r#""related":[],"help":[],"#,
+        r#""fixes":[{"message":"insert `;`","span":{"line":1,"column":10,"endLine":1,"endColumn":10,"start":9,"end":9},"replacement":";"}]}"#,
+    ));
+
+    // Applying the fix to the byte range yields code that compiles
+    let fix = &err.fixes[0];
+    let fixed = format!("{}{}{}", &source[..fix.span.start], fix.replacement, &source[fix.span.end..]);
+    assert!(compile_module(&fixed, &CompileOptions::default()).is_ok());
+
+    let source = "const c = 1;\nc = 2;";
+    let err = compile_module(source, &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0201"));
+    assert_eq!(err.related[0].span.start, 6);
+    let fix = &err.fixes[0];
+    assert_eq!(&source[fix.span.start..fix.span.end], "const");
+    assert_eq!(fix.replacement, "let");
+}
+
+#[test]
+fn test_sema_errors() {
+    let code = |input: &str| compile_module(input, &CompileOptions::default()).unwrap_err().code;
+
+    assert_eq!(code("let x = 1; let x = 2;"), Some("E0204"));
+    assert_eq!(code("function f(a, a) { return a; }"), Some("E0204"));
+    assert_eq!(code("function f() { return 1; } function f() { return 2; }"), Some("E0204"));
+    assert_eq!(code("function f() { function g() { return 1; } return 0; }"), Some("E0205"));
+
+    let err = compile_module("function add(a, b) {\n  return a + b;\n}\nadd(1);", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.to_string(), "Error at line 4, column 1: Function 'add' expects 2 arguments, found 1");
+    assert_eq!(err.related[0].span.line, 1);
+
+    // Top-level variables live in main, so functions can't see them
+    assert_eq!(code("let g = 1; function f() { return g; }"), Some("E0200"));
+}
+
+#[test]
+fn test_codegen_reports_missing_analysis() {
+    // Codegen trusts sema's results; anything missing is an internal error rather than a panic
+    for (source, options) in [
+        ("let x = 1; x + 1;", CompileOptions::default()),
+        ("function f() { return 1; } f();", CompileOptions::default()),
+        ("let x = 1; x + 1;", CompileOptions { dynamic: true, ..CompileOptions::default() }),
+        ("function f() { return 1; } f();", CompileOptions { dynamic: true, ..CompileOptions::default() }),
+    ] {
+        let program = parse(source).unwrap();
+        let err = CodeGenerator::with_options(options, Analysis::default()).generate(&program).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Internal, "{}", source);
+        assert_eq!(err.kind.exit_code(), 7);
+        assert!(err.message.starts_with("Internal compiler error"), "{}", err.message);
+    }
+}
+
+#[test]
+fn test_block_scoping() {
+    // The inner `x` is a separate local that only exists inside the block
+    let output = compile("let x = 1; { let x = 2; x = x + 1; } x;");
+    assert_contains(&output, "(local $x_0 i32)");
+    assert_contains(&output, "(local $x_1 i32)");
+    assert_contains(&output, "local.get $x_0\n  )");
+
+    // Sibling blocks may declare the same name
+    compile("{ let y = 1; } { let y = 2; }");
+
+    let err = compile_module("{ let y = 1; } y;", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0200"));
+
+    // A param may be named like a local's wasm name; the local then takes another number
+    let input = "function f(a_0){ let a = 1; return a_0; } f(5);";
+    for level in [OptLevel::O0, OptLevel::O2] {
+        assert_eq!(support::run(input, &CompileOptions::new(level)), "5", "at {:?}", level);
+    }
+    let output = compile_with_options(input, &CompileOptions::new(OptLevel::O0));
+    assert_contains(&output, "(local $a_1 i32)");
+}
+
+#[test]
+fn test_temporal_dead_zone() {
+    // The inner `x` is in scope for the whole block, so the assignment can't reach the outer one
+    let err = compile_module("let x = 1;\n{\n  x = 2;\n  let x = 3;\n}", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.to_string(), "Error at line 3, column 3: Cannot access 'x' before initialization");
+    assert_eq!(err.code, Some("E0206"));
+    assert_eq!(err.related[0].span.line, 4);
+
+    let err = compile_module("let y = y + 1;", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0206"));
+}
+
+#[test]
+fn test_single_statement_bodies_are_scoped() {
+    // A declaration used as an `if`/`while` body is a local of its own scope
+    let output = compile("let c = 1; if (c) let z = 5; else let z = 7; c;");
+    assert_contains(&output, "(local $z_1 i32)");
+    assert_contains(&output, "(local $z_2 i32)");
+
+    let err = compile_module("let c = 1; if (c) let z = 5; z;", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0200"));
+}
+
+#[test]
+fn test_did_you_mean() {
+    let err = compile_module("function add(a, b) { return a + b; }\nad(1, 2);", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0202"));
+    assert_eq!(err.help, vec!["a function with a similar name exists: `add`"]);
+    assert_eq!(err.fixes[0].replacement, "add");
+
+    let err = compile_module("let total = 1;\ntotl;", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.help, vec!["a variable with a similar name exists: `total`"]);
+
+    // Nothing close enough
+    let err = compile_module("let total = 1;\nxyz;", &CompileOptions::default()).unwrap_err();
+    assert!(err.help.is_empty());
+}
+
+#[test]
+fn test_loose_arity() {
+    let input = "function add(a, b) { return a + b; }\nadd(5) + add(1, 2, 3);";
+    assert_eq!(compile_module(input, &CompileOptions::default()).unwrap_err().code, Some("E0203"));
+
+    let mut options = CompileOptions::new(OptLevel::O0);
+    options.loose_arity = true;
+    let output = compile_with_options(input, &options);
+    // add(5) gets a 0 for `b`; add(1, 2, 3) evaluates and drops the 3
+    assert_contains(&output, "i32.const 5\n    i32.const 0\n    call $add");
+    assert_contains(&output, "i32.const 3\n    drop\n    call $add");
+}
+
+fn lint_codes(input: &str, options: &CompileOptions) -> Vec<(&'static str, usize)> {
+    lint(input, options).unwrap().iter()
+        .map(|d| (d.code.unwrap(), d.span.unwrap().line))
+        .collect()
+}
+
+#[test]
+fn test_lints() {
+    let input = "\
+function f(a, _b) { return 0; }
+let x = 1;
+let y = 2;
+if (y = 3) { y; }
+while (0) { y; }
+while (1) { y; }
+f(y, 2);";
+    assert_eq!(lint_codes(input, &CompileOptions::default()), vec![
+        ("unused-parameter", 1),
+        ("unused-variable", 2),
+        ("assignment-in-condition", 4),
+        ("constant-condition", 5),
+    ]);
+
+    // Opt-in rules, and turning one off
+    let mut options = CompileOptions::default();
+    options.lints.set(Lint::PreferConst, LintLevel::Warn);
+    options.lints.set(Lint::ShadowedBinding, LintLevel::Deny);
+    options.lints.set(Lint::UnusedVariable, LintLevel::Allow);
+    let warnings = lint("let x = 1; { let x = 2; x; } x;", &options).unwrap();
+    let codes: Vec<_> = warnings.iter().map(|d| (d.code.unwrap(), d.severity)).collect();
+    assert_eq!(codes, vec![
+        ("prefer-const", Severity::Warning),
+        ("prefer-const", Severity::Warning),
+        ("shadowed-binding", Severity::Error),
+    ]);
+
+    // Updating a variable only reads it when the result is used, and is always a write
+    let mut options = CompileOptions::default();
+    options.lints.set(Lint::PreferConst, LintLevel::Warn);
+    let input = "\
+let a = 0;
+a++;
+let b = 0;
+b += 1, --b;
+let c = 0;
+const d = c++;
+let e = 0;
+e += e;
+d;";
+    assert_eq!(lint_codes(input, &options), vec![("unused-variable", 1), ("unused-variable", 3)]);
+}
+
+#[test]
+fn test_lint_disable_comment() {
+    let input = "\
+// humera-disable-next-line unused-variable
+let x = 1;
+// humera-disable-next-line constant-condition
+let y = 2;
+// humera-disable-next-line
+if (0) { let z = 3; }";
+    // Only the rules named in a comment are silenced; a bare comment silences all of them
+    assert_eq!(lint_codes(input, &CompileOptions::default()), vec![("unused-variable", 4)]);
+}
+
+#[test]
+fn test_format_round_trip() {
+    let sources = [
+        "let x = (1 + 2) * 3 - (4 - 5); let y = 1 - (2 - 3); let z = -(-x) + -(1 * 2);",
+        "let a = 1; let b = 2; a = b = (a == b) == (1 < 2);",
+        "function f(a, b) { if (a) return b; else if (b) { return a; } else { while (a < b) a = a + 1; } return; }",
+        "{ let c = !(1 + f(2, 3 % 4)); } if (1) {} else {}",
+        "let m = (a | b) & ~c ^ d << 2 >>> (e >> 1); m <<= (m & 1) == 0; m >>>= 1 + 2;",
+        "i += j++ * -(--k); i -= 1; i *= 2; i /= 3; i %= 4; ++i; i--; x = - --y - -y--;",
+        "x = a ? b : c ? d : e; y = (a ? b : c) ? d : e; z = (a, b = 1, f((c, d), e)); let w = (x, y);",
+        "x = 2 ** 3 ** 2 * (2 ** 3) ** 2; y = (-2) ** -a + -(2 ** a) + (++b) ** 2; x **= Math.min(a, Math.abs(b));",
+        "a: b: while (x) { do { if (y) continue a; else break; } while (z); switch (x) { case 1: case 2: { break b; } default: x = 0; } } c: { break c; } switch (x) {}",
+    ];
+    for source in sources {
+        let formatted = format_source(source).unwrap();
+        let reparsed = parse(&formatted).unwrap_or_else(|err| panic!("{}\n{}", err, formatted));
+        assert_eq!(dump::ast(&reparsed), dump::ast(&parse(source).unwrap()), "{}", formatted);
+        assert_eq!(format_source(&formatted).unwrap(), formatted, "formatting is not idempotent");
+    }
+}
+
+#[test]
+fn test_format_layout_and_comments() {
+    let source = "// header\n\n\n\nfunction f(n){\n  // inside\n  if(n<=1) {return 1;} // base case\n  else return n*f(n-1);\n}\nlet x=(1+2)*3;\n// end\n";
+    let expected = "\
+// header
+
+function f(n) {
+  // inside
+  if (n <= 1) {
+    return 1;
+  } // base case
+  else
+    return n * f(n - 1);
+}
+let x = (1 + 2) * 3;
+// end
+";
+    assert_eq!(format_source(source).unwrap(), expected);
+
+    // Literals are printed from their own token, without the parentheses and comments around them
+    let source = "let a = (/* hi */ 5) + (0xFF);\nlet b = ('s');\nlet c = (- /* min */ 2147483648);\n";
+    let formatted = format_source(source).unwrap();
+    assert_eq!(formatted, "let a = 5 + 0xFF; /* hi */\nlet b = 's';\nlet c = -2147483648; /* min */\n");
+    assert_eq!(format_source(&formatted).unwrap(), formatted, "formatting is not idempotent");
+
+    // Comments inside an expression move after its statement, without opening a blank line
+    let source = "let x = 1 + /* c */ 2;\nlet y = f(a, // first\n  b);\nlet z = 3;\n";
+    let formatted = format_source(source).unwrap();
+    assert_eq!(formatted, "let x = 1 + 2; /* c */\nlet y = f(a, b);\n// first\nlet z = 3;\n");
+    assert_eq!(format_source(&formatted).unwrap(), formatted, "formatting is not idempotent");
+}
+
+#[test]
+fn test_trivia() {
+    use humera_js_compiler::ast::{Statement, StatementKind};
+
+    let source = "// leading\nlet x = 1;  // trailing\n\nfunction f() {\n    return x; // inner\n}\n// end\n";
+
+    // Tokens and their trivia reproduce the source exactly
+    let tokens = Lexer::with_trivia(source).tokenize().unwrap();
+    let mut rebuilt = String::new();
+    for token in &tokens {
+        rebuilt.extend(token.leading.iter().map(|t| t.text.as_str()));
+        rebuilt.push_str(&source[token.span.start..token.span.end]);
+        rebuilt.extend(token.trailing.iter().map(|t| t.text.as_str()));
+    }
+    assert_eq!(rebuilt, source);
+    assert!(Lexer::new(source).tokenize().unwrap().iter().all(|t| t.leading.is_empty() && t.trailing.is_empty()));
+
+    let program = Parser::new(Lexer::with_trivia(source)).unwrap().parse_program().unwrap();
+    let comments = |stmt: &Statement| -> Vec<String> {
+        stmt.leading.iter().filter(|t| t.is_comment()).map(|t| t.text.clone()).collect()
+    };
+    assert_eq!(comments(&program.body[0]), ["// leading"]);
+    // `// trailing` ends the line of `let x`, so it isn't leading `function f`
+    assert!(comments(&program.body[1]).is_empty());
+    let StatementKind::FunctionDeclaration { body, .. } = &program.body[1].kind else { panic!() };
+    assert!(body[0].leading.iter().all(|t| t.kind == TriviaKind::Whitespace));
+
+    assert!(parse(source).unwrap().body[0].leading.is_empty());
+}
+
+#[test]
+fn test_block_comments() {
+    let tokens = tokenize("let /* one */ x = 1; /* two\nlines */ x;").unwrap();
+    let output = dump::tokens(&tokens);
+    assert_contains(&output, "1:15    Identifier(\"x\")\n");
+    assert_contains(&output, "2:10    Identifier(\"x\")\n");
+
+    let err = compile_module("let x = 1;\n  /* never closed\n", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Lexer);
+    assert_eq!(err.to_string(), "Error at line 2, column 3: Unterminated block comment");
+    assert!(Lexer::with_trivia("/* a */ 1; /*").tokenize().is_err());
+}
+
+#[test]
+fn test_doc_comments() {
+    let source = "\
+/**
+ * Adds two numbers.
+ *
+ * Wraps on overflow.
+ */
+function add(a, b) { return a + b; }
+/* plain */
+function f() { return 1; }
+/** Stale: separated from `g` by another comment */ // g
+function g() { return 2; }
+";
+    assert_eq!(docs(source).unwrap(), "\
+## `add(a, b)`
+
+Adds two numbers.
+
+Wraps on overflow.
+
+## `f()`
+
+## `g()`
+");
+}
+
+#[test]
+fn test_number_literals() {
+    let value = |literal: &str| {
+        let program = parse(&format!("{};", literal)).unwrap_or_else(|err| panic!("{}: {}", literal, err));
+        let output = dump::ast(&program);
+        output.lines().last().unwrap().trim().to_string()
+    };
+    assert_eq!(value("0xFF"), "Number 255");
+    assert_eq!(value("0o17"), "Number 15");
+    assert_eq!(value("0b1010"), "Number 10");
+    assert_eq!(value("1_000_000"), "Number 1000000");
+    assert_eq!(value("1.5e3"), "Number 1500");
+    assert_eq!(value("250e-1"), "Number 25");
+    assert_eq!(value("-2147483648"), "Number -2147483648");
+    assert_eq!(value("0x7fff_ffff"), "Number 2147483647");
+    // Hex, octal and binary literals are 32-bit patterns, as after `| 0`
+    assert_eq!(value("0xFFFFFFFF"), "Number -1");
+    assert_eq!(value("0x8000_0000"), "Number -2147483648");
+    assert_eq!(value(&format!("0b{}", "1".repeat(32))), "Number -1");
+    assert_eq!(value("0o37777777776"), "Number -2");
+    for level in [OptLevel::O0, OptLevel::O2] {
+        let options = CompileOptions::new(level);
+        assert_eq!(support::run("let mask = 0xFFFF_FF00; mask >> 8;", &options), "-1");
+        assert_eq!(support::run("0x8000_0000 == -2147483648;", &options), "1");
+    }
+
+    let error = |literal: &str| {
+        let err = parse(&format!("let x = {};", literal)).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Lexer, "{}", literal);
+        (err.code.unwrap(), err.message)
+    };
+    assert_eq!(error("2147483648"), ("E0002", "Number literal out of range: 2147483648".to_string()));
+    assert_eq!(error("3000000000"), ("E0002", "Number literal out of range: 3000000000".to_string()));
+    assert_eq!(error("1e10").0, "E0002");
+    assert_eq!(error("0x1_0000_0000"), ("E0002", "Number literal out of range: 0x1_0000_0000".to_string()));
+    let err = parse("0x100000000;").unwrap_err();
+    assert!(err.help.iter().any(|help| help.contains("0xFFFFFFFF")), "{:?}", err.help);
+    assert!(parse("2147483648;").unwrap_err().help.iter().any(|help| help.contains("2147483647")));
+    assert_eq!(error("1.5"), ("E0004", "Number literal is not an integer: 1.5".to_string()));
+    assert_eq!(error("1__000").0, "E0004");
+    assert_eq!(error("0b102").0, "E0004");
+    assert_eq!(error("0x").0, "E0004");
+    assert_eq!(error("0_1").0, "E0004");
+    assert_eq!(error("0_0").0, "E0004");
+    assert_eq!(value("10_1"), "Number 101");
+
+    // --dynamic takes any number, rounded to the nearest double
+    let options = CompileOptions { dynamic: true, ..CompileOptions::default() };
+    let dynamic = |literal: &str| {
+        let program = parse_with_options(&format!("{};", literal), &options).unwrap_or_else(|err| panic!("{}: {}", literal, err));
+        dump::ast(&program).lines().last().unwrap().trim().to_string()
+    };
+    assert_eq!(dynamic("1.5"), "Number 1.5");
+    assert_eq!(dynamic("2_147_483_648"), "Number 2147483648");
+    assert_eq!(dynamic(".5e1"), "Number 5");
+    assert_eq!(dynamic("0xFFFF_FFFF_FFFF"), "Number 281474976710655");
+    assert_eq!(dynamic("9007199254740993"), "Number 9007199254740992");
+    assert_eq!(dynamic("1e400"), "Number inf");
+    assert!(parse_with_options("0_1;", &options).is_err());
+    assert_eq!(support::run("0.1 + 0.2;", &options), "0.30000000000000004");
+    assert_eq!(support::run("let x = 2.5; x * 3_000_000_000;", &options), "7500000000");
+    assert_eq!(support::run("1.9 | 0;", &options), "1");
+    assert_eq!(support::run("4294967297 | 0;", &options), "1");
+    assert_eq!(support::run("-2147483648 - 1;", &options), "-2147483649");
+
+    // The formatter keeps literals as written
+    assert_eq!(format_source("let x=0xFF+1_000;").unwrap(), "let x = 0xFF + 1_000;\n");
+}
+
+#[test]
+fn test_bitwise_operators() {
+    // `|` < `^` < `&` < equality < comparison < shift < additive
+    let program = parse("a | b ^ c & d == e < f << g + h;").unwrap();
+    assert_eq!(dump::ast(&program), "\
+Program
+  ExpressionStatement
+    Binary |
+      Identifier a
+      Binary ^
+        Identifier b
+        Binary &
+          Identifier c
+          Binary ==
+            Identifier d
+            Binary <
+              Identifier e
+              Binary <<
+                Identifier f
+                Binary +
+                  Identifier g
+                  Identifier h
+");
+
+    let output = compile("function f(a, b) { a &= b; a |= ~b; return a >>> 1 ^ a >> 2 << 3; } f(1, 2);");
+    assert_contains(&output, "local.get $a\n    local.get $b\n    i32.and\n    local.tee $a");
+    assert_contains(&output, "i32.const -1\n    i32.xor");
+    assert_contains(&output, "i32.shr_u");
+    assert_contains(&output, "i32.shr_s");
+    assert_contains(&output, "i32.shl");
+
+    // Folded with JavaScript's semantics: shift counts wrap at 32, `>>>` is reinterpreted as signed
+    let output = compile_with_options("let x = (~5 + (1 << 33)) ^ (-16 >>> 28) | (-8 >> 1) & 7;", &CompileOptions::new(OptLevel::O2));
+    assert_contains(&output, "i32.const -9\n");
+    // Without --dynamic that's what running it gives too, unlike JavaScript's 4294967289
+    for level in [OptLevel::O0, OptLevel::O2] {
+        assert_eq!(support::run("let n = -7; n >>> 0;", &CompileOptions::new(level)), "-7");
+        assert_eq!(support::run("-7 >>> 0;", &CompileOptions::new(level)), "-7");
+    }
+    let options = CompileOptions { dynamic: true, ..CompileOptions::default() };
+    assert_eq!(support::run("let n = -7; n >>> 0;", &options), "4294967289");
+
+    let err = compile_module("const c = 1; c |= 2;", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0201"));
+}
+
+#[test]
+fn test_increment_and_compound_assignment() {
+    let program = parse("x += -y++ * ++z;").unwrap();
+    assert_eq!(dump::ast(&program), "\
+Program
+  ExpressionStatement
+    CompoundAssignment x +=
+      Binary *
+        Unary -
+          Update y++
+        Update ++z
+");
+
+    // Postfix leaves the old value on the stack, prefix the new one
+    let output = compile_with_options("function f(i) { return i++ + --i; } f(1);", &CompileOptions::new(OptLevel::O0));
+    assert_contains(&output, "local.get $i\n    local.get $i\n    i32.const 1\n    i32.add\n    local.set $i");
+    assert_contains(&output, "local.get $i\n    i32.const 1\n    i32.sub\n    local.tee $i");
+
+    // As a statement, `i++` needs no copy of the old value
+    let output = compile_with_options("function f(i) { i++; return i; } f(1);", &CompileOptions::new(OptLevel::O0));
+    assert_contains(&output, "local.get $i\n    i32.const 1\n    i32.add\n    local.tee $i\n    drop");
+
+    let output = compile("function f(a) { a *= 3; a %= 2; return a; } f(1);");
+    assert_contains(&output, "local.get $a\n    i32.const 3\n    i32.mul\n    local.tee $a\n    i32.const 2\n    i32.rem_s");
+
+    let code = |source: &str| compile_module(source, &CompileOptions::default()).unwrap_err().code;
+    assert_eq!(code("const c = 1; c++;"), Some("E0201"));
+    assert_eq!(code("const c = 1; c -= 1;"), Some("E0201"));
+    assert_eq!(code("let x = 1; 1++;"), Some("E0103"));
+    assert_eq!(code("let x = 1; ++(x + 1);"), Some("E0103"));
+}
+
+#[test]
+fn test_conditional_and_comma() {
+    // `?:` is right-associative and binds looser than `|`; `=` binds looser than both arms
+    let program = parse("x = a | b ? c : d ? e : f, g;").unwrap();
+    assert_eq!(dump::ast(&program), "\
+Program
+  ExpressionStatement
+    Sequence
+      Assignment x
+        Conditional
+          Binary |
+            Identifier a
+            Identifier b
+          Identifier c
+          Conditional
+            Identifier d
+            Identifier e
+            Identifier f
+      Identifier g
+");
+
+    // Pure arms become a `select`; an arm that could trap or has side effects needs a real branch
+    let output = compile("function f(a, b) { return a ? a + b : b; } f(1, 2);");
+    assert_contains(&output, "local.get $b\n    local.get $a\n    select");
+    let output = compile("function f(a, b) { return a ? b / a : 0; } f(1, 2);");
+    assert_contains(&output, "(if (result i32)\n      (then\n        local.get $b\n        local.get $a\n        i32.div_s");
+    // ...also when the division is an argument of a builtin
+    let input = "function f(x){ return x != 0 ? Math.abs(10 / x) : 0; } f(0);";
+    for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        assert_eq!(support::run(input, &CompileOptions::new(level)), "0", "at {:?}", level);
+    }
+
+    // Only the arm that is taken survives a constant condition
+    let output = compile_with_options("function f(a) { return 1 ? a : f(a); } f(1);", &CompileOptions::new(OptLevel::O2));
+    assert_contains(&output, "local.get $a\n    return");
+
+    // Every element but the last is evaluated for its side effects and dropped
+    let output = compile_with_options("function f(a) { return (a = 2, a + 1); } f(1);", &CompileOptions::new(OptLevel::O0));
+    assert_contains(&output, "i32.const 2\n    local.tee $a\n    drop\n    local.get $a\n    i32.const 1\n    i32.add");
+}
+
+#[test]
+fn test_exponent_and_math() {
+    let program = parse("x = -a * b ** c ** -d;").unwrap();
+    assert_eq!(dump::ast(&program), "\
+Program
+  ExpressionStatement
+    Assignment x
+      Binary *
+        Unary -
+          Identifier a
+        Binary **
+          Identifier b
+          Binary **
+            Identifier c
+            Unary -
+              Identifier d
+");
+    // As in JavaScript, `-a ** 2` is ambiguous, even on the right of another `**`
+    let code = |source: &str| compile_module(source, &CompileOptions::default()).unwrap_err().code;
+    assert_eq!(code("let a = 2; -a ** 2;"), Some("E0104"));
+    assert_eq!(code("let a = 2; a ** -a ** 2;"), Some("E0104"));
+
+    // Negative exponents truncate the fraction, as `| 0` would
+    let output = compile_with_options("2 ** 10 + 3 ** -1 + (-1) ** -3 + Math.sqrt(17) + Math.clz32(1) + Math.min(4, -2, 3);", &CompileOptions::new(OptLevel::O2));
+    assert_contains(&output, "i32.const 1056\n");
+
+    // Native instructions where wasm has them, a helper function per builtin otherwise
+    let output = compile("function f(a, b) { return Math.imul(a, b) + Math.clz32(a) + Math.floor(b); } f(1, 2);");
+    assert_contains(&output, "i32.mul\n    local.get $a\n    i32.clz\n    i32.add\n    local.get $b\n    i32.add");
+    let output = compile("function f(a, b) { return a ** b + Math.max(a, b, 0) + Math.max(a, 1); } f(1, 2);");
+    assert_contains(&output, "call $Math.pow");
+    assert_contains(&output, "call $Math.max\n    call $Math.max");
+    assert_eq!(output.matches("(func $Math.max").count(), 1);
+    assert_contains(&output, "(func $Math.pow (param $base i32) (param $exp i32) (result i32)");
+
+    assert_eq!(code("Math.abs(1, 2);"), Some("E0203"));
+    // `Math.max()` is -Infinity, which isn't an i32 however loose the arity is
+    let err = compile_module("Math.max();", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0211"));
+    assert_eq!(err.message, "'Math.max' with no arguments needs --dynamic");
+    let options = CompileOptions { loose_arity: true, ..CompileOptions::default() };
+    assert_eq!(compile_module("Math.min();", &options).unwrap_err().code, Some("E0211"));
+    let options = CompileOptions { dynamic: true, ..CompileOptions::default() };
+    assert_eq!(support::run("Math.min();", &options), "Infinity");
+    assert_eq!(support::run("Math.max() + 1;", &options), "-Infinity");
+    let err = compile_module("Math.abz(1);", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0202"));
+    assert_eq!(err.fixes[0].replacement, "Math.abs");
+}
+
+#[test]
+fn test_switch_and_labels() {
+    let program = parse("outer: do { switch (x) { case 1: break; default: continue outer; } } while (0);").unwrap();
+    assert_eq!(dump::ast(&program), "\
+Program
+  Labeled outer
+    DoWhile
+      Block
+        Switch
+          Identifier x
+          Case
+            Number 1
+            Break
+          Default
+            Continue outer
+      Number 0
+");
+
+    // Dense constant cases dispatch through a jump table, anything else through a chain of tests
+    let output = compile("function f(n) { switch (n) { case 3: return 1; case 4: return 2; case 6: return 3; } return 0; } f(1);");
+    assert_contains(&output, "i32.const 3\n            i32.sub\n            br_table $case_1 $case_2 $break_0 $case_3 $break_0\n");
+    let output = compile("function f(n) { switch (n) { case 1: n = 5; case 100: return n; } return 0; } f(1);");
+    assert_contains(&output, "local.tee $switch.0\n          i32.const 1\n          i32.eq\n          br_if $case_1\n");
+    assert_contains(&output, "local.get $switch.0\n          i32.const 100\n          i32.eq\n          br_if $case_2\n          br $break_0\n");
+
+    // `continue` in a `do ... while` jumps to the condition, not back to the top
+    let output = compile("function f(n) { do { if (n) continue; n = 1; } while (n < 3); return n; } f(1);");
+    assert_contains(&output, "(loop $loop_1\n      (block $continue_2");
+    assert_contains(&output, "i32.lt_s\n      br_if $loop_1");
+
+    let code = |source: &str| compile_module(source, &CompileOptions::default()).unwrap_err().code;
+    assert_eq!(code("break;"), Some("E0207"));
+    assert_eq!(code("switch (1) { default: continue; }"), Some("E0207"));
+    assert_eq!(code("a: { while (1) { continue a; } }"), Some("E0207"));
+    assert_eq!(code("outer: while (1) { break outr; }"), Some("E0208"));
+    assert_eq!(code("a: while (1) { a: while (1) {} }"), Some("E0209"));
+    assert_eq!(code("switch (1) { default: default: }"), Some("E0105"));
+
+    // A case's `let` may not have run when another case is reached
+    let source = "function f(v) { let r = 0; switch (v) { case 1: let y = 5; r = y; break; case 2: r = y + 1; break; } return r; } f(1) * 100 + f(2);";
+    let err = compile_module(source, &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0206"));
+    assert_eq!(err.message, "Cannot access 'y' before initialization");
+    assert_eq!(code("let r = 0; switch (1) { case 1: r = y; case 2: let y = 1; }"), Some("E0206"));
+    assert_eq!(code("switch (1) { case 1: let y = 1; break; case 2: let y = 2; }"), Some("E0204"));
+    let output = compile("function f(v) { switch (v) { case 1: let y = 5; { y = y + 1; } return y; } return 0; } f(1);");
+    assert_contains(&output, "(local $y_0 i32)");
+}
+
+#[test]
+fn test_automatic_semicolon_insertion() {
+    let program = parse("function f(a) {\n  return\n  a\n}\nlet b = 1\nb\n++b\nwhile (b) { b = 0 }\nouter: do { break\nouter } while (b) f(b)").unwrap();
+    assert_eq!(dump::ast(&program), "\
+Program
+  FunctionDeclaration f(a)
+    Return
+    ExpressionStatement
+      Identifier a
+  VariableDeclaration let b
+    Number 1
+  ExpressionStatement
+    Identifier b
+  ExpressionStatement
+    Update ++b
+  While
+    Identifier b
+    Block
+      ExpressionStatement
+        Assignment b
+          Number 0
+  Labeled outer
+    DoWhile
+      Block
+        Break
+        ExpressionStatement
+          Identifier outer
+      Identifier b
+  ExpressionStatement
+    Call f
+      Identifier b
+");
+
+    // A line break only ends a statement where the next token can't continue it
+    let program = parse("let a = b\n(c)\n-d").unwrap();
+    assert_eq!(program.body.len(), 1);
+    let err = parse("let a = 1 let b = 2").unwrap_err();
+    assert_eq!(err.message, "Expected `;`, found `let`");
+
+    assert_eq!(format_source("let a = 1\na += 2\n").unwrap(), "let a = 1;\na += 2;\n");
+}
+
+#[test]
+fn test_dynamic_values() {
+    // Without --dynamic the literals are plain integers and `===` is `==`
+    let output = compile_with_options("let a = true; let b = null; a === b;", &CompileOptions::new(OptLevel::O0));
+    assert_contains(&output, "i32.const 1\n    local.set $a_0\n    i32.const 0\n    local.set $b_1");
+    assert_contains(&output, "i32.eq");
+    let err = compile_module("let s = 'a';", &CompileOptions::default()).unwrap_err();
+    assert_eq!(err.code, Some("E0210"));
+    assert_eq!(err.message, "String literals need --dynamic");
+    assert_eq!(compile_module("typeof 1;", &CompileOptions::default()).unwrap_err().code, Some("E0210"));
+    assert_eq!(compile_module("let s = 'a\n';", &CompileOptions::default()).unwrap_err().code, Some("E0005"));
+
+    let options = CompileOptions { dynamic: true, ..CompileOptions::default() };
+    let output = compile_with_options("let a = null;\nif (typeof a === \"object\") a = 1;\nreturn a === 1;", &options);
+    assert_contains(&output, "(local $a_0 i64)");
+    assert_contains(&output, "call $runtime.typeof");
+    assert_contains(&output, "call $runtime.strict_equals");
+    assert_contains(&output, "(data (i32.const 0) \"\\06\\00\\00\\00object");
+    assert_contains(&output, "(export \"_start\" (func $program.start))");
+    // Arithmetic on values known to be numbers skips the runtime
+    let output = compile_with_options("let a = 1 / 2 * 3; typeof (a - 1);", &options);
+    assert_contains(&output, "f64.mul");
+    assert!(!output.contains("runtime.typeof"));
+
+    // Only literal conditions are constant: `null === 0` is false here but `0 == 0` without --dynamic
+    assert_eq!(lint_codes("if (null === 0) {}", &CompileOptions::default()), vec![("constant-condition", 1)]);
+    assert_eq!(lint_codes("if (null === 0) {}\nif ('') {}\nwhile (true) {}", &options), vec![("constant-condition", 2)]);
+}
+
+#[test]
+fn test_dynamic_runtime() {
+    let options = CompileOptions { dynamic: true, ..CompileOptions::default() };
+    let output = compile_with_options("function f(a, b) { return a + b == a < b; }", &options);
+    assert_contains(&output, "call $runtime.add");
+    assert_contains(&output, "call $runtime.loose_equals");
+    assert_contains(&output, "call $runtime.compare");
+    // Concatenation needs the heap, and `to_string` formats numbers
+    let output = compile_with_options("function f(a) { return \"n = \" + a; }", &options);
+    assert_contains(&output, "call $runtime.concat");
+    assert_contains(&output, "(func $runtime.number_to_string");
+    assert_contains(&output, "(global $heap (mut i32)");
+    assert!(!output.contains("runtime.add"));
+
+    // Variables and results that always have one type skip the runtime
+    let output = compile_with_options("function n() { return 10; }\nlet i = 0;\nwhile (i < n()) i = i + 1;\nreturn i;", &options);
+    assert_contains(&output, "f64.lt");
+    assert_contains(&output, "f64.add");
+    assert!(!output.contains("runtime.compare"));
+    assert!(!output.contains("runtime.add"));
+    // ...but not once a string is assigned
+    let output = compile_with_options("let i = 0;\nif (i < 1) i = \"s\";\nreturn i + 1;", &options);
+    assert_contains(&output, "call $runtime.add");
+    // The generated entry points don't clash with functions of the same name
+    assert_eq!(support::run("function _start() { return 1; } _start() + 1;", &options), "2");
+    assert_eq!(support::run("function main() { return 1; } main() + 2;", &CompileOptions::default()), "3");
+    let output = compile_with_options("function _start() { return 1; } _start();", &options);
+    assert_eq!(output.matches("(func $_start ").count(), 1, "{}", output);
+    // Of the 17-digit candidates that read back, the closest one is printed
+    assert_eq!(support::run("2 ** 70 + \"\" == \"1.1805916207174113e+21\";", &options), "1");
+    assert_eq!(support::run("1 / 30000000 + \"\" == \"3.3333333333333334e-8\";", &options), "1");
+}
+
+#[test]
+fn test_dynamic_conversions() {
+    let options = CompileOptions { dynamic: true, ..CompileOptions::default() };
+    let run = |source: &str| support::run(source, &options);
+    let prints = |expression: &str, expected: &str| {
+        let source = format!("{} + \"\" == \"{}\";", expression, expected);
+        assert_eq!(run(&source), "1", "{} should print as {}", expression, expected);
+    };
+
+    // Numbers print in fixed notation from 1e-6 up to 1e21
+    prints("10 ** 21", "1e+21");
+    prints("10 ** 21 - 131072", "999999999999999900000");
+    prints("10 ** 20 * 3", "300000000000000000000");
+    prints("1 / 10 ** 6", "0.000001");
+    prints("123 / 10 ** 6", "0.000123");
+    prints("1 / 10 ** 7", "1e-7");
+    prints("3 / 10 ** 7", "3e-7");
+    prints("-5 / 2", "-2.5");
+    prints("-0", "0");
+    prints("1 / 0", "Infinity");
+    prints("-1 / 0", "-Infinity");
+    prints("0 / 0", "NaN");
+    prints("2 ** 53 + 1", "9007199254740992");
+
+    // Strings read as numbers with JavaScript's rules
+    assert_eq!(run("\"  12  \" * 1;"), "12");
+    assert_eq!(run("\"\" * 1;"), "0");
+    assert_eq!(run("\" \\n\" * 1;"), "0");
+    assert_eq!(run("\"0x1f\" * 1;"), "31");
+    assert_eq!(run("\"0b101\" * 1;"), "5");
+    assert_eq!(run("\"1e3\" * 1;"), "1000");
+    assert_eq!(run("\"-.5\" * 2;"), "-1");
+    assert_eq!(run("\"5.\" * 1;"), "5");
+    assert_eq!(run("\"0.1\" * 3;"), "0.30000000000000004");
+    assert_eq!(run("\"9007199254740993\" * 1;"), "9007199254740992");
+    assert_eq!(run("\"Infinity\" * 1;"), "Infinity");
+    assert_eq!(run("\"-Infinity\" * 1;"), "-Infinity");
+    assert_eq!(run("\"12px\" * 1;"), "NaN");
+    assert_eq!(run("\"1e\" * 1;"), "NaN");
+    assert_eq!(run("\"+0x10\" * 1;"), "NaN");
+
+    // `==` converts across types, except that null and undefined only equal each other
+    assert_eq!(run("\"1\" == 1;"), "1");
+    assert_eq!(run("true == \"1\";"), "1");
+    assert_eq!(run("\"\" == 0;"), "1");
+    assert_eq!(run("\"0x10\" == 16;"), "1");
+    assert_eq!(run("null == undefined;"), "1");
+    assert_eq!(run("null == 0;"), "0");
+    assert_eq!(run("undefined == 0;"), "0");
+    assert_eq!(run("\"NaN\" == 0 / 0;"), "0");
+    assert_eq!(run("0 / 0 == 0 / 0;"), "0");
+
+    // Two strings compare by their characters, anything else as numbers
+    assert_eq!(run("\"B\" < \"a\";"), "1");
+    assert_eq!(run("\"10\" < \"9\";"), "1");
+    assert_eq!(run("\"ab\" < \"abc\";"), "1");
+    assert_eq!(run("\"é\" > \"z\";"), "1");
+    assert_eq!(run("\"10\" < 9;"), "0");
+    assert_eq!(run("\"a\" < 1;"), "0");
+    assert_eq!(run("null < 1;"), "1");
+    assert_eq!(run("undefined < 1;"), "0");
+}
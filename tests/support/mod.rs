@@ -0,0 +1,419 @@
+// A small interpreter for the `wasm::Module`s the compiler produces, so tests can check what a
+// program evaluates to and not just which instructions it contains. It runs the module as the
+// passes left it and panics on a trap.
+
+use humera_js_compiler::compile_module;
+use humera_js_compiler::options::CompileOptions;
+use humera_js_compiler::wasm::{Function, Instruction, Module, ValType};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F64(f64),
+}
+
+impl Value {
+    fn zero(ty: ValType) -> Value {
+        match ty {
+            ValType::I32 => Value::I32(0),
+            ValType::I64 => Value::I64(0),
+            ValType::F64 => Value::F64(0.0),
+        }
+    }
+}
+
+// What the `_start` export returns for `input`, as JavaScript would print the number
+pub fn run(input: &str, options: &CompileOptions) -> String {
+    let (module, _) = compile_module(input, options).unwrap_or_else(|err| panic!("{}", err));
+    match Machine::new(&module).call_export("_start") {
+        Value::I32(n) => n.to_string(),
+        Value::F64(x) => number(x),
+        Value::I64(v) => panic!("_start returned an i64: {}", v),
+    }
+}
+
+fn number(x: f64) -> String {
+    if x.is_nan() {
+        "NaN".to_string()
+    } else if x.is_infinite() {
+        if x < 0.0 { "-Infinity" } else { "Infinity" }.to_string()
+    } else {
+        x.to_string()
+    }
+}
+
+// How a branch left a body
+enum Flow {
+    Next,
+    Branch(String),
+    Return,
+}
+
+pub struct Machine<'a> {
+    module: &'a Module,
+    pub memory: Vec<u8>,
+    globals: HashMap<String, i32>,
+    stack: Vec<Value>,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        let mut memory = Vec::new();
+        if module.memory {
+            memory = vec![0; module.memory_pages() as usize * 65536];
+            memory[..module.data.len()].copy_from_slice(&module.data);
+        }
+        let globals = module.globals.iter().cloned().collect();
+        Machine { module, memory, globals, stack: Vec::new() }
+    }
+
+    pub fn call_export(&mut self, export: &str) -> Value {
+        let (_, name) = self.module.exports.iter().find(|(e, _)| e == export).expect("no such export");
+        self.call(name, Vec::new())
+    }
+
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Value {
+        let function = self.function(name);
+        let mut locals: HashMap<String, Value> = function.params.iter().map(|p| p.name.clone()).zip(args).collect();
+        for local in &function.locals {
+            locals.insert(local.name.clone(), Value::zero(local.ty));
+        }
+        let height = self.stack.len();
+        self.execute(&function.body, &mut locals);
+        let result = self.pop();
+        self.stack.truncate(height);
+        result
+    }
+
+    fn function(&self, name: &str) -> &'a Function {
+        let module = self.module;
+        let mut functions = module.functions.iter().filter(|f| f.name == name);
+        let function = functions.next().unwrap_or_else(|| panic!("no function ${}", name));
+        assert!(functions.next().is_none(), "${} is defined twice", name);
+        function
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn pop_i32(&mut self) -> i32 {
+        match self.pop() {
+            Value::I32(n) => n,
+            other => panic!("expected an i32, found {:?}", other),
+        }
+    }
+
+    fn pop_i64(&mut self) -> i64 {
+        match self.pop() {
+            Value::I64(n) => n,
+            other => panic!("expected an i64, found {:?}", other),
+        }
+    }
+
+    fn pop_f64(&mut self) -> f64 {
+        match self.pop() {
+            Value::F64(x) => x,
+            other => panic!("expected an f64, found {:?}", other),
+        }
+    }
+
+    fn i32_op(&mut self, op: impl Fn(i32, i32) -> i32) {
+        let b = self.pop_i32();
+        let a = self.pop_i32();
+        self.stack.push(Value::I32(op(a, b)));
+    }
+
+    fn i64_op(&mut self, op: impl Fn(i64, i64) -> i64) {
+        let b = self.pop_i64();
+        let a = self.pop_i64();
+        self.stack.push(Value::I64(op(a, b)));
+    }
+
+    fn i64_test(&mut self, op: impl Fn(i64, i64) -> bool) {
+        let b = self.pop_i64();
+        let a = self.pop_i64();
+        self.stack.push(Value::I32(op(a, b) as i32));
+    }
+
+    fn f64_op(&mut self, op: impl Fn(f64, f64) -> f64) {
+        let b = self.pop_f64();
+        let a = self.pop_f64();
+        self.stack.push(Value::F64(op(a, b)));
+    }
+
+    fn f64_test(&mut self, op: impl Fn(f64, f64) -> bool) {
+        let b = self.pop_f64();
+        let a = self.pop_f64();
+        self.stack.push(Value::I32(op(a, b) as i32));
+    }
+
+    fn f64_unary(&mut self, op: impl Fn(f64) -> f64) {
+        let x = self.pop_f64();
+        self.stack.push(Value::F64(op(x)));
+    }
+
+    fn address(&mut self, offset: u32) -> usize {
+        let address = self.pop_i32() as u32 as usize + offset as usize;
+        assert!(address < self.memory.len(), "out of bounds memory access at {}", address);
+        address
+    }
+
+    fn load<const N: usize>(&mut self, offset: u32) -> [u8; N] {
+        let address = self.address(offset);
+        self.memory[address..address + N].try_into().expect("out of bounds memory access")
+    }
+
+    fn store(&mut self, offset: u32, bytes: &[u8]) {
+        let address = self.address(offset);
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn execute(&mut self, body: &[Instruction], locals: &mut HashMap<String, Value>) -> Flow {
+        use Instruction::*;
+        for instruction in body {
+            match instruction {
+                I32Const(n) => self.stack.push(Value::I32(*n)),
+                I64Const(n) => self.stack.push(Value::I64(*n)),
+                F64Const(x) => self.stack.push(Value::F64(*x)),
+                LocalGet(name) => self.stack.push(locals[name]),
+                LocalSet(name) => {
+                    let value = self.pop();
+                    locals.insert(name.clone(), value);
+                }
+                LocalTee(name) => {
+                    let value = *self.stack.last().expect("stack underflow");
+                    locals.insert(name.clone(), value);
+                }
+                GlobalGet(name) => self.stack.push(Value::I32(self.globals[name])),
+                GlobalSet(name) => {
+                    let value = self.pop_i32();
+                    self.globals.insert(name.clone(), value);
+                }
+
+                I32Add => self.i32_op(i32::wrapping_add),
+                I32Sub => self.i32_op(i32::wrapping_sub),
+                I32Mul => self.i32_op(i32::wrapping_mul),
+                I32DivS => self.i32_op(|a, b| a.checked_div(b).expect("integer divide by zero or overflow")),
+                I32RemS => self.i32_op(|a, b| {
+                    assert!(b != 0, "integer divide by zero");
+                    a.wrapping_rem(b)
+                }),
+                I32Eq => self.i32_op(|a, b| (a == b) as i32),
+                I32Ne => self.i32_op(|a, b| (a != b) as i32),
+                I32LtS => self.i32_op(|a, b| (a < b) as i32),
+                I32GtS => self.i32_op(|a, b| (a > b) as i32),
+                I32LeS => self.i32_op(|a, b| (a <= b) as i32),
+                I32GeS => self.i32_op(|a, b| (a >= b) as i32),
+                I32LtU => self.i32_op(|a, b| ((a as u32) < b as u32) as i32),
+                I32GtU => self.i32_op(|a, b| (a as u32 > b as u32) as i32),
+                I32Eqz => {
+                    let a = self.pop_i32();
+                    self.stack.push(Value::I32((a == 0) as i32));
+                }
+                I32And => self.i32_op(|a, b| a & b),
+                I32Or => self.i32_op(|a, b| a | b),
+                I32Xor => self.i32_op(|a, b| a ^ b),
+                I32Shl => self.i32_op(|a, b| a.wrapping_shl(b as u32)),
+                I32ShrS => self.i32_op(|a, b| a.wrapping_shr(b as u32)),
+                I32ShrU => self.i32_op(|a, b| (a as u32).wrapping_shr(b as u32) as i32),
+                I32Clz => {
+                    let a = self.pop_i32();
+                    self.stack.push(Value::I32(a.leading_zeros() as i32));
+                }
+                F64ConvertI32S => {
+                    let a = self.pop_i32();
+                    self.stack.push(Value::F64(a as f64));
+                }
+                F64ConvertI32U => {
+                    let a = self.pop_i32();
+                    self.stack.push(Value::F64(a as u32 as f64));
+                }
+                // Rust's float-to-int casts saturate and turn NaN into 0, like `trunc_sat`
+                I32TruncSatF64S => {
+                    let x = self.pop_f64();
+                    self.stack.push(Value::I32(x as i32));
+                }
+                I32TruncSatF64U => {
+                    let x = self.pop_f64();
+                    self.stack.push(Value::I32(x as u32 as i32));
+                }
+                I32Load(offset) => {
+                    let bytes = self.load(*offset);
+                    self.stack.push(Value::I32(i32::from_le_bytes(bytes)));
+                }
+                I32Load8U(offset) => {
+                    let [byte] = self.load(*offset);
+                    self.stack.push(Value::I32(byte as i32));
+                }
+                I64Load(offset) => {
+                    let bytes = self.load(*offset);
+                    self.stack.push(Value::I64(i64::from_le_bytes(bytes)));
+                }
+                I32Store(offset) => {
+                    let value = self.pop_i32();
+                    self.store(*offset, &value.to_le_bytes());
+                }
+                I32Store8(offset) => {
+                    let value = self.pop_i32();
+                    self.store(*offset, &[value as u8]);
+                }
+                MemorySize => self.stack.push(Value::I32((self.memory.len() / 65536) as i32)),
+                MemoryGrow => {
+                    let pages = self.pop_i32();
+                    let old = (self.memory.len() / 65536) as i32;
+                    self.memory.resize(self.memory.len() + pages as usize * 65536, 0);
+                    self.stack.push(Value::I32(old));
+                }
+                MemoryCopy => {
+                    let length = self.pop_i32() as usize;
+                    let source = self.pop_i32() as usize;
+                    let destination = self.pop_i32() as usize;
+                    self.memory.copy_within(source..source + length, destination);
+                }
+                MemoryFill => {
+                    let length = self.pop_i32() as usize;
+                    let byte = self.pop_i32() as u8;
+                    let destination = self.pop_i32() as usize;
+                    self.memory[destination..destination + length].fill(byte);
+                }
+
+                I64Eq => self.i64_test(|a, b| a == b),
+                I64Ne => self.i64_test(|a, b| a != b),
+                I64Eqz => {
+                    let a = self.pop_i64();
+                    self.stack.push(Value::I32((a == 0) as i32));
+                }
+                I64And => self.i64_op(|a, b| a & b),
+                I64Or => self.i64_op(|a, b| a | b),
+                I64ShrU => self.i64_op(|a, b| (a as u64).wrapping_shr(b as u32) as i64),
+                I64Add => self.i64_op(i64::wrapping_add),
+                I64Sub => self.i64_op(i64::wrapping_sub),
+                I64Mul => self.i64_op(i64::wrapping_mul),
+                I64DivU => self.i64_op(|a, b| (a as u64).checked_div(b as u64).expect("integer divide by zero") as i64),
+                I64RemU => self.i64_op(|a, b| (a as u64).checked_rem(b as u64).expect("integer divide by zero") as i64),
+                I64ExtendI32U => {
+                    let a = self.pop_i32();
+                    self.stack.push(Value::I64(a as u32 as i64));
+                }
+                I32WrapI64 => {
+                    let a = self.pop_i64();
+                    self.stack.push(Value::I32(a as i32));
+                }
+                I64TruncSatF64S => {
+                    let x = self.pop_f64();
+                    self.stack.push(Value::I64(x as i64));
+                }
+                I64TruncSatF64U => {
+                    let x = self.pop_f64();
+                    self.stack.push(Value::I64(x as u64 as i64));
+                }
+                F64ConvertI64S => {
+                    let a = self.pop_i64();
+                    self.stack.push(Value::F64(a as f64));
+                }
+                F64ConvertI64U => {
+                    let a = self.pop_i64();
+                    self.stack.push(Value::F64(a as u64 as f64));
+                }
+                I64ReinterpretF64 => {
+                    let x = self.pop_f64();
+                    self.stack.push(Value::I64(x.to_bits() as i64));
+                }
+                F64ReinterpretI64 => {
+                    let a = self.pop_i64();
+                    self.stack.push(Value::F64(f64::from_bits(a as u64)));
+                }
+                F64Add => self.f64_op(|a, b| a + b),
+                F64Sub => self.f64_op(|a, b| a - b),
+                F64Mul => self.f64_op(|a, b| a * b),
+                F64Div => self.f64_op(|a, b| a / b),
+                // Unlike Rust's, wasm's `min` and `max` propagate NaN and order -0 before 0
+                F64Min => self.f64_op(|a, b| match (a, b) {
+                    _ if a.is_nan() || b.is_nan() => f64::NAN,
+                    _ if a == b => if a.is_sign_negative() { a } else { b },
+                    _ => a.min(b),
+                }),
+                F64Max => self.f64_op(|a, b| match (a, b) {
+                    _ if a.is_nan() || b.is_nan() => f64::NAN,
+                    _ if a == b => if a.is_sign_negative() { b } else { a },
+                    _ => a.max(b),
+                }),
+                F64Copysign => self.f64_op(f64::copysign),
+                F64Eq => self.f64_test(|a, b| a == b),
+                F64Ne => self.f64_test(|a, b| a != b),
+                F64Lt => self.f64_test(|a, b| a < b),
+                F64Gt => self.f64_test(|a, b| a > b),
+                F64Le => self.f64_test(|a, b| a <= b),
+                F64Ge => self.f64_test(|a, b| a >= b),
+                F64Neg => self.f64_unary(|x| -x),
+                F64Abs => self.f64_unary(f64::abs),
+                F64Sqrt => self.f64_unary(f64::sqrt),
+                F64Floor => self.f64_unary(f64::floor),
+                F64Ceil => self.f64_unary(f64::ceil),
+                F64Trunc => self.f64_unary(f64::trunc),
+                F64Nearest => self.f64_unary(f64::round_ties_even),
+
+                Call(name) => {
+                    let params = self.function(name).params.len();
+                    let args = self.stack.split_off(self.stack.len() - params);
+                    let result = self.call(name, args);
+                    self.stack.push(result);
+                }
+                Drop => {
+                    self.pop();
+                }
+                Select => {
+                    let condition = self.pop_i32();
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(if condition != 0 { a } else { b });
+                }
+                Return => return Flow::Return,
+                Br(label) => return Flow::Branch(label.clone()),
+                BrIf(label) => {
+                    if self.pop_i32() != 0 {
+                        return Flow::Branch(label.clone());
+                    }
+                }
+                BrTable { labels, default } => {
+                    let index = self.pop_i32() as u32 as usize;
+                    return Flow::Branch(labels.get(index).unwrap_or(default).clone());
+                }
+
+                // Blocks and loops have no results, so a branch out of one leaves the stack as
+                // it was on entry
+                Block { label, body } => {
+                    let height = self.stack.len();
+                    match self.execute(body, locals) {
+                        Flow::Branch(target) if target == *label => self.stack.truncate(height),
+                        Flow::Next => {}
+                        flow => return flow,
+                    }
+                }
+                Loop { label, body } => {
+                    let height = self.stack.len();
+                    loop {
+                        match self.execute(body, locals) {
+                            Flow::Branch(target) if target == *label => self.stack.truncate(height),
+                            Flow::Next => break,
+                            flow => return flow,
+                        }
+                    }
+                }
+                If { then_body, else_body, .. } => {
+                    let body = if self.pop_i32() != 0 { Some(then_body) } else { else_body.as_ref() };
+                    if let Some(body) = body {
+                        match self.execute(body, locals) {
+                            Flow::Next => {}
+                            flow => return flow,
+                        }
+                    }
+                }
+            }
+        }
+        Flow::Next
+    }
+}